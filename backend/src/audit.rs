@@ -0,0 +1,186 @@
+// Audit trail for security-sensitive actions. `record_in` writes the event in
+// the action's own transaction, so the two commit or roll back together.
+// `record` writes it on its own once the action is done: if that write fails
+// the request fails, but the action stands and only the error log has it.
+// The table is append-only (triggers reject updates and deletes); events can
+// also be mirrored, best effort, to a JSON-lines file for SIEM ingestion with
+// AUDIT_LOG_FILE.
+use crate::auth::ClientInfo;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::AppState;
+use serde_json::{json, Value};
+use sqlx::SqliteConnection;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub const LOGIN: &str = "auth.login";
+pub const LOGIN_FAILED: &str = "auth.login_failed";
+pub const LOGOUT: &str = "auth.logout";
+pub const REGISTERED: &str = "auth.registered";
+pub const PASSWORD_CHANGED: &str = "auth.password_changed";
+pub const PASSWORD_RESET: &str = "auth.password_reset";
+pub const SESSION_REVOKED: &str = "auth.session_revoked";
+pub const MFA_ENABLED: &str = "auth.mfa_enabled";
+pub const MFA_DISABLED: &str = "auth.mfa_disabled";
+pub const RECOVERY_CODES_REGENERATED: &str = "auth.recovery_codes_regenerated";
+pub const IDENTITY_UNLINKED: &str = "auth.identity_unlinked";
+pub const ACCOUNT_EXPORTED: &str = "account.exported";
+pub const ACCOUNT_DELETED: &str = "account.deleted";
+pub const ACCOUNT_DELETION_DENIED: &str = "account.deletion_denied";
+
+pub const API_KEY_ADDED: &str = "api_key.added";
+pub const API_KEY_DELETED: &str = "api_key.deleted";
+pub const API_KEY_REVEALED: &str = "api_key.revealed";
+pub const API_KEY_REVEAL_DENIED: &str = "api_key.reveal_denied";
+pub const API_KEY_PINNED: &str = "api_key.pinned";
+pub const ACCESS_TOKEN_CREATED: &str = "access_token.created";
+pub const ACCESS_TOKEN_REVOKED: &str = "access_token.revoked";
+pub const SETTINGS_UPDATED: &str = "settings.updated";
+
+pub const CHAT_SHARED: &str = "chat.shared";
+pub const CHAT_SHARE_UPDATED: &str = "chat.share_updated";
+pub const CHAT_UNSHARED: &str = "chat.unshared";
+
+pub const WORKSPACE_MEMBER_ADDED: &str = "workspace.member_added";
+pub const WORKSPACE_MEMBER_UPDATED: &str = "workspace.member_updated";
+pub const WORKSPACE_MEMBER_REMOVED: &str = "workspace.member_removed";
+pub const WORKSPACE_KEY_ADDED: &str = "workspace.key_added";
+pub const WORKSPACE_KEY_DELETED: &str = "workspace.key_deleted";
+
+pub const ADMIN_USER_CREATED: &str = "admin.user_created";
+pub const ADMIN_USER_UPDATED: &str = "admin.user_updated";
+pub const ADMIN_USER_DELETED: &str = "admin.user_deleted";
+pub const ADMIN_PASSWORD_RESET: &str = "admin.password_reset";
+pub const ADMIN_MFA_RESET: &str = "admin.mfa_reset";
+pub const ADMIN_INVITE_CREATED: &str = "admin.invite_created";
+pub const ADMIN_INVITE_DELETED: &str = "admin.invite_deleted";
+pub const ADMIN_SERVER_KEY_MODELS_SET: &str = "admin.server_key_models_set";
+
+// Appends every recorded event as one JSON object per line.
+pub struct FileSink {
+    path: PathBuf,
+    // Keeps concurrent events from interleaving within a line.
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    async fn append(&self, line: String) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{}\n", line).as_bytes()).await
+    }
+}
+
+pub fn sink_from_config(config: &Config) -> Option<Arc<FileSink>> {
+    let path = config.audit_log_file.as_ref()?;
+    tracing::info!("mirroring audit events to {}", path);
+    Some(Arc::new(FileSink {
+        path: PathBuf::from(path),
+        lock: Mutex::new(()),
+    }))
+}
+
+pub struct AuditEvent<'a> {
+    action: &'a str,
+    user_id: Option<&'a str>,
+    target: Option<&'a str>,
+    client: Option<&'a ClientInfo>,
+    metadata: Option<Value>,
+}
+
+impl<'a> AuditEvent<'a> {
+    pub fn new(action: &'a str) -> Self {
+        Self {
+            action,
+            user_id: None,
+            target: None,
+            client: None,
+            metadata: None,
+        }
+    }
+
+    pub fn user(mut self, user_id: &'a str) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn target(mut self, target: &'a str) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn client(mut self, client: &'a ClientInfo) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: Value) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub async fn record(self, app_state: &AppState) -> Result<(), AppError> {
+        let mut conn = app_state.db_pool.acquire().await?;
+        let action = self.action;
+        self.record_in(app_state, &mut conn).await.map_err(|e| {
+            tracing::error!("failed to record audit event {}: {:?}", action, e);
+            e
+        })
+    }
+
+    // Writes the event on `conn`; pass the action's transaction to commit both
+    // together.
+    pub async fn record_in(
+        self,
+        app_state: &AppState,
+        conn: &mut SqliteConnection,
+    ) -> Result<(), AppError> {
+        let id = Uuid::new_v4().to_string();
+        let ip = self.client.and_then(|c| c.ip.as_deref());
+        let user_agent = self.client.and_then(|c| c.user_agent.as_deref());
+
+        let created_at: String = sqlx::query_scalar(
+            r#"
+            INSERT INTO audit_events (id, user_id, action, target, ip_address, user_agent, metadata)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING created_at
+            "#,
+        )
+        .bind(&id)
+        .bind(self.user_id)
+        .bind(self.action)
+        .bind(self.target)
+        .bind(ip)
+        .bind(user_agent)
+        .bind(self.metadata.as_ref().map(|m| m.to_string()))
+        .fetch_one(conn)
+        .await?;
+
+        // The database stays the record of truth, so a failing file sink is
+        // only logged.
+        if let Some(sink) = &app_state.audit_sink {
+            let line = json!({
+                "id": id,
+                "created_at": created_at,
+                "actor": self.user_id,
+                "action": self.action,
+                "target": self.target,
+                "ip": ip,
+                "user_agent": user_agent,
+                "metadata": self.metadata,
+            });
+            if let Err(e) = sink.append(line.to_string()).await {
+                tracing::warn!("failed to write audit event to file: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
+use crate::database::{format_db_timestamp, parse_db_timestamp, ApiToken};
 use crate::error::AppError;
 use crate::AppState;
 use async_trait::async_trait;
 use axum::{
-    extract::{FromRef, FromRequestParts},
-    http::request::Parts,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header, request::Parts, Method},
 };
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
@@ -11,23 +12,110 @@ use axum_extra::{
 };
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+pub const PERSONAL_ACCESS_TOKEN_PREFIX: &str = "neko_pat_";
+pub const REFRESH_TOKEN_PREFIX: &str = "neko_rt_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
     pub iat: usize,
+    // Session the access token belongs to; checked against `sessions` on every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    // Only set for personal access tokens; JWTs carry full access.
+    #[serde(skip)]
+    pub scopes: Option<Vec<TokenScope>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenScope {
+    ChatsRead,
+    ChatsWrite,
+    Generate,
+    KeysManage,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 4] = [
+        TokenScope::ChatsRead,
+        TokenScope::ChatsWrite,
+        TokenScope::Generate,
+        TokenScope::KeysManage,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::ChatsRead => "chats:read",
+            TokenScope::ChatsWrite => "chats:write",
+            TokenScope::Generate => "generate",
+            TokenScope::KeysManage => "keys:manage",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_str() == value)
+    }
+
+    // Maps a request onto the scope a personal access token needs for it.
+    // Routes that return None (token management, settings, ...) are JWT-only.
+    pub(crate) fn required_for(method: &Method, path: &str) -> Option<Self> {
+        // Revealing a stored key needs the account password, so it is JWT-only.
+        if path.starts_with("/api/keys/") && path.ends_with("/reveal") {
+            return None;
+        }
+
+        if path.starts_with("/api/keys")
+            || path == "/api/settings/api-keys"
+            || path == "/api/settings/models/fetch"
+        {
+            return Some(TokenScope::KeysManage);
+        }
+
+        if path.starts_with("/v1/") {
+            return Some(TokenScope::Generate);
+        }
+
+        if let Some(rest) = path.strip_prefix("/api/chats") {
+            let generates = rest.ends_with("/stream")
+                || rest.ends_with("/regenerate")
+                || rest.ends_with("/parallel")
+                || (method == Method::POST && rest.ends_with("/messages"));
+            return Some(if generates {
+                TokenScope::Generate
+            } else if method == Method::GET {
+                TokenScope::ChatsRead
+            } else {
+                TokenScope::ChatsWrite
+            });
+        }
+
+        None
+    }
 }
 
-pub fn create_jwt(user_id: String, secret: &str) -> Result<String, AppError> {
+pub fn create_jwt(
+    user_id: String,
+    session_id: String,
+    secret: &str,
+    ttl_minutes: i64,
+) -> Result<String, AppError> {
     let iat = Utc::now();
-    let exp = iat + Duration::days(7);
+    let exp = iat + Duration::minutes(ttl_minutes);
 
     let claims = Claims {
         sub: user_id,
         exp: exp.timestamp() as usize,
         iat: iat.timestamp() as usize,
+        sid: Some(session_id),
+        scopes: None,
     };
 
     let header = Header::default();
@@ -35,6 +123,384 @@ pub fn create_jwt(user_id: String, secret: &str) -> Result<String, AppError> {
     encode(&header, &claims, &key).map_err(AppError::from)
 }
 
+// Generates a random opaque token with the given prefix, e.g. "neko_pat_...".
+pub fn generate_token(prefix: &str) -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    format!("{}{}", prefix, random)
+}
+
+// Opaque tokens are only ever stored as a SHA-256 hex digest.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Request metadata recorded on sessions.
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+
+        let forwarded_ip = if app_state.config.trust_proxy_headers {
+            parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|ip| ip.trim().to_string())
+        } else {
+            None
+        };
+
+        let ip = forwarded_ip.or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        });
+
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(512).collect());
+
+        Ok(ClientInfo { ip, user_agent })
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionTokens {
+    pub token: String,
+    pub refresh_token: String,
+}
+
+// Starts a new server-side session and returns its first access/refresh token pair.
+pub async fn create_session(
+    app_state: &AppState,
+    user_id: &str,
+    client: &ClientInfo,
+    device_name: Option<String>,
+) -> Result<SessionTokens, AppError> {
+    let session_id = Uuid::new_v4().to_string();
+    let refresh_token = generate_token(REFRESH_TOKEN_PREFIX);
+    let expires_at = Utc::now() + Duration::days(app_state.config.refresh_token_ttl_days);
+
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, refresh_token_hash, device_name, user_agent, ip_address, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(&session_id)
+    .bind(user_id)
+    .bind(hash_token(&refresh_token))
+    .bind(device_name)
+    .bind(&client.user_agent)
+    .bind(&client.ip)
+    .bind(format_db_timestamp(expires_at))
+    .execute(&app_state.db_pool)
+    .await?;
+
+    let token = create_jwt(
+        user_id.to_string(),
+        session_id,
+        &app_state.config.jwt_secret,
+        app_state.config.access_token_ttl_minutes,
+    )?;
+
+    Ok(SessionTokens {
+        token,
+        refresh_token,
+    })
+}
+
+// Revokes every open session of a user, e.g. after a password change.
+// `keep_session_id` lets the session that made the change stay logged in.
+pub async fn revoke_all_sessions(
+    pool: &sqlx::SqlitePool,
+    user_id: &str,
+    keep_session_id: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE sessions SET revoked_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+        WHERE user_id = $1 AND revoked_at IS NULL AND id IS NOT $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(keep_session_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Personal access tokens outlive sessions, so a password change has to drop
+// them too or a leaked token keeps working.
+pub async fn revoke_api_tokens(pool: &sqlx::SqlitePool, user_id: &str) -> Result<(), AppError> {
+    sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    PasswordReset,
+}
+
+impl EmailTokenPurpose {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmailTokenClaims {
+    sub: String,
+    exp: usize,
+    purpose: EmailTokenPurpose,
+    jti: String,
+}
+
+// Issues a signed token for links sent by email. The jti is recorded so the
+// token can only be redeemed once; issuing a new one invalidates older ones.
+pub async fn issue_email_token(
+    app_state: &AppState,
+    user_id: &str,
+    purpose: EmailTokenPurpose,
+    ttl: Duration,
+) -> Result<String, AppError> {
+    let jti = Uuid::new_v4().to_string();
+    let expires_at = Utc::now() + ttl;
+
+    sqlx::query(
+        r#"
+        UPDATE email_tokens SET used_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+        WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .execute(&app_state.db_pool)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO email_tokens (jti, user_id, purpose, expires_at) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(&jti)
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(format_db_timestamp(expires_at))
+    .execute(&app_state.db_pool)
+    .await?;
+
+    let claims = EmailTokenClaims {
+        sub: user_id.to_string(),
+        exp: expires_at.timestamp() as usize,
+        purpose,
+        jti,
+    };
+    let key = EncodingKey::from_secret(app_state.config.jwt_secret.as_ref());
+    encode(&Header::default(), &claims, &key).map_err(AppError::from)
+}
+
+// Verifies and redeems an email token, returning the user it was issued for.
+pub async fn consume_email_token(
+    app_state: &AppState,
+    token: &str,
+    purpose: EmailTokenPurpose,
+) -> Result<String, AppError> {
+    let invalid = || AppError::BadRequest("invalid or expired token".to_string());
+
+    let claims = decode::<EmailTokenClaims>(
+        token,
+        &DecodingKey::from_secret(app_state.config.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| invalid())?
+    .claims;
+
+    if claims.purpose != purpose {
+        return Err(invalid());
+    }
+
+    let result = sqlx::query(
+        r#"
+        UPDATE email_tokens SET used_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+        WHERE jti = $1 AND user_id = $2 AND purpose = $3 AND used_at IS NULL
+          AND expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now')
+        "#,
+    )
+    .bind(&claims.jti)
+    .bind(&claims.sub)
+    .bind(purpose.as_str())
+    .execute(&app_state.db_pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(invalid());
+    }
+
+    Ok(claims.sub)
+}
+
+const MFA_TOKEN_TTL_MINUTES: i64 = 5;
+
+// Issued by login when the account has 2FA enabled. It only proves the first
+// factor and can't be used as an access token (no `iat`/`sid`).
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaPendingClaims {
+    sub: String,
+    exp: usize,
+    mfa_pending: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_name: Option<String>,
+}
+
+pub fn create_mfa_token(
+    user_id: &str,
+    device_name: Option<String>,
+    secret: &str,
+) -> Result<String, AppError> {
+    let claims = MfaPendingClaims {
+        sub: user_id.to_string(),
+        exp: (Utc::now() + Duration::minutes(MFA_TOKEN_TTL_MINUTES)).timestamp() as usize,
+        mfa_pending: true,
+        device_name,
+    };
+    let key = EncodingKey::from_secret(secret.as_ref());
+    encode(&Header::default(), &claims, &key).map_err(AppError::from)
+}
+
+// Returns the user id and device name carried by a pending MFA token.
+pub fn decode_mfa_token(token: &str, secret: &str) -> Result<(String, Option<String>), AppError> {
+    let claims = decode::<MfaPendingClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::BadRequest("invalid or expired mfa token".to_string()))?
+    .claims;
+
+    if !claims.mfa_pending {
+        return Err(AppError::BadRequest(
+            "invalid or expired mfa token".to_string(),
+        ));
+    }
+
+    Ok((claims.sub, claims.device_name))
+}
+
+// Verifies an access token's signature and that its session is still active.
+pub async fn decode_access_token(app_state: &AppState, token: &str) -> Result<Claims, AppError> {
+    let claims = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(app_state.config.jwt_secret.as_ref()),
+        &Validation::default(),
+    )
+    .map_err(|_| AppError::Unauthorized)?
+    .claims;
+
+    let session_id = claims.sid.as_deref().ok_or(AppError::Unauthorized)?;
+    if !session_is_active(&app_state.db_pool, session_id, &claims.sub).await? {
+        return Err(AppError::Unauthorized);
+    }
+
+    Ok(claims)
+}
+
+// A session stays usable until it is revoked or expires, or its user is
+// disabled or deleted.
+pub async fn session_is_active(
+    pool: &sqlx::SqlitePool,
+    session_id: &str,
+    user_id: &str,
+) -> Result<bool, AppError> {
+    let active = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM sessions
+            JOIN users ON users.id = sessions.user_id
+            WHERE sessions.id = $1 AND sessions.user_id = $2 AND sessions.revoked_at IS NULL
+              AND sessions.expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now')
+              AND users.disabled = false
+        )
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(active)
+}
+
+async fn authenticate_personal_access_token(
+    app_state: &AppState,
+    token: &str,
+) -> Result<Claims, AppError> {
+    let record = sqlx::query_as::<_, ApiToken>(
+        r#"
+        SELECT api_tokens.* FROM api_tokens
+        JOIN users ON users.id = api_tokens.user_id
+        WHERE api_tokens.token_hash = $1 AND users.disabled = false
+        "#,
+    )
+    .bind(hash_token(token))
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::Unauthorized)?;
+
+    let expires_at = record.expires_at.as_deref().and_then(parse_db_timestamp);
+    if matches!(expires_at, Some(exp) if exp <= Utc::now()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    sqlx::query(
+        "UPDATE api_tokens SET last_used_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = $1",
+    )
+    .bind(&record.id)
+    .execute(&app_state.db_pool)
+    .await?;
+
+    Ok(Claims {
+        sub: record.user_id,
+        exp: expires_at.map(|e| e.timestamp() as usize).unwrap_or(0),
+        iat: Utc::now().timestamp() as usize,
+        sid: None,
+        scopes: Some(
+            record
+                .scopes
+                .split(',')
+                .filter_map(TokenScope::parse)
+                .collect(),
+        ),
+    })
+}
+
 #[async_trait]
 impl<S> FromRequestParts<S> for Claims
 where
@@ -44,19 +510,88 @@ where
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Already resolved for this request (by the rate limiter).
+        if let Some(claims) = parts.extensions.get::<Claims>() {
+            return Ok(claims.clone());
+        }
+
         let app_state = AppState::from_ref(state);
         let TypedHeader(Authorization(bearer)) =
             TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
                 .await
                 .map_err(|_| AppError::Unauthorized)?;
 
-        let token_data = decode::<Claims>(
-            bearer.token(),
-            &DecodingKey::from_secret(app_state.config.jwt_secret.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|_| AppError::Unauthorized)?;
+        if bearer.token().starts_with(PERSONAL_ACCESS_TOKEN_PREFIX) {
+            let claims = authenticate_personal_access_token(&app_state, bearer.token()).await?;
+            let path = parts.uri.path();
+            if path != "/api/auth/me" {
+                let required = TokenScope::required_for(&parts.method, path).ok_or_else(|| {
+                    AppError::Forbidden(
+                        "this endpoint cannot be used with a personal access token".to_string(),
+                    )
+                })?;
+                let granted = claims.scopes.as_deref().unwrap_or_default();
+                if !granted.contains(&required) {
+                    return Err(AppError::Forbidden(format!(
+                        "token is missing the '{}' scope",
+                        required.as_str()
+                    )));
+                }
+            }
+            return Ok(claims);
+        }
+
+        decode_access_token(&app_state, bearer.token()).await
+    }
+}
+
+// Like `Claims`, but additionally requires the caller to be an admin.
+pub struct AdminClaims(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AdminClaims
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        let app_state = AppState::from_ref(state);
+
+        let role = sqlx::query_scalar::<_, String>("SELECT role FROM users WHERE id = $1")
+            .bind(&claims.sub)
+            .fetch_optional(&app_state.db_pool)
+            .await?;
+
+        if role.as_deref() != Some("admin") {
+            return Err(AppError::Forbidden("admin access required".to_string()));
+        }
+
+        Ok(AdminClaims(claims))
+    }
+}
+
+// Like `Claims`, but refuses personal access tokens whatever their scopes, for
+// account-security endpoints that only a signed-in session may use.
+pub struct SessionClaims(pub Claims);
 
-        Ok(token_data.claims)
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionClaims
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        if claims.sid.is_none() {
+            return Err(AppError::Forbidden(
+                "this endpoint cannot be used with a personal access token".to_string(),
+            ));
+        }
+        Ok(SessionClaims(claims))
     }
 }
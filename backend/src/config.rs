@@ -1,14 +1,69 @@
-use std::env;
+use crate::llm::routing::RetryPolicy;
+use std::{collections::HashMap, env, time::Duration};
+
+const GOOGLE_ISSUER: &str = "https://accounts.google.com";
+
+// Providers that can be given a server-wide key through <PROVIDER>_API_KEY.
+pub const SERVER_KEY_PROVIDERS: [&str; 5] = ["openai", "anthropic", "openrouter", "xai", "gemini"];
+
+// An OpenID Connect identity provider users can sign in with.
+#[derive(Clone, Debug)]
+pub struct OidcProvider {
+    pub id: String,
+    pub display_name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+    // Claim (dotted path, e.g. "realm_access.roles") holding the user's groups.
+    pub role_claim: Option<String>,
+    // Members of any of these groups get the admin role, everyone else "user".
+    pub admin_groups: Vec<String>,
+    // Accept the email claim even without email_verified (e.g. Azure AD).
+    pub trust_email: bool,
+}
 
 #[derive(Clone)]
 pub struct Config {
     pub database_url: String,
     pub jwt_secret: String,
-    pub encryption_key: String,
-    pub google_client_id: Option<String>,
-    pub google_client_secret: Option<String>,
-    pub google_redirect_uri: String,
+    // Master keys for secrets at rest as (version, secret); the first encrypts.
+    pub encryption_keys: Vec<(u32, String)>,
+    pub oidc_providers: Vec<OidcProvider>,
+    // Last-resort provider keys used when neither the user nor any of their
+    // workspaces has a key for the provider.
+    pub server_api_keys: HashMap<String, String>,
+    // Mirrors audit events to this file as JSON lines when set.
+    pub audit_log_file: Option<String>,
     pub disable_admin_account: bool,
+    pub access_token_ttl_minutes: i64,
+    pub refresh_token_ttl_days: i64,
+    pub trust_proxy_headers: bool,
+    pub app_base_url: String,
+    pub mail_backend: String,
+    pub mail_from: String,
+    pub mail_dir: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub smtp_tls: String,
+    pub allow_signup: bool,
+    pub signup_allowed_domains: Vec<String>,
+    pub require_invite_code: bool,
+    pub rate_limit_enabled: bool,
+    pub rate_limit_auth_per_minute: u32,
+    pub rate_limit_login_per_account_per_minute: u32,
+    pub rate_limit_generation_per_minute: u32,
+    pub rate_limit_generation_per_ip_per_minute: u32,
+    pub login_lockout_threshold: i64,
+    pub login_lockout_base_seconds: i64,
+    pub login_lockout_max_seconds: i64,
+    // Retries for provider calls that fail before the first token.
+    pub llm_retry: RetryPolicy,
+    // Offers the scripted "mock" provider (see llm::mock) to every user.
+    pub enable_mock_provider: bool,
 }
 
 impl Config {
@@ -24,18 +79,190 @@ impl Config {
             }
         }
 
+        let app_base_url = env::var("APP_BASE_URL")
+            .map(|v| v.trim_end_matches('/').to_string())
+            .unwrap_or_else(|_| "http://localhost:5173".to_string());
+
         Self {
+            oidc_providers: oidc_providers_from_env(&app_base_url),
+            server_api_keys: server_api_keys_from_env(),
+            audit_log_file: env::var("AUDIT_LOG_FILE").ok().filter(|v| !v.is_empty()),
             database_url,
             jwt_secret: env::var("JWT_SECRET").expect("JWT_SECRET must be set"),
-            encryption_key: env::var("ENCRYPTION_KEY")
-                .expect("ENCRYPTION_KEY must be set (32 chars)"),
-            google_client_id: env::var("GOOGLE_CLIENT_ID").ok(),
-            google_client_secret: env::var("GOOGLE_CLIENT_SECRET").ok(),
-            google_redirect_uri: env::var("GOOGLE_REDIRECT_URI")
-                .unwrap_or_else(|_| "http://localhost:5173/auth/callback".to_string()),
+            encryption_keys: match env::var("ENCRYPTION_KEYS") {
+                Ok(keys) => crate::crypto::parse_key_list(&keys)
+                    .unwrap_or_else(|e| panic!("invalid ENCRYPTION_KEYS: {}", e)),
+                Err(_) => vec![(
+                    1,
+                    env::var("ENCRYPTION_KEY")
+                        .expect("ENCRYPTION_KEY or ENCRYPTION_KEYS must be set"),
+                )],
+            },
             disable_admin_account: env::var("DISABLE_ADMIN_ACCOUNT")
                 .map(|v| v.to_lowercase() == "true" || v == "1")
                 .unwrap_or(false),
+            access_token_ttl_minutes: env::var("ACCESS_TOKEN_TTL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(15),
+            refresh_token_ttl_days: env::var("REFRESH_TOKEN_TTL_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            trust_proxy_headers: env::var("TRUST_PROXY_HEADERS")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(false),
+            app_base_url,
+            mail_backend: env::var("MAIL_BACKEND")
+                .map(|v| v.to_lowercase())
+                .unwrap_or_else(|_| "log".to_string()),
+            mail_from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "neko.chat <noreply@localhost>".to_string()),
+            mail_dir: env::var("MAIL_DIR").unwrap_or_else(|_| "mail".to_string()),
+            smtp_host: env::var("SMTP_HOST").ok(),
+            smtp_port: env::var("SMTP_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(587),
+            smtp_username: env::var("SMTP_USERNAME").ok(),
+            smtp_password: env::var("SMTP_PASSWORD").ok(),
+            smtp_tls: env::var("SMTP_TLS")
+                .map(|v| v.to_lowercase())
+                .unwrap_or_else(|_| "starttls".to_string()),
+            allow_signup: env::var("ALLOW_SIGNUP")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(true),
+            signup_allowed_domains: env::var("SIGNUP_ALLOWED_DOMAINS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|d| d.trim().trim_start_matches('@').to_lowercase())
+                        .filter(|d| !d.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            require_invite_code: env::var("REQUIRE_INVITE_CODE")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(false),
+            rate_limit_enabled: env::var("RATE_LIMIT_ENABLED")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(true),
+            rate_limit_auth_per_minute: env_number("RATE_LIMIT_AUTH_PER_MINUTE", 20),
+            rate_limit_login_per_account_per_minute: env_number(
+                "RATE_LIMIT_LOGIN_PER_ACCOUNT_PER_MINUTE",
+                10,
+            ),
+            rate_limit_generation_per_minute: env_number("RATE_LIMIT_GENERATION_PER_MINUTE", 30),
+            rate_limit_generation_per_ip_per_minute: env_number(
+                "RATE_LIMIT_GENERATION_PER_IP_PER_MINUTE",
+                60,
+            ),
+            login_lockout_threshold: env_number("LOGIN_LOCKOUT_THRESHOLD", 5),
+            login_lockout_base_seconds: env_number("LOGIN_LOCKOUT_BASE_SECONDS", 30),
+            login_lockout_max_seconds: env_number("LOGIN_LOCKOUT_MAX_SECONDS", 3600),
+            llm_retry: RetryPolicy {
+                max_attempts: env_number("LLM_RETRY_MAX_ATTEMPTS", 3u32).max(1),
+                base_delay: Duration::from_millis(env_number("LLM_RETRY_BASE_DELAY_MS", 500)),
+                max_delay: Duration::from_millis(env_number("LLM_RETRY_MAX_DELAY_MS", 10_000)),
+            },
+            enable_mock_provider: env::var("ENABLE_MOCK_PROVIDER")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(false),
+        }
+    }
+}
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .map(|v| {
+            v.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn server_api_keys_from_env() -> HashMap<String, String> {
+    SERVER_KEY_PROVIDERS
+        .iter()
+        .filter_map(|provider| {
+            let key = env::var(format!("{}_API_KEY", provider.to_uppercase())).ok()?;
+            let key = key.trim();
+            (!key.is_empty()).then(|| (provider.to_string(), key.to_string()))
+        })
+        .collect()
+}
+
+// Providers listed in OIDC_PROVIDERS are read from OIDC_<ID>_* variables.
+// GOOGLE_CLIENT_ID/GOOGLE_CLIENT_SECRET still work and add a Google preset.
+fn oidc_providers_from_env(app_base_url: &str) -> Vec<OidcProvider> {
+    let default_redirect_uri = format!("{}/auth/callback", app_base_url);
+    let mut providers = Vec::new();
+
+    for id in env_list("OIDC_PROVIDERS") {
+        let id = id.to_lowercase();
+        if !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            tracing::warn!("ignoring OIDC provider with invalid id {:?}", id);
+            continue;
         }
+
+        let prefix = format!("OIDC_{}_", id.to_uppercase().replace('-', "_"));
+        let var = |name: &str| env::var(format!("{}{}", prefix, name)).ok();
+
+        let issuer = var("ISSUER").or_else(|| (id == "google").then(|| GOOGLE_ISSUER.to_string()));
+        let (Some(issuer), Some(client_id)) = (issuer, var("CLIENT_ID")) else {
+            tracing::warn!(
+                "ignoring OIDC provider {}: {}ISSUER and {}CLIENT_ID must be set",
+                id,
+                prefix,
+                prefix
+            );
+            continue;
+        };
+
+        providers.push(OidcProvider {
+            display_name: var("NAME").unwrap_or_else(|| id.clone()),
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: var("CLIENT_SECRET"),
+            redirect_uri: var("REDIRECT_URI").unwrap_or_else(|| default_redirect_uri.clone()),
+            scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+            role_claim: var("ROLE_CLAIM").filter(|v| !v.is_empty()),
+            admin_groups: env_list(&format!("{}ADMIN_GROUPS", prefix)),
+            trust_email: var("TRUST_EMAIL")
+                .map(|v| v.to_lowercase() == "true" || v == "1")
+                .unwrap_or(false),
+            id,
+        });
+    }
+
+    if let (Ok(client_id), false) = (
+        env::var("GOOGLE_CLIENT_ID"),
+        providers.iter().any(|p| p.id == "google"),
+    ) {
+        providers.push(OidcProvider {
+            id: "google".to_string(),
+            display_name: "Google".to_string(),
+            issuer: GOOGLE_ISSUER.to_string(),
+            client_id,
+            client_secret: env::var("GOOGLE_CLIENT_SECRET").ok(),
+            redirect_uri: env::var("GOOGLE_REDIRECT_URI").unwrap_or(default_redirect_uri),
+            scopes: "openid email profile".to_string(),
+            role_claim: None,
+            admin_groups: Vec::new(),
+            trust_email: false,
+        });
     }
+
+    providers
 }
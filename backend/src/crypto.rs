@@ -0,0 +1,315 @@
+// Encryption of secrets at rest (provider API keys, TOTP secrets).
+//
+// Values are AES-256-GCM encrypted with a fresh random nonce and stored as
+// `v{version}:{base64(nonce || ciphertext)}`. The version names the master key
+// used, so several master keys can be active while rows are re-encrypted under
+// a new one. Values without a prefix were written by magic_crypt before this
+// format existed and are still readable.
+use crate::config::Config;
+use crate::error::AppError;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hkdf::Hkdf;
+use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use sha2::Sha256;
+
+const NONCE_LEN: usize = 12;
+const KEY_INFO: &[u8] = b"neko.chat secret encryption";
+
+struct MasterKey {
+    version: u32,
+    cipher: Aes256Gcm,
+    // The raw secret, kept to read values written by magic_crypt.
+    secret: String,
+}
+
+pub struct Keyring {
+    // The first key encrypts; all of them decrypt.
+    keys: Vec<MasterKey>,
+}
+
+impl Keyring {
+    pub fn new(keys: &[(u32, String)]) -> Self {
+        assert!(!keys.is_empty(), "at least one encryption key is required");
+
+        let keys = keys
+            .iter()
+            .map(|(version, secret)| {
+                let mut derived = [0u8; 32];
+                Hkdf::<Sha256>::new(None, secret.as_bytes())
+                    .expand(KEY_INFO, &mut derived)
+                    .expect("32 bytes is a valid HKDF output length");
+                MasterKey {
+                    version: *version,
+                    cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&derived)),
+                    secret: secret.clone(),
+                }
+            })
+            .collect();
+
+        Self { keys }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(&config.encryption_keys)
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.keys[0].version
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let key = &self.keys[0];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM encryption does not fail for in-memory buffers");
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        format!("v{}:{}", key.version, STANDARD.encode(payload))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<String, AppError> {
+        let Some((version, payload)) = parse_versioned(value) else {
+            return self.decrypt_legacy(value);
+        };
+
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.version == version)
+            .ok_or_else(|| {
+                tracing::error!("no encryption key configured for version {}", version);
+                AppError::InternalServerError
+            })?;
+
+        let payload = STANDARD
+            .decode(payload)
+            .map_err(|_| AppError::InternalServerError)?;
+        if payload.len() <= NONCE_LEN {
+            return Err(AppError::InternalServerError);
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+
+        let plaintext = key
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                tracing::error!("failed to decrypt value encrypted with key v{}", version);
+                AppError::InternalServerError
+            })?;
+        String::from_utf8(plaintext).map_err(|_| AppError::InternalServerError)
+    }
+
+    // magic_crypt has no authentication tag, so a wrong key can "succeed" with
+    // garbage; only accept output that is valid UTF-8, trying each key in turn.
+    fn decrypt_legacy(&self, value: &str) -> Result<String, AppError> {
+        self.keys
+            .iter()
+            .find_map(|k| {
+                new_magic_crypt!(&k.secret, 256)
+                    .decrypt_base64_to_string(value)
+                    .ok()
+            })
+            .ok_or(AppError::InternalServerError)
+    }
+
+    // True when a value should be rewritten under the current key.
+    pub fn needs_reencrypt(&self, value: &str) -> bool {
+        parse_versioned(value).is_none_or(|(version, _)| version != self.current_version())
+    }
+}
+
+fn parse_versioned(value: &str) -> Option<(u32, &str)> {
+    let (version, payload) = value.strip_prefix('v')?.split_once(':')?;
+    Some((version.parse().ok()?, payload))
+}
+
+// Parses ENCRYPTION_KEYS ("2:new-secret,1:old-secret"). Versions must be unique.
+pub fn parse_key_list(value: &str) -> Result<Vec<(u32, String)>, String> {
+    let mut keys: Vec<(u32, String)> = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (version, secret) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("expected version:secret, got {:?}", entry))?;
+        let version: u32 = version
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid key version {:?}", version))?;
+        if secret.is_empty() {
+            return Err(format!("key v{} is empty", version));
+        }
+        if keys.iter().any(|(v, _)| *v == version) {
+            return Err(format!("key version {} is listed twice", version));
+        }
+        keys.push((version, secret.to_string()));
+    }
+    if keys.is_empty() {
+        return Err("no keys given".to_string());
+    }
+    Ok(keys)
+}
+
+#[derive(Debug, Default)]
+pub struct ReencryptReport {
+    pub api_keys: u64,
+    pub totp_secrets: u64,
+}
+
+// Rewrites every stored secret that isn't under the current master key (legacy
+// magic_crypt values included). Runs in one transaction, so a value that can't
+// be decrypted aborts the whole run and nothing is half-migrated.
+pub async fn reencrypt_stored_secrets(
+    pool: &sqlx::SqlitePool,
+    keyring: &Keyring,
+) -> Result<ReencryptReport, AppError> {
+    let mut tx = pool.begin().await?;
+    let mut report = ReencryptReport::default();
+
+    // Provider keys, both personal and workspace-owned.
+    for table in ["user_api_keys", "workspace_api_keys"] {
+        let api_keys = sqlx::query_as::<_, (String, String)>(&format!(
+            "SELECT id, encrypted_key FROM {}",
+            table
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for (key_id, encrypted_key) in api_keys {
+            if !keyring.needs_reencrypt(&encrypted_key) {
+                continue;
+            }
+            let plaintext = keyring.decrypt(&encrypted_key).inspect_err(|_| {
+                tracing::error!("cannot decrypt {} row {}", table, key_id);
+            })?;
+            sqlx::query(&format!(
+                "UPDATE {} SET encrypted_key = $1 WHERE id = $2",
+                table
+            ))
+            .bind(keyring.encrypt(&plaintext))
+            .bind(&key_id)
+            .execute(&mut *tx)
+            .await?;
+            report.api_keys += 1;
+        }
+    }
+
+    let totp_secrets = sqlx::query_as::<_, (String, String)>(
+        "SELECT id, totp_secret FROM users WHERE totp_secret IS NOT NULL",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for (user_id, encrypted_secret) in totp_secrets {
+        if !keyring.needs_reencrypt(&encrypted_secret) {
+            continue;
+        }
+        let plaintext = keyring.decrypt(&encrypted_secret).inspect_err(|_| {
+            tracing::error!("cannot decrypt TOTP secret of user {}", user_id);
+        })?;
+        sqlx::query("UPDATE users SET totp_secret = $1 WHERE id = $2")
+            .bind(keyring.encrypt(&plaintext))
+            .bind(&user_id)
+            .execute(&mut *tx)
+            .await?;
+        report.totp_secrets += 1;
+    }
+
+    tx.commit().await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(keys: &[(u32, &str)]) -> Keyring {
+        Keyring::new(
+            &keys
+                .iter()
+                .map(|(version, secret)| (*version, secret.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn round_trips_under_the_current_key() {
+        let keys = keyring(&[(2, "new-secret"), (1, "old-secret")]);
+        let encrypted = keys.encrypt("sk-test-123");
+
+        assert!(encrypted.starts_with("v2:"));
+        assert!(!encrypted.contains("sk-test-123"));
+        assert_eq!(keys.decrypt(&encrypted).unwrap(), "sk-test-123");
+        // Fresh nonce every time.
+        assert_ne!(keys.encrypt("sk-test-123"), encrypted);
+    }
+
+    #[test]
+    fn decrypts_values_under_an_older_key() {
+        let old = keyring(&[(1, "old-secret")]);
+        let encrypted = old.encrypt("sk-test-123");
+
+        let rotated = keyring(&[(2, "new-secret"), (1, "old-secret")]);
+        assert_eq!(rotated.decrypt(&encrypted).unwrap(), "sk-test-123");
+
+        let retired = keyring(&[(2, "new-secret")]);
+        assert!(retired.decrypt(&encrypted).is_err());
+    }
+
+    #[test]
+    fn reads_legacy_magic_crypt_values() {
+        let legacy = new_magic_crypt!("old-secret", 256).encrypt_str_to_base64("sk-legacy");
+
+        let keys = keyring(&[(2, "new-secret"), (1, "old-secret")]);
+        assert_eq!(keys.decrypt(&legacy).unwrap(), "sk-legacy");
+    }
+
+    #[test]
+    fn rejects_tampered_values_and_wrong_keys() {
+        let keys = keyring(&[(1, "secret")]);
+        let encrypted = keys.encrypt("sk-test-123");
+
+        let mut payload = STANDARD.decode(&encrypted[3..]).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        let tampered = format!("v1:{}", STANDARD.encode(payload));
+        assert!(keys.decrypt(&tampered).is_err());
+
+        // Same version, different secret: the tag doesn't verify.
+        assert!(keyring(&[(1, "other-secret")]).decrypt(&encrypted).is_err());
+
+        assert!(keys.decrypt("v1:not base64").is_err());
+        assert!(keys
+            .decrypt(&format!("v1:{}", STANDARD.encode([0u8; NONCE_LEN])))
+            .is_err());
+    }
+
+    #[test]
+    fn flags_values_not_under_the_current_key() {
+        let old = keyring(&[(1, "old-secret")]);
+        let keys = keyring(&[(2, "new-secret"), (1, "old-secret")]);
+        let legacy = new_magic_crypt!("old-secret", 256).encrypt_str_to_base64("sk-legacy");
+
+        assert!(!keys.needs_reencrypt(&keys.encrypt("sk-test")));
+        assert!(keys.needs_reencrypt(&old.encrypt("sk-test")));
+        assert!(keys.needs_reencrypt(&legacy));
+    }
+
+    #[test]
+    fn parses_key_lists() {
+        assert_eq!(
+            parse_key_list("2:new, 1:old").unwrap(),
+            vec![(2, "new".to_string()), (1, "old".to_string())]
+        );
+        assert!(parse_key_list("").is_err());
+        assert!(parse_key_list("new").is_err());
+        assert!(parse_key_list("x:new").is_err());
+        assert!(parse_key_list("1:").is_err());
+        assert!(parse_key_list("1:a,1:b").is_err());
+    }
+}
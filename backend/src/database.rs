@@ -1,4 +1,37 @@
+use crate::error::AppError;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+// Timestamps are stored as TEXT using strftime('%Y-%m-%d %H:%M:%f', 'now').
+pub const DB_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+pub fn parse_db_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f")
+        .ok()
+        .map(|dt| dt.and_utc())
+}
+
+pub fn format_db_timestamp(value: DateTime<Utc>) -> String {
+    value.format(DB_TIMESTAMP_FORMAT).to_string()
+}
+
+// Longest lifetime a client may ask for with `expires_in_days`.
+pub const MAX_EXPIRY_DAYS: i64 = 3650;
+
+// Turns an `expires_in_days` request field into a stored expiry timestamp.
+pub fn expiry_from_days(days: Option<i64>) -> Result<Option<String>, AppError> {
+    match days {
+        None => Ok(None),
+        Some(days) if (1..=MAX_EXPIRY_DAYS).contains(&days) => Ok(Some(format_db_timestamp(
+            Utc::now() + Duration::days(days),
+        ))),
+        Some(_) => Err(AppError::BadRequest(format!(
+            "expires_in_days must be between 1 and {}",
+            MAX_EXPIRY_DAYS
+        ))),
+    }
+}
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
@@ -8,10 +41,14 @@ pub struct User {
     pub name: String,
     #[serde(skip_serializing)]
     pub password_hash: Option<String>,
-    pub google_id: Option<String>,
     pub avatar_url: Option<String>,
     pub role: String,
     pub created_at: String,
+    pub email_verified: bool,
+    pub totp_enabled: bool,
+    pub disabled: bool,
+    pub failed_login_count: i64,
+    pub locked_until: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
@@ -28,6 +65,23 @@ pub struct Chat {
     pub parent_chat_id: Option<String>,
     pub branch_point_message_id: Option<String>,
     pub created_at: String,
+    // Key to use for this chat; the model's or provider's default otherwise.
+    pub api_key_id: Option<String>,
+    // Sampling settings (see llm::GenerationParams) as JSON; NULL uses the
+    // owner's defaults.
+    #[sqlx(default)]
+    #[serde(serialize_with = "serialize_json_text")]
+    pub generation_params: Option<String>,
+    // Routes ({provider, model}) to try in order when the chat's own provider
+    // keeps failing, as JSON; NULL for none.
+    #[sqlx(default)]
+    #[serde(serialize_with = "serialize_json_text")]
+    pub fallback_chain: Option<String>,
+    // The requesting user's access ("owner", "editor" or "viewer"); only set
+    // when listing chats.
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
@@ -38,16 +92,34 @@ pub struct Message {
     pub role: String,
     pub content: String,
     pub created_at: String,
+    // Who wrote the message; None for assistant replies and older messages.
+    pub user_id: Option<String>,
+    // What a reasoning model thought before answering, kept out of `content`.
+    #[sqlx(default)]
+    pub reasoning: Option<String>,
+    // Provider and model that generated an assistant reply, which differ from
+    // the chat's when a fallback route was used. None for older replies.
+    #[sqlx(default)]
+    pub provider: Option<String>,
+    #[sqlx(default)]
+    pub model: Option<String>,
 }
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
 #[serde(rename_all = "camelCase")]
 pub struct UserApiKey {
+    pub id: String,
     pub user_id: String,
     pub provider: String,
+    pub label: String,
+    pub priority: i64,
     #[serde(skip_serializing)]
     pub encrypted_key: String,
+    pub key_hint: Option<String>,
     pub created_at: String,
+    pub last_validated_at: Option<String>,
+    pub last_error: Option<String>,
+    pub last_used_at: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
@@ -59,4 +131,930 @@ pub struct UserModel {
     pub is_enabled: bool,
     pub display_order: i32,
     pub created_at: String,
+    pub api_key_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: String,
+    pub user_id: String,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+    pub last_used_at: String,
+    pub expires_at: String,
+    pub revoked_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UserIdentity {
+    pub id: String,
+    pub user_id: String,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: String,
+    pub last_login_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntry {
+    pub id: String,
+    pub user_id: Option<String>,
+    // Joined from users; None once the actor's account is deleted.
+    pub user_email: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(serialize_with = "serialize_json_text")]
+    pub metadata: Option<String>,
+    pub created_at: String,
+}
+
+// Emits a JSON column as the object it holds rather than as a string.
+pub(crate) fn serialize_json_text<S: serde::Serializer>(
+    value: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value
+        .as_deref()
+        .map(|text| serde_json::from_str::<serde_json::Value>(text).unwrap_or_else(|_| text.into()))
+        .serialize(serializer)
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteCode {
+    pub id: String,
+    pub code: String,
+    pub note: Option<String>,
+    pub created_by: Option<String>,
+    pub expires_at: Option<String>,
+    pub used_by: Option<String>,
+    pub used_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct Workspace {
+    pub id: String,
+    pub name: String,
+    pub created_by: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceApiKey {
+    pub id: String,
+    pub workspace_id: String,
+    pub provider: String,
+    pub label: String,
+    pub priority: i64,
+    #[serde(skip_serializing)]
+    pub encrypted_key: String,
+    pub key_hint: Option<String>,
+    pub added_by: Option<String>,
+    pub created_at: String,
+    pub last_validated_at: Option<String>,
+    pub last_error: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+// Creates the tables on a new database and migrates an existing one to the
+// current schema. Safe to run on every start.
+pub async fn init_schema(db_pool: &SqlitePool) {
+    let schema_statements = vec![
+        r#"CREATE TABLE IF NOT EXISTS users (
+            id TEXT PRIMARY KEY,
+            email TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            password_hash TEXT,
+            google_id TEXT UNIQUE,
+            avatar_url TEXT,
+            role TEXT NOT NULL DEFAULT 'user',
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS chats (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            title TEXT NOT NULL,
+            system_prompt TEXT,
+            provider TEXT NOT NULL DEFAULT 'openai',
+            model TEXT NOT NULL DEFAULT 'gpt-4o',
+            pinned BOOLEAN NOT NULL DEFAULT false,
+            is_branch BOOLEAN NOT NULL DEFAULT false,
+            parent_chat_id TEXT,
+            branch_point_message_id TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS messages (
+            id TEXT PRIMARY KEY,
+            chat_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS user_api_keys (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            label TEXT NOT NULL DEFAULT 'default',
+            priority INTEGER NOT NULL DEFAULT 0,
+            encrypted_key TEXT NOT NULL,
+            key_hint TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            last_validated_at TEXT,
+            last_error TEXT,
+            last_used_at TEXT,
+            UNIQUE (user_id, provider, label),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS system_prompts (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            prompt TEXT NOT NULL,
+            description TEXT,
+            is_default BOOLEAN NOT NULL DEFAULT false,
+            category TEXT NOT NULL DEFAULT 'general',
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS user_settings (
+            user_id TEXT PRIMARY KEY,
+            theme TEXT NOT NULL DEFAULT 'dark',
+            language TEXT NOT NULL DEFAULT 'en',
+            font_size INTEGER NOT NULL DEFAULT 14,
+            notifications_enabled BOOLEAN NOT NULL DEFAULT true,
+            auto_save BOOLEAN NOT NULL DEFAULT true,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS user_models (
+            user_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            model_id TEXT NOT NULL,
+            model_name TEXT NOT NULL,
+            is_enabled BOOLEAN NOT NULL DEFAULT true,
+            display_order INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            PRIMARY KEY (user_id, provider, model_id),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS api_tokens (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            token_prefix TEXT NOT NULL,
+            scopes TEXT NOT NULL,
+            expires_at TEXT,
+            last_used_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS sessions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            refresh_token_hash TEXT NOT NULL UNIQUE,
+            previous_refresh_token_hash TEXT,
+            device_name TEXT,
+            user_agent TEXT,
+            ip_address TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            last_used_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            expires_at TEXT NOT NULL,
+            revoked_at TEXT,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions (user_id)"#,
+        r#"CREATE TABLE IF NOT EXISTS usage_logs (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            model TEXT NOT NULL,
+            source TEXT NOT NULL,
+            prompt_chars INTEGER NOT NULL DEFAULT 0,
+            completion_chars INTEGER NOT NULL DEFAULT 0,
+            success BOOLEAN NOT NULL DEFAULT true,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS oauth_states (
+            state TEXT PRIMARY KEY,
+            provider TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            link_user_id TEXT,
+            expires_at TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (link_user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS mfa_recovery_codes (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS user_identities (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            email TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            last_login_at TEXT,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            UNIQUE(provider, subject),
+            UNIQUE(user_id, provider)
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS audit_events (
+            id TEXT PRIMARY KEY,
+            user_id TEXT,
+            action TEXT NOT NULL,
+            target TEXT,
+            ip_address TEXT,
+            user_agent TEXT,
+            metadata TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS idx_audit_events_created_at ON audit_events (created_at)"#,
+        r#"CREATE INDEX IF NOT EXISTS idx_audit_events_user_id ON audit_events (user_id)"#,
+        // Audit events are append-only. The one update allowed is the actor
+        // being cleared when their account is deleted (ON DELETE SET NULL).
+        r#"CREATE TRIGGER IF NOT EXISTS audit_events_no_update
+            BEFORE UPDATE OF id, action, target, ip_address, user_agent, metadata, created_at ON audit_events
+            BEGIN SELECT RAISE(ABORT, 'audit events are append-only'); END"#,
+        r#"CREATE TRIGGER IF NOT EXISTS audit_events_keep_actor
+            BEFORE UPDATE OF user_id ON audit_events WHEN NEW.user_id IS NOT NULL
+            BEGIN SELECT RAISE(ABORT, 'audit events are append-only'); END"#,
+        r#"CREATE TRIGGER IF NOT EXISTS audit_events_no_delete
+            BEFORE DELETE ON audit_events
+            BEGIN SELECT RAISE(ABORT, 'audit events are append-only'); END"#,
+        r#"CREATE TABLE IF NOT EXISTS invite_codes (
+            id TEXT PRIMARY KEY,
+            code TEXT NOT NULL UNIQUE,
+            note TEXT,
+            created_by TEXT,
+            expires_at TEXT,
+            used_by TEXT,
+            used_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL,
+            FOREIGN KEY (used_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS email_tokens (
+            jti TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            purpose TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            used_at TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS workspaces (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            created_by TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS workspace_members (
+            workspace_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            role TEXT NOT NULL DEFAULT 'member',
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            PRIMARY KEY (workspace_id, user_id),
+            FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS idx_workspace_members_user_id ON workspace_members (user_id)"#,
+        r#"CREATE TABLE IF NOT EXISTS workspace_api_keys (
+            id TEXT PRIMARY KEY,
+            workspace_id TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            label TEXT NOT NULL DEFAULT 'default',
+            priority INTEGER NOT NULL DEFAULT 0,
+            encrypted_key TEXT NOT NULL,
+            key_hint TEXT,
+            added_by TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            last_validated_at TEXT,
+            last_error TEXT,
+            last_used_at TEXT,
+            UNIQUE (workspace_id, provider, label),
+            FOREIGN KEY (workspace_id) REFERENCES workspaces(id) ON DELETE CASCADE,
+            FOREIGN KEY (added_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS chat_members (
+            chat_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            role TEXT NOT NULL DEFAULT 'viewer',
+            invited_by TEXT,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            PRIMARY KEY (chat_id, user_id),
+            FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE,
+            FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY (invited_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS idx_chat_members_user_id ON chat_members (user_id)"#,
+        r#"CREATE TABLE IF NOT EXISTS server_key_models (
+            provider TEXT NOT NULL,
+            model_id TEXT NOT NULL,
+            created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+            PRIMARY KEY (provider, model_id)
+        )"#,
+    ];
+
+    for statement in schema_statements {
+        if let Err(e) = sqlx::query(statement).execute(db_pool).await {
+            if !e.to_string().contains("already exists") {
+                tracing::error!("schema statement failed: {}", e);
+                tracing::error!("statement was: {}", statement);
+            }
+        }
+    }
+
+    tracing::info!("database schema initialized");
+
+    let migration_result =
+        sqlx::query("ALTER TABLE chats ADD COLUMN is_branch BOOLEAN DEFAULT false")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added is_branch column to chats table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("is_branch column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add is_branch column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE chats ADD COLUMN parent_chat_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added parent_chat_id column to chats table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("parent_chat_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add parent_chat_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE chats ADD COLUMN branch_point_message_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => {
+            tracing::info!("added branch_point_message_id column to chats table")
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("branch_point_message_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add branch_point_message_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added email_verified column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("email_verified column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add email_verified column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE users ADD COLUMN totp_secret TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added totp_secret column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("totp_secret column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add totp_secret column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE users ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT false")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added totp_enabled column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("totp_enabled column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add totp_enabled column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE users ADD COLUMN totp_last_step INTEGER")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added totp_last_step column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("totp_last_step column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add totp_last_step column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added disabled column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("disabled column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add disabled column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE oauth_states ADD COLUMN invite_code TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added invite_code column to oauth_states table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("invite_code column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add invite_code column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE users ADD COLUMN failed_login_count INTEGER NOT NULL DEFAULT 0")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added failed_login_count column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("failed_login_count column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add failed_login_count column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE users ADD COLUMN locked_until TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added locked_until column to users table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("locked_until column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add locked_until column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE oauth_states ADD COLUMN nonce TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added nonce column to oauth_states table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("nonce column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add nonce column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    // Google logins used to be stored in users.google_id; move them over to
+    // user_identities (Google's user id is the OIDC subject) and clear the column.
+    let migration_result = sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO user_identities (id, user_id, provider, subject, email)
+        SELECT lower(hex(randomblob(16))), id, 'google', google_id, email
+        FROM users WHERE google_id IS NOT NULL
+        "#,
+    )
+    .execute(db_pool)
+    .await
+    .and(
+        sqlx::query("UPDATE users SET google_id = NULL WHERE google_id IS NOT NULL")
+            .execute(db_pool)
+            .await,
+    );
+
+    match migration_result {
+        Ok(result) if result.rows_affected() > 0 => {
+            tracing::info!(
+                "moved {} google_id links to user_identities",
+                result.rows_affected()
+            )
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("failed to migrate google_id links: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE user_api_keys ADD COLUMN key_hint TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added key_hint column to user_api_keys table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("key_hint column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add key_hint column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE user_api_keys ADD COLUMN last_validated_at TEXT")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added last_validated_at column to user_api_keys table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("last_validated_at column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add last_validated_at column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE user_api_keys ADD COLUMN last_error TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added last_error column to user_api_keys table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("last_error column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add last_error column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE user_api_keys ADD COLUMN last_used_at TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added last_used_at column to user_api_keys table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("last_used_at column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add last_used_at column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE chats ADD COLUMN api_key_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added api_key_id column to chats table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("chats.api_key_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add chats.api_key_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE user_models ADD COLUMN api_key_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added api_key_id column to user_models table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("user_models.api_key_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add user_models.api_key_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query(
+        "ALTER TABLE user_settings ADD COLUMN key_failover BOOLEAN NOT NULL DEFAULT false",
+    )
+    .execute(db_pool)
+    .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added key_failover column to user_settings table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("user_settings.key_failover column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add user_settings.key_failover column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE messages ADD COLUMN user_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added user_id column to messages table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("messages.user_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add messages.user_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE usage_logs ADD COLUMN workspace_id TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added workspace_id column to usage_logs table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("usage_logs.workspace_id column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add usage_logs.workspace_id column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE chats ADD COLUMN generation_params TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added generation_params column to chats table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("chats.generation_params column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add chats.generation_params column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE user_settings ADD COLUMN generation_params TEXT")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added generation_params column to user_settings table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!(
+                "user_settings.generation_params column already exists, skipping migration"
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "failed to add user_settings.generation_params column: {}",
+                e
+            );
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE messages ADD COLUMN reasoning TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added reasoning column to messages table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("messages.reasoning column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add messages.reasoning column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query(
+        "ALTER TABLE user_settings ADD COLUMN reasoning_in_history BOOLEAN NOT NULL DEFAULT false",
+    )
+    .execute(db_pool)
+    .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added reasoning_in_history column to user_settings table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!(
+                "user_settings.reasoning_in_history column already exists, skipping migration"
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "failed to add user_settings.reasoning_in_history column: {}",
+                e
+            );
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result =
+        sqlx::query("ALTER TABLE user_settings ADD COLUMN gemini_safety_settings TEXT")
+            .execute(db_pool)
+            .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added gemini_safety_settings column to user_settings table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!(
+                "user_settings.gemini_safety_settings column already exists, skipping migration"
+            );
+        }
+        Err(e) => {
+            tracing::error!(
+                "failed to add user_settings.gemini_safety_settings column: {}",
+                e
+            );
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE chats ADD COLUMN fallback_chain TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added fallback_chain column to chats table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("chats.fallback_chain column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add chats.fallback_chain column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE messages ADD COLUMN provider TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added provider column to messages table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("messages.provider column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add messages.provider column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    let migration_result = sqlx::query("ALTER TABLE messages ADD COLUMN model TEXT")
+        .execute(db_pool)
+        .await;
+
+    match migration_result {
+        Ok(_) => tracing::info!("added model column to messages table"),
+        Err(sqlx::Error::Database(db_err)) if db_err.message().contains("duplicate column") => {
+            tracing::info!("messages.model column already exists, skipping migration");
+        }
+        Err(e) => {
+            tracing::error!("failed to add messages.model column: {}", e);
+            panic!("migration failed: {}", e);
+        }
+    }
+
+    // user_api_keys used to be keyed by (user_id, provider), so a user could
+    // store one key per provider. Rebuild it with an id and a label; existing
+    // keys become each provider's "default" key.
+    let has_key_ids: bool = sqlx::query_scalar(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('user_api_keys') WHERE name = 'id'",
+    )
+    .fetch_one(db_pool)
+    .await
+    .expect("failed to inspect user_api_keys");
+
+    if !has_key_ids {
+        let migration_result = async {
+            let mut tx = db_pool.begin().await?;
+            sqlx::query(
+                r#"CREATE TABLE user_api_keys_new (
+                    id TEXT PRIMARY KEY,
+                    user_id TEXT NOT NULL,
+                    provider TEXT NOT NULL,
+                    label TEXT NOT NULL DEFAULT 'default',
+                    priority INTEGER NOT NULL DEFAULT 0,
+                    encrypted_key TEXT NOT NULL,
+                    key_hint TEXT,
+                    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+                    last_validated_at TEXT,
+                    last_error TEXT,
+                    last_used_at TEXT,
+                    UNIQUE (user_id, provider, label),
+                    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+                )"#,
+            )
+            .execute(&mut *tx)
+            .await?;
+            let copied = sqlx::query(
+                r#"
+                INSERT INTO user_api_keys_new (id, user_id, provider, label, encrypted_key, key_hint, created_at, last_validated_at, last_error, last_used_at)
+                SELECT lower(hex(randomblob(16))), user_id, provider, 'default', encrypted_key, key_hint, created_at, last_validated_at, last_error, last_used_at
+                FROM user_api_keys
+                "#,
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DROP TABLE user_api_keys").execute(&mut *tx).await?;
+            sqlx::query("ALTER TABLE user_api_keys_new RENAME TO user_api_keys")
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok::<_, sqlx::Error>(copied.rows_affected())
+        }
+        .await;
+
+        match migration_result {
+            Ok(count) => tracing::info!("rebuilt user_api_keys with key ids ({} keys kept)", count),
+            Err(e) => {
+                tracing::error!("failed to rebuild user_api_keys: {}", e);
+                panic!("migration failed: {}", e);
+            }
+        }
+    }
+
+    tracing::info!("all migrations completed");
 }
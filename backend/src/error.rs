@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -11,7 +11,12 @@ pub enum AppError {
     InternalServerError,
     BadRequest(String),
     Unauthorized,
+    Forbidden(String),
     NotFound,
+    TooManyRequests {
+        message: String,
+        retry_after: u64,
+    },
     DatabaseError(sqlx::Error),
     JwtError(jsonwebtoken::errors::Error),
     PasswordHashError(bcrypt::BcryptError),
@@ -19,6 +24,8 @@ pub enum AppError {
         provider: String,
         status_code: Option<u16>,
         message: String,
+        // Seconds the provider asked us to wait (Retry-After), if it said.
+        retry_after: Option<u64>,
     },
 }
 
@@ -28,11 +35,18 @@ impl fmt::Display for AppError {
             AppError::InternalServerError => "Internal Server Error",
             AppError::BadRequest(msg) => msg.as_str(),
             AppError::Unauthorized => "Unauthorized",
+            AppError::Forbidden(msg) => msg.as_str(),
             AppError::NotFound => "Not Found",
+            AppError::TooManyRequests { message, .. } => message.as_str(),
             AppError::DatabaseError(_) => "Database operation failed",
             AppError::JwtError(_) => "Invalid token",
             AppError::PasswordHashError(_) => "Could not process request",
-            AppError::LLMProviderError { provider, status_code, message } => {
+            AppError::LLMProviderError {
+                provider,
+                status_code,
+                message,
+                ..
+            } => {
                 if let Some(code) = status_code {
                     return write!(f, "{} (HTTP {}): {}", provider, code, message);
                 } else {
@@ -52,7 +66,20 @@ impl IntoResponse for AppError {
             AppError::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
             AppError::NotFound => (StatusCode::NOT_FOUND, self.to_string()),
+            AppError::TooManyRequests {
+                message,
+                retry_after,
+            } => {
+                let body = Json(json!({ "error": message, "retryAfter": retry_after }));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
             AppError::DatabaseError(ref e) => {
                 tracing::error!("Database error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
@@ -65,7 +92,12 @@ impl IntoResponse for AppError {
                 tracing::error!("Password hash error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
-            AppError::LLMProviderError { ref provider, status_code, ref message } => {
+            AppError::LLMProviderError {
+                ref provider,
+                status_code,
+                ref message,
+                ..
+            } => {
                 tracing::error!("LLM Provider error - {}: {}", provider, message);
                 // For HTTP responses, convert to appropriate status code
                 let response_status = status_code
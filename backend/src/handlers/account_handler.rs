@@ -0,0 +1,331 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{Claims, ClientInfo},
+    database::{
+        ApiToken, AuditLogEntry, Chat, Message, Session, User, UserApiKey, UserIdentity, UserModel,
+    },
+    error::AppError,
+    handlers::{
+        auth_handler,
+        key_handler::ApiKeyResponse,
+        settings_handler::{SystemPrompt, UserSettings},
+    },
+    AppState,
+};
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+
+// Bumped whenever the layout of the export changes incompatibly.
+const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+pub struct DeleteAccountPayload {
+    password: String,
+    // Required when two-factor authentication is enabled.
+    code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ChatExport {
+    #[serde(flatten)]
+    chat: Chat,
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedChatExport {
+    chat_id: String,
+    title: String,
+    role: String,
+    created_at: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceMembershipExport {
+    workspace_id: String,
+    name: String,
+    role: String,
+    created_at: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageExport {
+    provider: String,
+    model: String,
+    source: String,
+    workspace_id: Option<String>,
+    prompt_chars: i64,
+    completion_chars: i64,
+    success: bool,
+    created_at: String,
+}
+
+// Everything stored about a user. Stored API keys are listed without the key
+// itself, and password hashes, TOTP secrets and token hashes are never included.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExport {
+    format_version: u32,
+    exported_at: String,
+    profile: User,
+    settings: Option<UserSettings>,
+    chats: Vec<ChatExport>,
+    shared_chats: Vec<SharedChatExport>,
+    // Messages the user wrote in chats owned by someone else.
+    messages_in_shared_chats: Vec<Message>,
+    system_prompts: Vec<SystemPrompt>,
+    models: Vec<UserModel>,
+    api_keys: Vec<ApiKeyResponse>,
+    access_tokens: Vec<ApiToken>,
+    sessions: Vec<Session>,
+    identities: Vec<UserIdentity>,
+    workspaces: Vec<WorkspaceMembershipExport>,
+    usage: Vec<UsageExport>,
+    audit_events: Vec<AuditLogEntry>,
+}
+
+async fn build_export(pool: &SqlitePool, user_id: &str) -> Result<AccountExport, AppError> {
+    let profile = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let owned_chats =
+        sqlx::query_as::<_, Chat>("SELECT * FROM chats WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+    let mut chats = Vec::with_capacity(owned_chats.len());
+    for chat in owned_chats {
+        let messages = sqlx::query_as::<_, Message>(
+            "SELECT * FROM messages WHERE chat_id = $1 ORDER BY created_at",
+        )
+        .bind(&chat.id)
+        .fetch_all(pool)
+        .await?;
+        chats.push(ChatExport { chat, messages });
+    }
+
+    let api_keys = sqlx::query_as::<_, UserApiKey>(
+        "SELECT * FROM user_api_keys WHERE user_id = $1 ORDER BY provider, priority",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(AccountExport {
+        format_version: EXPORT_FORMAT_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        settings: sqlx::query_as("SELECT * FROM user_settings WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?,
+        chats,
+        shared_chats: sqlx::query_as(
+            r#"
+            SELECT chat_members.chat_id, chats.title, chat_members.role, chat_members.created_at
+            FROM chat_members JOIN chats ON chats.id = chat_members.chat_id
+            WHERE chat_members.user_id = $1
+            ORDER BY chat_members.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        messages_in_shared_chats: sqlx::query_as(
+            r#"
+            SELECT messages.* FROM messages JOIN chats ON chats.id = messages.chat_id
+            WHERE messages.user_id = $1 AND chats.user_id != $1
+            ORDER BY messages.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        system_prompts: sqlx::query_as(
+            "SELECT * FROM system_prompts WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        models: sqlx::query_as(
+            "SELECT * FROM user_models WHERE user_id = $1 ORDER BY provider, display_order",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        api_keys: api_keys.into_iter().map(ApiKeyResponse::from).collect(),
+        access_tokens: sqlx::query_as(
+            "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        sessions: sqlx::query_as("SELECT * FROM sessions WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?,
+        identities: sqlx::query_as(
+            "SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        workspaces: sqlx::query_as(
+            r#"
+            SELECT workspaces.id AS workspace_id, workspaces.name, m.role, m.created_at
+            FROM workspace_members m JOIN workspaces ON workspaces.id = m.workspace_id
+            WHERE m.user_id = $1
+            ORDER BY m.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        usage: sqlx::query_as(
+            r#"
+            SELECT provider, model, source, workspace_id, prompt_chars, completion_chars, success, created_at
+            FROM usage_logs WHERE user_id = $1 ORDER BY created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        audit_events: sqlx::query_as(
+            r#"
+            SELECT audit_events.*, users.email AS user_email
+            FROM audit_events LEFT JOIN users ON users.id = audit_events.user_id
+            WHERE audit_events.user_id = $1
+            ORDER BY audit_events.created_at
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?,
+        profile,
+    })
+}
+
+// Returns a JSON archive of everything stored about the user, as a download.
+pub async fn export_account(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    let export = build_export(&app_state.db_pool, &claims.sub).await?;
+
+    AuditEvent::new(audit::ACCOUNT_EXPORTED)
+        .user(&claims.sub)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    let disposition = format!(
+        "attachment; filename=\"neko-chat-export-{}.json\"",
+        Utc::now().format("%Y-%m-%d")
+    );
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(export)))
+}
+
+// Deletes the account and everything that belongs to it: chats and their
+// messages, keys, prompts, settings, models, tokens and sessions all cascade
+// from the user row. The server keeps no files per user (there are no
+// attachments on disk), so the database is all there is to clean up.
+// Workspaces the user solely owns go with them when nobody else is in them;
+// otherwise ownership has to be handed over first.
+pub async fn delete_account(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Json(payload): Json<DeleteAccountPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let pool = &app_state.db_pool;
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(pool)
+        .await?;
+
+    let verified = auth_handler::reauthenticate(
+        &app_state,
+        &user,
+        &payload.password,
+        payload.code.as_deref(),
+        "delete it",
+    )
+    .await?;
+    if !verified {
+        AuditEvent::new(audit::ACCOUNT_DELETION_DENIED)
+            .user(&user.id)
+            .client(&client)
+            .record(&app_state)
+            .await?;
+        return Err(AppError::Forbidden("re-authentication failed".to_string()));
+    }
+
+    // (workspace id, name, whether anyone else is a member)
+    let sole_owned: Vec<(String, String, bool)> = sqlx::query_as(
+        r#"
+        SELECT w.id, w.name,
+            EXISTS(SELECT 1 FROM workspace_members o WHERE o.workspace_id = w.id AND o.user_id != $1)
+        FROM workspaces w
+        JOIN workspace_members m ON m.workspace_id = w.id AND m.user_id = $1 AND m.role = 'owner'
+        WHERE NOT EXISTS(
+            SELECT 1 FROM workspace_members o
+            WHERE o.workspace_id = w.id AND o.user_id != $1 AND o.role = 'owner'
+        )
+        "#,
+    )
+    .bind(&user.id)
+    .fetch_all(pool)
+    .await?;
+
+    let blocking: Vec<&str> = sole_owned
+        .iter()
+        .filter(|(_, _, has_others)| *has_others)
+        .map(|(_, name, _)| name.as_str())
+        .collect();
+    if !blocking.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "make someone else an owner of these workspaces first: {}",
+            blocking.join(", ")
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+    for (workspace_id, _, _) in &sole_owned {
+        sqlx::query("DELETE FROM workspaces WHERE id = $1")
+            .bind(workspace_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    // Messages written in other people's chats stay in those chats, but no
+    // longer point at the deleted account.
+    sqlx::query("UPDATE messages SET user_id = NULL WHERE user_id = $1")
+        .bind(&user.id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(&user.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    AuditEvent::new(audit::ACCOUNT_DELETED)
+        .target(&user.id)
+        .client(&client)
+        .metadata(json!({
+            "email": user.email,
+            "deleted_workspaces": sole_owned.iter().map(|(id, _, _)| id).collect::<Vec<_>>(),
+        }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
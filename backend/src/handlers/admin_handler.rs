@@ -0,0 +1,594 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{self, AdminClaims, ClientInfo},
+    config::{Config, SERVER_KEY_PROVIDERS},
+    database::{expiry_from_days, AuditLogEntry, InviteCode, User},
+    error::AppError,
+    handlers::{auth_handler::is_valid_email, key_handler},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use bcrypt::{hash, DEFAULT_COST};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const VALID_ROLES: [&str; 2] = ["user", "admin"];
+
+// Per-user activity figures shown next to each account.
+const USER_SUMMARY_SELECT: &str = r#"
+    SELECT users.*,
+        (SELECT COUNT(*) FROM chats WHERE chats.user_id = users.id) AS chat_count,
+        (SELECT COUNT(*) FROM messages JOIN chats ON chats.id = messages.chat_id
+            WHERE chats.user_id = users.id) AS message_count,
+        (SELECT MAX(activity) FROM (
+            SELECT MAX(last_used_at) AS activity FROM sessions WHERE sessions.user_id = users.id
+            UNION ALL
+            SELECT MAX(messages.created_at) FROM messages JOIN chats ON chats.id = messages.chat_id
+                WHERE chats.user_id = users.id
+        )) AS last_active_at
+    FROM users
+"#;
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminUserSummary {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    user: User,
+    chat_count: i64,
+    message_count: i64,
+    last_active_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ListUsersQuery {
+    q: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct AuditEventsQuery {
+    user_id: Option<String>,
+    // An exact action ("auth.login") or a whole category ("auth").
+    action: Option<String>,
+    target: Option<String>,
+    ip: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateUserPayload {
+    name: String,
+    email: String,
+    password: String,
+    role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserPayload {
+    name: Option<String>,
+    role: Option<String>,
+    disabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordPayload {
+    #[serde(alias = "newPassword")]
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInvitePayload {
+    note: Option<String>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminStats {
+    user_count: i64,
+    admin_count: i64,
+    disabled_count: i64,
+    active_users_last_7_days: i64,
+    chat_count: i64,
+    message_count: i64,
+    last_activity_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ServerKeyModelsPayload {
+    models: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerKeyStatus {
+    provider: String,
+    configured: bool,
+    // Empty when every model may be used with the key.
+    allowed_models: Vec<String>,
+}
+
+fn validate_role(role: &str) -> Result<(), AppError> {
+    if VALID_ROLES.contains(&role) {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!(
+            "unknown role '{}'. valid roles: {}",
+            role,
+            VALID_ROLES.join(", ")
+        )))
+    }
+}
+
+async fn fetch_summary(pool: &SqlitePool, user_id: &str) -> Result<AdminUserSummary, AppError> {
+    sqlx::query_as::<_, AdminUserSummary>(&format!("{} WHERE users.id = $1", USER_SUMMARY_SELECT))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::NotFound)
+}
+
+pub async fn list_users(
+    State(pool): State<SqlitePool>,
+    _admin: AdminClaims,
+    Query(params): Query<ListUsersQuery>,
+) -> Result<Json<Vec<AdminUserSummary>>, AppError> {
+    let search = params
+        .q
+        .map(|q| q.trim().to_string())
+        .filter(|q| !q.is_empty())
+        .map(|q| format!("%{}%", q));
+
+    let users = sqlx::query_as::<_, AdminUserSummary>(&format!(
+        "{} WHERE ($1 IS NULL OR users.email LIKE $1 OR users.name LIKE $1) ORDER BY users.created_at DESC LIMIT $2 OFFSET $3",
+        USER_SUMMARY_SELECT
+    ))
+    .bind(search)
+    .bind(params.limit.unwrap_or(50).clamp(1, 500))
+    .bind(params.offset.unwrap_or(0).max(0))
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(users))
+}
+
+pub async fn get_user(
+    State(pool): State<SqlitePool>,
+    _admin: AdminClaims,
+    Path(user_id): Path<String>,
+) -> Result<Json<AdminUserSummary>, AppError> {
+    Ok(Json(fetch_summary(&pool, &user_id).await?))
+}
+
+pub async fn create_user(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Json(payload): Json<CreateUserPayload>,
+) -> Result<Json<AdminUserSummary>, AppError> {
+    let pool = &app_state.db_pool;
+    let email = payload.email.trim();
+    if !is_valid_email(email) {
+        return Err(AppError::BadRequest("invalid email address".to_string()));
+    }
+    if payload.name.trim().is_empty() || payload.password.is_empty() {
+        return Err(AppError::BadRequest(
+            "name and password are required".to_string(),
+        ));
+    }
+    let role = payload.role.as_deref().unwrap_or("user");
+    validate_role(role)?;
+
+    let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_one(pool)
+        .await?;
+    if exists > 0 {
+        return Err(AppError::BadRequest(
+            "a user with this email already exists".to_string(),
+        ));
+    }
+
+    let user_id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO users (id, name, email, password_hash, role) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(&user_id)
+    .bind(payload.name.trim())
+    .bind(email)
+    .bind(hash(&payload.password, DEFAULT_COST)?)
+    .bind(role)
+    .execute(pool)
+    .await?;
+
+    AuditEvent::new(audit::ADMIN_USER_CREATED)
+        .user(&claims.sub)
+        .target(&user_id)
+        .client(&client)
+        .metadata(json!({ "email": email, "role": role }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(fetch_summary(pool, &user_id).await?))
+}
+
+pub async fn update_user(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateUserPayload>,
+) -> Result<Json<AdminUserSummary>, AppError> {
+    let pool = &app_state.db_pool;
+    // Guard against admins locking themselves out.
+    if user_id == claims.sub
+        && (payload.disabled == Some(true) || payload.role.as_deref().is_some_and(|r| r != "admin"))
+    {
+        return Err(AppError::BadRequest(
+            "you cannot disable or demote your own account".to_string(),
+        ));
+    }
+    if let Some(role) = payload.role.as_deref() {
+        validate_role(role)?;
+    }
+    if matches!(payload.name.as_deref(), Some(name) if name.trim().is_empty()) {
+        return Err(AppError::BadRequest("name cannot be empty".to_string()));
+    }
+
+    let result = sqlx::query(
+        r#"
+        UPDATE users SET
+            name = COALESCE($1, name),
+            role = COALESCE($2, role),
+            disabled = COALESCE($3, disabled)
+        WHERE id = $4
+        "#,
+    )
+    .bind(payload.name.as_deref().map(str::trim))
+    .bind(&payload.role)
+    .bind(payload.disabled)
+    .bind(&user_id)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    if payload.disabled == Some(true) {
+        auth::revoke_all_sessions(pool, &user_id, None).await?;
+    }
+
+    AuditEvent::new(audit::ADMIN_USER_UPDATED)
+        .user(&claims.sub)
+        .target(&user_id)
+        .client(&client)
+        .metadata(json!({
+            "name": payload.name,
+            "role": payload.role,
+            "disabled": payload.disabled,
+        }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(fetch_summary(pool, &user_id).await?))
+}
+
+pub async fn reset_user_password(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+    Json(payload): Json<ResetPasswordPayload>,
+) -> Result<Json<Value>, AppError> {
+    let pool = &app_state.db_pool;
+    if payload.new_password.is_empty() {
+        return Err(AppError::BadRequest("new password is required".to_string()));
+    }
+
+    // Resetting the password also lifts any login lockout.
+    let result = sqlx::query(
+        "UPDATE users SET password_hash = $1, failed_login_count = 0, locked_until = NULL WHERE id = $2",
+    )
+    .bind(hash(&payload.new_password, DEFAULT_COST)?)
+        .bind(&user_id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    auth::revoke_all_sessions(pool, &user_id, None).await?;
+    auth::revoke_api_tokens(pool, &user_id).await?;
+
+    AuditEvent::new(audit::ADMIN_PASSWORD_RESET)
+        .user(&claims.sub)
+        .target(&user_id)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn delete_user(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    if user_id == claims.sub {
+        return Err(AppError::BadRequest(
+            "you cannot delete your own account here".to_string(),
+        ));
+    }
+
+    let email: String = sqlx::query_scalar("DELETE FROM users WHERE id = $1 RETURNING email")
+        .bind(&user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    AuditEvent::new(audit::ADMIN_USER_DELETED)
+        .user(&claims.sub)
+        .target(&user_id)
+        .client(&client)
+        .metadata(json!({ "email": email }))
+        .record(&app_state)
+        .await
+}
+
+pub async fn get_stats(
+    State(pool): State<SqlitePool>,
+    _admin: AdminClaims,
+) -> Result<Json<AdminStats>, AppError> {
+    let (user_count, admin_count, disabled_count) = sqlx::query_as::<_, (i64, i64, i64)>(
+        r#"
+        SELECT COUNT(*),
+            COALESCE(SUM(role = 'admin'), 0),
+            COALESCE(SUM(disabled), 0)
+        FROM users
+        "#,
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let active_users_last_7_days = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(DISTINCT user_id) FROM sessions
+        WHERE last_used_at > strftime('%Y-%m-%d %H:%M:%f', 'now', '-7 days')
+        "#,
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let chat_count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM chats")
+        .fetch_one(&pool)
+        .await?;
+
+    let (message_count, last_activity_at) = sqlx::query_as::<_, (i64, Option<String>)>(
+        "SELECT COUNT(*), MAX(created_at) FROM messages",
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    Ok(Json(AdminStats {
+        user_count,
+        admin_count,
+        disabled_count,
+        active_users_last_7_days,
+        chat_count,
+        message_count,
+        last_activity_at,
+    }))
+}
+
+// Invite codes look like "K7QF-2MZX-9RTA": easy to read out and type.
+fn generate_invite_code() -> String {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..3)
+        .map(|_| {
+            (0..4)
+                .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub async fn list_invites(
+    State(pool): State<SqlitePool>,
+    _admin: AdminClaims,
+) -> Result<Json<Vec<InviteCode>>, AppError> {
+    let invites =
+        sqlx::query_as::<_, InviteCode>("SELECT * FROM invite_codes ORDER BY created_at DESC")
+            .fetch_all(&pool)
+            .await?;
+
+    Ok(Json(invites))
+}
+
+pub async fn create_invite(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Json(payload): Json<CreateInvitePayload>,
+) -> Result<Json<InviteCode>, AppError> {
+    let pool = &app_state.db_pool;
+    let expires_at = expiry_from_days(payload.expires_in_days)?;
+
+    let invite = sqlx::query_as::<_, InviteCode>(
+        r#"
+        INSERT INTO invite_codes (id, code, note, created_by, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(generate_invite_code())
+    .bind(payload.note)
+    .bind(&claims.sub)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+
+    AuditEvent::new(audit::ADMIN_INVITE_CREATED)
+        .user(&claims.sub)
+        .target(&invite.id)
+        .client(&client)
+        .metadata(json!({ "note": invite.note, "expires_at": invite.expires_at }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(invite))
+}
+
+pub async fn delete_invite(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(invite_id): Path<String>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    let result = sqlx::query("DELETE FROM invite_codes WHERE id = $1")
+        .bind(&invite_id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::ADMIN_INVITE_DELETED)
+        .user(&claims.sub)
+        .target(&invite_id)
+        .client(&client)
+        .record(&app_state)
+        .await
+}
+
+async fn server_key_status(
+    pool: &SqlitePool,
+    config: &Config,
+    provider: &str,
+) -> Result<ServerKeyStatus, AppError> {
+    Ok(ServerKeyStatus {
+        provider: provider.to_string(),
+        configured: config.server_api_keys.contains_key(provider),
+        allowed_models: key_handler::server_key_models(pool, provider).await?,
+    })
+}
+
+// Which providers have a server-wide key and the models each is limited to.
+pub async fn list_server_keys(
+    State(pool): State<SqlitePool>,
+    State(config): State<Config>,
+    _admin: AdminClaims,
+) -> Result<Json<Vec<ServerKeyStatus>>, AppError> {
+    let mut statuses = Vec::with_capacity(SERVER_KEY_PROVIDERS.len());
+    for provider in SERVER_KEY_PROVIDERS {
+        statuses.push(server_key_status(&pool, &config, provider).await?);
+    }
+    Ok(Json(statuses))
+}
+
+// Replaces the models usable with a provider's server key; an empty list
+// lifts the restriction.
+pub async fn set_server_key_models(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(provider): Path<String>,
+    Json(payload): Json<ServerKeyModelsPayload>,
+) -> Result<Json<ServerKeyStatus>, AppError> {
+    let pool = &app_state.db_pool;
+    if !SERVER_KEY_PROVIDERS.contains(&provider.as_str()) {
+        return Err(AppError::BadRequest(format!(
+            "unknown provider '{}'",
+            provider
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM server_key_models WHERE provider = $1")
+        .bind(&provider)
+        .execute(&mut *tx)
+        .await?;
+    for model in payload
+        .models
+        .iter()
+        .map(|m| m.trim())
+        .filter(|m| !m.is_empty())
+    {
+        sqlx::query(
+            "INSERT INTO server_key_models (provider, model_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(&provider)
+        .bind(model)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+
+    AuditEvent::new(audit::ADMIN_SERVER_KEY_MODELS_SET)
+        .user(&claims.sub)
+        .target(&provider)
+        .client(&client)
+        .metadata(json!({ "models": payload.models }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(
+        server_key_status(pool, &app_state.config, &provider).await?,
+    ))
+}
+
+// Newest first. `since` and `until` take timestamps in the stored format
+// ("2024-05-01" or "2024-05-01 12:00:00") and bound created_at.
+pub async fn list_audit_events(
+    State(pool): State<SqlitePool>,
+    _admin: AdminClaims,
+    Query(params): Query<AuditEventsQuery>,
+) -> Result<Json<Vec<AuditLogEntry>>, AppError> {
+    let events = sqlx::query_as::<_, AuditLogEntry>(
+        r#"
+        SELECT audit_events.*, users.email AS user_email
+        FROM audit_events
+        LEFT JOIN users ON users.id = audit_events.user_id
+        WHERE ($1 IS NULL OR audit_events.user_id = $1)
+          AND ($2 IS NULL OR action = $2 OR action LIKE $2 || '.%')
+          AND ($3 IS NULL OR target = $3)
+          AND ($4 IS NULL OR ip_address = $4)
+          AND ($5 IS NULL OR audit_events.created_at >= $5)
+          AND ($6 IS NULL OR audit_events.created_at < $6)
+        ORDER BY audit_events.created_at DESC
+        LIMIT $7 OFFSET $8
+        "#,
+    )
+    .bind(params.user_id)
+    .bind(params.action)
+    .bind(params.target)
+    .bind(params.ip)
+    .bind(params.since)
+    .bind(params.until)
+    .bind(params.limit.unwrap_or(100).clamp(1, 1000))
+    .bind(params.offset.unwrap_or(0).max(0))
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(events))
+}
@@ -1,13 +1,25 @@
-use crate::{auth, database::User, error::AppError, AppState};
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{
+        self, hash_token, Claims, ClientInfo, EmailTokenPurpose, SessionTokens,
+        REFRESH_TOKEN_PREFIX,
+    },
+    config::Config,
+    database::{format_db_timestamp, parse_db_timestamp, Session, User},
+    error::AppError,
+    handlers::mfa_handler,
+    mailer::Email,
+    rate_limit, AppState,
+};
 use axum::{
-    extract::{Query, State},
+    extract::{Path, State},
     Json,
 };
 use bcrypt::{hash, verify, DEFAULT_COST};
-use reqwest::Client;
+use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
 use uuid::Uuid;
 
 #[derive(Deserialize)]
@@ -15,41 +27,206 @@ pub struct RegisterPayload {
     name: String,
     email: String,
     password: String,
+    #[serde(alias = "inviteCode")]
+    invite_code: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct LoginPayload {
     email: String,
     password: String,
+    device_name: Option<String>,
 }
 
 #[derive(Deserialize)]
-pub struct GoogleCallbackQuery {
-    code: String,
-    state: String,
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordPayload {
+    current_password: String,
+    new_password: String,
+    // Required when two-factor authentication is enabled.
+    code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshPayload {
+    refresh_token: String,
 }
 
 #[derive(Deserialize)]
-pub struct GoogleUserInfo {
-    id: String,
+pub struct EmailTokenPayload {
+    token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequestPayload {
     email: String,
-    name: String,
-    picture: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirmPayload {
+    token: String,
+    #[serde(alias = "newPassword")]
+    new_password: String,
 }
 
 #[derive(Serialize)]
 pub struct AuthResponse {
-    token: String,
+    #[serde(flatten)]
+    tokens: SessionTokens,
     user: User,
 }
 
+// Login answers with tokens, or with a pending-MFA token when the account has
+// 2FA enabled; that token is exchanged at POST /api/auth/mfa.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(Box<AuthResponse>),
+    MfaRequired {
+        mfa_required: bool,
+        mfa_token: String,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct MfaLoginPayload {
+    mfa_token: String,
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    #[serde(flatten)]
+    session: Session,
+    current: bool,
+}
+
+const VERIFY_EMAIL_TTL_HOURS: i64 = 24;
+const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+// Deliberately loose: a single '@', a dotted domain and no whitespace. The
+// verification email is what actually proves the address works.
+pub(crate) fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    email.len() <= 254
+        && !local.is_empty()
+        && !domain.contains('@')
+        && !email.chars().any(char::is_whitespace)
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|part| !part.is_empty())
+}
+
+async fn send_verification_email(app_state: &AppState, user: &User) -> Result<(), AppError> {
+    let token = auth::issue_email_token(
+        app_state,
+        &user.id,
+        EmailTokenPurpose::VerifyEmail,
+        Duration::hours(VERIFY_EMAIL_TTL_HOURS),
+    )
+    .await?;
+
+    app_state
+        .mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Hi {},\n\nConfirm your email address for neko.chat by opening this link:\n\n{}/auth/verify-email?token={}\n\nThe link expires in {} hours.\n",
+                user.name, app_state.config.app_base_url, token, VERIFY_EMAIL_TTL_HOURS
+            ),
+        })
+        .await
+}
+
+// Rejects new accounts that ALLOW_SIGNUP / SIGNUP_ALLOWED_DOMAINS don't permit,
+// and makes sure an invite code is present when one is required.
+pub(crate) fn check_signup_policy(
+    config: &Config,
+    email: &str,
+    invite_code: Option<&str>,
+) -> Result<(), AppError> {
+    if !config.allow_signup {
+        return Err(AppError::Forbidden(
+            "public signup is disabled on this server. ask an admin to create an account for you"
+                .to_string(),
+        ));
+    }
+
+    if !config.signup_allowed_domains.is_empty() {
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+        if !config.signup_allowed_domains.contains(&domain) {
+            return Err(AppError::Forbidden(format!(
+                "signup is restricted to these email domains: {}",
+                config.signup_allowed_domains.join(", ")
+            )));
+        }
+    }
+
+    if config.require_invite_code && invite_code.is_none_or(|c| c.trim().is_empty()) {
+        return Err(AppError::Forbidden(
+            "an invite code is required to sign up".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// Claims a single-use invite for a new user. Runs inside the signup
+// transaction so a failed signup doesn't burn the code.
+pub(crate) async fn redeem_invite_code(
+    conn: &mut SqliteConnection,
+    code: &str,
+    user_id: &str,
+) -> Result<(), AppError> {
+    let result = sqlx::query(
+        r#"
+        UPDATE invite_codes
+        SET used_by = $1, used_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+        WHERE code = $2 AND used_at IS NULL
+          AND (expires_at IS NULL OR expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now'))
+        "#,
+    )
+    .bind(user_id)
+    .bind(code.trim().to_uppercase())
+    .execute(conn)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest(
+            "invite code is invalid, expired or already used".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn signup_options(State(config): State<Config>) -> Json<Value> {
+    Json(json!({
+        "allow_signup": config.allow_signup,
+        "require_invite_code": config.require_invite_code,
+        "allowed_domains": config.signup_allowed_domains,
+    }))
+}
+
 pub async fn register(
-    State(pool): State<SqlitePool>,
+    State(app_state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<RegisterPayload>,
 ) -> Result<Json<User>, AppError> {
+    let email = payload.email.trim();
+    if !is_valid_email(email) {
+        return Err(AppError::BadRequest("invalid email address".to_string()));
+    }
+    check_signup_policy(&app_state.config, email, payload.invite_code.as_deref())?;
+
     let password_hash = hash(&payload.password, DEFAULT_COST)?;
     let user_id = Uuid::new_v4().to_string();
 
+    let mut tx = app_state.db_pool.begin().await?;
     sqlx::query(
         r#"
         INSERT INTO users (id, name, email, password_hash)
@@ -58,158 +235,590 @@ pub async fn register(
     )
     .bind(&user_id)
     .bind(&payload.name)
-    .bind(&payload.email)
+    .bind(email)
     .bind(&password_hash)
-    .execute(&pool)
+    .execute(&mut *tx)
     .await?;
 
+    if let (true, Some(code)) = (
+        app_state.config.require_invite_code,
+        payload.invite_code.as_deref(),
+    ) {
+        redeem_invite_code(&mut tx, code, &user_id).await?;
+    }
+    tx.commit().await?;
+
     let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
         .bind(user_id)
-        .fetch_one(&pool)
+        .fetch_one(&app_state.db_pool)
         .await?;
 
+    AuditEvent::new(audit::REGISTERED)
+        .user(&user.id)
+        .target(&user.email)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    // Registration still succeeds if the mail can't be sent; the user can ask for a new link.
+    if let Err(e) = send_verification_email(&app_state, &user).await {
+        tracing::warn!(
+            "failed to send verification email to {}: {:?}",
+            user.email,
+            e
+        );
+    }
+
     Ok(Json(user))
 }
 
 pub async fn login(
     State(app_state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<LoginPayload>,
-) -> Result<Json<AuthResponse>, AppError> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
-        .bind(payload.email)
+) -> Result<Json<LoginResponse>, AppError> {
+    app_state.rate_limits.check_login_account(&payload.email)?;
+
+    let Some(user) = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+        .bind(&payload.email)
         .fetch_optional(&app_state.db_pool)
         .await?
-        .ok_or(AppError::Unauthorized)?;
+    else {
+        AuditEvent::new(audit::LOGIN_FAILED)
+            .target(&payload.email)
+            .client(&client)
+            .metadata(json!({ "reason": "unknown_account" }))
+            .record(&app_state)
+            .await?;
+        return Err(AppError::Unauthorized);
+    };
+
+    ensure_not_locked(&user)?;
 
     // Check if user has a password (not OAuth user)
     let password_hash = user.password_hash.as_ref().ok_or(AppError::Unauthorized)?;
 
     if !verify(&payload.password, password_hash)? {
+        record_failed_login(&app_state, &user.id).await?;
+        AuditEvent::new(audit::LOGIN_FAILED)
+            .user(&user.id)
+            .target(&user.email)
+            .client(&client)
+            .metadata(json!({ "reason": "wrong_password" }))
+            .record(&app_state)
+            .await?;
         return Err(AppError::Unauthorized);
     }
 
-    let token = auth::create_jwt(user.id.clone(), &app_state.config.jwt_secret)?;
+    Ok(Json(
+        start_session(&app_state, user, &client, payload.device_name).await?,
+    ))
+}
+
+// Rejects credential checks while an account is locked after failed logins.
+pub(crate) fn ensure_not_locked(user: &User) -> Result<(), AppError> {
+    let Some(locked_until) = user.locked_until.as_deref().and_then(parse_db_timestamp) else {
+        return Ok(());
+    };
 
-    Ok(Json(AuthResponse { token, user }))
+    let remaining = locked_until - Utc::now();
+    if remaining > Duration::zero() {
+        return Err(AppError::TooManyRequests {
+            message: "too many failed login attempts, try again later".to_string(),
+            retry_after: ((remaining.num_milliseconds() + 999) / 1000) as u64,
+        });
+    }
+    Ok(())
 }
 
-pub async fn get_me(claims: crate::auth::Claims) -> Result<Json<Value>, AppError> {
-    Ok(Json(json!({ "user_id": claims.sub })))
+// Counts a failed password or second-factor attempt and locks the account once
+// the configured threshold is reached, for longer with every further failure.
+pub(crate) async fn record_failed_login(
+    app_state: &AppState,
+    user_id: &str,
+) -> Result<(), AppError> {
+    let failures: i64 = sqlx::query_scalar(
+        "UPDATE users SET failed_login_count = failed_login_count + 1 WHERE id = $1 RETURNING failed_login_count",
+    )
+    .bind(user_id)
+    .fetch_one(&app_state.db_pool)
+    .await?;
+
+    let config = &app_state.config;
+    if let Some(lock) = rate_limit::lockout_duration(
+        failures,
+        config.login_lockout_threshold,
+        config.login_lockout_base_seconds,
+        config.login_lockout_max_seconds,
+    ) {
+        sqlx::query("UPDATE users SET locked_until = $1 WHERE id = $2")
+            .bind(format_db_timestamp(Utc::now() + lock))
+            .bind(user_id)
+            .execute(&app_state.db_pool)
+            .await?;
+        tracing::warn!(
+            "locked user {} for {}s after {} failed login attempts",
+            user_id,
+            lock.num_seconds(),
+            failures
+        );
+    }
+
+    Ok(())
 }
 
-pub async fn google_auth_url(State(app_state): State<AppState>) -> Result<Json<Value>, AppError> {
-    let client_id = app_state
-        .config
-        .google_client_id
-        .ok_or_else(|| AppError::BadRequest("Google OAuth not configured".to_string()))?;
+// Asks for the password again (and the second factor when enabled) before a
+// sensitive action. Failures count towards the login lockout like a failed login.
+pub(crate) async fn reauthenticate(
+    app_state: &AppState,
+    user: &User,
+    password: &str,
+    code: Option<&str>,
+    purpose: &str,
+) -> Result<bool, AppError> {
+    ensure_not_locked(user)?;
 
-    let auth_url = format!(
-        "https://accounts.google.com/o/oauth2/auth?client_id={}&redirect_uri={}&scope=openid email profile&response_type=code&state={}",
-        client_id,
-        urlencoding::encode(&app_state.config.google_redirect_uri),
-        uuid::Uuid::new_v4()
-    );
+    let password_hash = user.password_hash.as_deref().ok_or_else(|| {
+        AppError::BadRequest(format!("set a password on your account to {}", purpose))
+    })?;
 
-    Ok(Json(json!({ "auth_url": auth_url })))
+    let mut verified = verify(password, password_hash)?;
+    if verified && user.totp_enabled {
+        verified = match code {
+            Some(code) => mfa_handler::verify_second_factor(app_state, &user.id, code).await?,
+            None => false,
+        };
+    }
+
+    if !verified {
+        record_failed_login(app_state, &user.id).await?;
+    }
+    Ok(verified)
+}
+
+async fn clear_failed_logins(pool: &SqlitePool, user: &User) -> Result<(), AppError> {
+    if user.failed_login_count > 0 || user.locked_until.is_some() {
+        sqlx::query("UPDATE users SET failed_login_count = 0, locked_until = NULL WHERE id = $1")
+            .bind(&user.id)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
+
+// Issues tokens once the first factor checked out, unless a second one is needed.
+pub(crate) async fn start_session(
+    app_state: &AppState,
+    user: User,
+    client: &ClientInfo,
+    device_name: Option<String>,
+) -> Result<LoginResponse, AppError> {
+    if user.disabled {
+        return Err(AppError::Forbidden(
+            "this account has been disabled".to_string(),
+        ));
+    }
+
+    if user.totp_enabled {
+        let mfa_token =
+            auth::create_mfa_token(&user.id, device_name, &app_state.config.jwt_secret)?;
+        return Ok(LoginResponse::MfaRequired {
+            mfa_required: true,
+            mfa_token,
+        });
+    }
+
+    // Failures only reset once every factor has been checked, so a known
+    // password can't be used to keep retrying second-factor codes.
+    clear_failed_logins(&app_state.db_pool, &user).await?;
+
+    let tokens = auth::create_session(app_state, &user.id, client, device_name).await?;
+    AuditEvent::new(audit::LOGIN)
+        .user(&user.id)
+        .target(&user.email)
+        .client(client)
+        .record(app_state)
+        .await?;
+    Ok(LoginResponse::Authenticated(Box::new(AuthResponse {
+        tokens,
+        user,
+    })))
 }
 
-pub async fn google_callback(
+pub async fn complete_mfa_login(
     State(app_state): State<AppState>,
-    Query(params): Query<GoogleCallbackQuery>,
+    client: ClientInfo,
+    Json(payload): Json<MfaLoginPayload>,
 ) -> Result<Json<AuthResponse>, AppError> {
-    let client_id = app_state
-        .config
-        .google_client_id
-        .ok_or_else(|| AppError::BadRequest("Google OAuth not configured".to_string()))?;
-    let client_secret = app_state
-        .config
-        .google_client_secret
-        .ok_or_else(|| AppError::BadRequest("Google OAuth not configured".to_string()))?;
-
-    // Exchange code for token
-    let client = Client::new();
-    let token_response = client
-        .post("https://oauth2.googleapis.com/token")
-        .form(&[
-            ("client_id", client_id.as_str()),
-            ("client_secret", client_secret.as_str()),
-            ("code", &params.code),
-            ("grant_type", "authorization_code"),
-            ("redirect_uri", &app_state.config.google_redirect_uri),
-        ])
-        .send()
-        .await
-        .map_err(|_| AppError::BadRequest("Failed to exchange code for token".to_string()))?;
+    let (user_id, device_name) =
+        auth::decode_mfa_token(&payload.mfa_token, &app_state.config.jwt_secret)?;
 
-    let token_data: serde_json::Value = token_response
-        .json()
-        .await
-        .map_err(|_| AppError::BadRequest("Failed to parse token response".to_string()))?;
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&user_id)
+        .fetch_one(&app_state.db_pool)
+        .await?;
 
-    let access_token = token_data["access_token"]
-        .as_str()
-        .ok_or_else(|| AppError::BadRequest("No access token received".to_string()))?;
+    ensure_not_locked(&user)?;
 
-    // Get user info from Google
-    let user_response = client
-        .get("https://www.googleapis.com/oauth2/v2/userinfo")
-        .bearer_auth(access_token)
-        .send()
-        .await
-        .map_err(|_| AppError::BadRequest("Failed to get user info".to_string()))?;
+    if !mfa_handler::verify_second_factor(&app_state, &user_id, &payload.code).await? {
+        record_failed_login(&app_state, &user_id).await?;
+        AuditEvent::new(audit::LOGIN_FAILED)
+            .user(&user.id)
+            .target(&user.email)
+            .client(&client)
+            .metadata(json!({ "reason": "wrong_second_factor" }))
+            .record(&app_state)
+            .await?;
+        return Err(AppError::Unauthorized);
+    }
 
-    let google_user: GoogleUserInfo = user_response
-        .json()
+    if user.disabled {
+        return Err(AppError::Forbidden(
+            "this account has been disabled".to_string(),
+        ));
+    }
+
+    clear_failed_logins(&app_state.db_pool, &user).await?;
+
+    let tokens = auth::create_session(&app_state, &user.id, &client, device_name).await?;
+    AuditEvent::new(audit::LOGIN)
+        .user(&user.id)
+        .target(&user.email)
+        .client(&client)
+        .metadata(json!({ "mfa": true }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(AuthResponse { tokens, user }))
+}
+
+// Exchanges a refresh token for a new access token. Refresh tokens rotate on
+// every use; presenting an already-rotated token revokes the whole session.
+pub async fn refresh(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshPayload>,
+) -> Result<Json<SessionTokens>, AppError> {
+    let pool = &app_state.db_pool;
+    let presented_hash = hash_token(&payload.refresh_token);
+
+    let session: Option<(String, String)> = sqlx::query_as(
+        r#"
+        SELECT id, user_id FROM sessions
+        WHERE refresh_token_hash = $1 AND revoked_at IS NULL
+          AND expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now')
+        "#,
+    )
+    .bind(&presented_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    let Some((session_id, user_id)) = session else {
+        let reused = sqlx::query(
+            "UPDATE sessions SET revoked_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE previous_refresh_token_hash = $1 AND revoked_at IS NULL",
+        )
+        .bind(&presented_hash)
+        .execute(pool)
+        .await?;
+        if reused.rows_affected() > 0 {
+            tracing::warn!("refresh token reuse detected, session revoked");
+        }
+        return Err(AppError::Unauthorized);
+    };
+
+    let refresh_token = auth::generate_token(REFRESH_TOKEN_PREFIX);
+    let rotated = sqlx::query(
+        r#"
+        UPDATE sessions
+        SET previous_refresh_token_hash = refresh_token_hash,
+            refresh_token_hash = $1,
+            last_used_at = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+            ip_address = COALESCE($2, ip_address),
+            user_agent = COALESCE($3, user_agent)
+        WHERE id = $4 AND refresh_token_hash = $5
+        "#,
+    )
+    .bind(hash_token(&refresh_token))
+    .bind(&client.ip)
+    .bind(&client.user_agent)
+    .bind(&session_id)
+    .bind(&presented_hash)
+    .execute(pool)
+    .await?;
+
+    // Lost a race against a concurrent refresh with the same token.
+    if rotated.rows_affected() == 0 {
+        return Err(AppError::Unauthorized);
+    }
+
+    let token = auth::create_jwt(
+        user_id,
+        session_id,
+        &app_state.config.jwt_secret,
+        app_state.config.access_token_ttl_minutes,
+    )?;
+
+    Ok(Json(SessionTokens {
+        token,
+        refresh_token,
+    }))
+}
+
+pub async fn logout(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+) -> Result<Json<Value>, AppError> {
+    let pool = &app_state.db_pool;
+    let session_id = claims
+        .sid
+        .ok_or_else(|| AppError::BadRequest("no session to log out of".to_string()))?;
+
+    sqlx::query(
+        "UPDATE sessions SET revoked_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(&session_id)
+    .bind(&claims.sub)
+    .execute(pool)
+    .await?;
+
+    AuditEvent::new(audit::LOGOUT)
+        .user(&claims.sub)
+        .target(&session_id)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn list_sessions(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+) -> Result<Json<Vec<SessionResponse>>, AppError> {
+    let sessions = sqlx::query_as::<_, Session>(
+        r#"
+        SELECT * FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL
+          AND expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now')
+        ORDER BY last_used_at DESC
+        "#,
+    )
+    .bind(&claims.sub)
+    .fetch_all(&pool)
+    .await?;
+
+    let response = sessions
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: claims.sid.as_deref() == Some(session.id.as_str()),
+            session,
+        })
+        .collect();
+
+    Ok(Json(response))
+}
+
+pub async fn revoke_session(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(session_id): Path<String>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    let result = sqlx::query(
+        "UPDATE sessions SET revoked_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(&session_id)
+    .bind(&claims.sub)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::SESSION_REVOKED)
+        .user(&claims.sub)
+        .target(&session_id)
+        .client(&client)
+        .record(&app_state)
         .await
-        .map_err(|_| AppError::BadRequest("Failed to parse user info".to_string()))?;
+}
+
+pub async fn change_password(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Json(payload): Json<ChangePasswordPayload>,
+) -> Result<Json<Value>, AppError> {
+    let pool = &app_state.db_pool;
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(pool)
+        .await?;
 
-    // Check if user exists, create if not
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE google_id = $1 OR email = $2")
-        .bind(&google_user.id)
-        .bind(&google_user.email)
+    if user.password_hash.is_none() {
+        return Err(AppError::BadRequest(
+            "this account does not use a password".to_string(),
+        ));
+    }
+
+    let verified = reauthenticate(
+        &app_state,
+        &user,
+        &payload.current_password,
+        payload.code.as_deref(),
+        "change it",
+    )
+    .await?;
+    if !verified {
+        return Err(AppError::Forbidden("re-authentication failed".to_string()));
+    }
+
+    let new_hash = hash(&payload.new_password, DEFAULT_COST)?;
+    sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+        .bind(new_hash)
+        .bind(&user.id)
+        .execute(pool)
+        .await?;
+
+    auth::revoke_all_sessions(pool, &user.id, claims.sid.as_deref()).await?;
+    auth::revoke_api_tokens(pool, &user.id).await?;
+
+    AuditEvent::new(audit::PASSWORD_CHANGED)
+        .user(&user.id)
+        .target(&user.email)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn request_email_verification(
+    State(app_state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Value>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+
+    if user.email_verified {
+        return Err(AppError::BadRequest(
+            "email is already verified".to_string(),
+        ));
+    }
+
+    send_verification_email(&app_state, &user).await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn verify_email(
+    State(app_state): State<AppState>,
+    Json(payload): Json<EmailTokenPayload>,
+) -> Result<Json<User>, AppError> {
+    let user_id =
+        auth::consume_email_token(&app_state, &payload.token, EmailTokenPurpose::VerifyEmail)
+            .await?;
+
+    let user = sqlx::query_as::<_, User>(
+        "UPDATE users SET email_verified = true WHERE id = $1 RETURNING *",
+    )
+    .bind(user_id)
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    Ok(Json(user))
+}
+
+// Always reports success so the endpoint can't be used to probe for accounts.
+pub async fn request_password_reset(
+    State(app_state): State<AppState>,
+    Json(payload): Json<PasswordResetRequestPayload>,
+) -> Result<Json<Value>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+        .bind(payload.email.trim())
         .fetch_optional(&app_state.db_pool)
         .await?;
 
-    let user = match user {
-        Some(mut existing_user) => {
-            // Update Google ID if not set
-            if existing_user.google_id.is_none() {
-                sqlx::query("UPDATE users SET google_id = $1, avatar_url = $2 WHERE id = $3")
-                    .bind(&google_user.id)
-                    .bind(&google_user.picture)
-                    .bind(&existing_user.id)
-                    .execute(&app_state.db_pool)
-                    .await?;
-                existing_user.google_id = Some(google_user.id);
-                existing_user.avatar_url = google_user.picture;
+    if let Some(user) = user {
+        // Send in the background so response timing doesn't reveal whether the account exists.
+        tokio::spawn(async move {
+            let result = async {
+                let token = auth::issue_email_token(
+                    &app_state,
+                    &user.id,
+                    EmailTokenPurpose::PasswordReset,
+                    Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+                )
+                .await?;
+
+                app_state
+                    .mailer
+                    .send(Email {
+                        to: user.email.clone(),
+                        subject: "Reset your password".to_string(),
+                        body: format!(
+                            "Hi {},\n\nSomeone asked to reset the password of your neko.chat account. If it was you, open this link to choose a new one:\n\n{}/auth/reset-password?token={}\n\nThe link expires in {} minutes. If you didn't ask for this, you can ignore this email.\n",
+                            user.name, app_state.config.app_base_url, token, PASSWORD_RESET_TTL_MINUTES
+                        ),
+                    })
+                    .await
             }
-            existing_user
-        }
-        None => {
-            // Create new user
-            let user_id = Uuid::new_v4().to_string();
-            sqlx::query(
-                r#"
-                INSERT INTO users (id, email, name, google_id, avatar_url, role)
-                VALUES ($1, $2, $3, $4, $5, 'user')
-                "#,
-            )
-            .bind(&user_id)
-            .bind(&google_user.email)
-            .bind(&google_user.name)
-            .bind(&google_user.id)
-            .bind(&google_user.picture)
-            .execute(&app_state.db_pool)
+            .await;
+
+            if let Err(e) = result {
+                tracing::warn!(
+                    "failed to send password reset email to {}: {:?}",
+                    user.email,
+                    e
+                );
+            }
+        });
+    }
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn confirm_password_reset(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<PasswordResetConfirmPayload>,
+) -> Result<Json<Value>, AppError> {
+    if payload.new_password.is_empty() {
+        return Err(AppError::BadRequest("new password is required".to_string()));
+    }
+
+    let user_id =
+        auth::consume_email_token(&app_state, &payload.token, EmailTokenPurpose::PasswordReset)
             .await?;
 
-            sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-                .bind(user_id)
-                .fetch_one(&app_state.db_pool)
-                .await?
-        }
-    };
+    let new_hash = hash(&payload.new_password, DEFAULT_COST)?;
+    // Receiving the reset link also proves the address belongs to the user.
+    sqlx::query(
+        "UPDATE users SET password_hash = $1, email_verified = true, failed_login_count = 0, locked_until = NULL WHERE id = $2",
+    )
+        .bind(new_hash)
+        .bind(&user_id)
+        .execute(&app_state.db_pool)
+        .await?;
+
+    auth::revoke_all_sessions(&app_state.db_pool, &user_id, None).await?;
+    auth::revoke_api_tokens(&app_state.db_pool, &user_id).await?;
 
-    let token = auth::create_jwt(user.id.clone(), &app_state.config.jwt_secret)?;
+    AuditEvent::new(audit::PASSWORD_RESET)
+        .user(&user_id)
+        .client(&client)
+        .record(&app_state)
+        .await?;
 
-    Ok(Json(AuthResponse { token, user }))
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn get_me(claims: crate::auth::Claims) -> Result<Json<Value>, AppError> {
+    Ok(Json(json!({ "user_id": claims.sub })))
 }
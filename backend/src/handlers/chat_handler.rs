@@ -1,17 +1,92 @@
 use crate::{
-    auth::Claims,
+    audit::{self, AuditEvent},
+    auth::{Claims, ClientInfo},
     database::{Chat, Message},
     error::AppError,
-    handlers::settings_handler::SystemPrompt,
+    handlers::{key_handler, settings_handler::SystemPrompt},
+    llm::{routing::Route, GenerationParams},
+    AppState,
 };
 use axum::{
     extract::{Path, State},
     Json,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::SqlitePool;
 use uuid::Uuid;
 
+// What a user may do with a chat: owners manage it and who it's shared with,
+// editors post and edit messages, viewers only read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChatAccess {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl ChatAccess {
+    // Roles that can be given to collaborators; the owner is always the chat's user_id.
+    fn parse_member_role(role: &str) -> Result<Self, AppError> {
+        match role {
+            "viewer" => Ok(ChatAccess::Viewer),
+            "editor" => Ok(ChatAccess::Editor),
+            _ => Err(AppError::BadRequest(format!(
+                "unknown chat role '{}'. valid roles: viewer, editor",
+                role
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatAccess::Viewer => "viewer",
+            ChatAccess::Editor => "editor",
+            ChatAccess::Owner => "owner",
+        }
+    }
+}
+
+// Checks the user's access to a chat and returns the chat's owner, whose keys
+// pay for replies. Chats the user can't see are NotFound, so ids can't be
+// probed; a role below `required` is Forbidden.
+pub(crate) async fn require_chat_access(
+    pool: &SqlitePool,
+    chat_id: &str,
+    user_id: &str,
+    required: ChatAccess,
+) -> Result<String, AppError> {
+    let (owner_id, member_role): (String, Option<String>) = sqlx::query_as(
+        r#"
+        SELECT chats.user_id, m.role FROM chats
+        LEFT JOIN chat_members m ON m.chat_id = chats.id AND m.user_id = $2
+        WHERE chats.id = $1
+        "#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    let access = if owner_id == user_id {
+        ChatAccess::Owner
+    } else {
+        match member_role {
+            Some(role) => ChatAccess::parse_member_role(&role)?,
+            None => return Err(AppError::NotFound),
+        }
+    };
+
+    if access < required {
+        return Err(AppError::Forbidden(format!(
+            "this chat is shared with you as {}",
+            access.as_str()
+        )));
+    }
+    Ok(owner_id)
+}
+
 #[derive(Deserialize)]
 pub struct CreateChatPayload {
     title: String,
@@ -21,6 +96,9 @@ pub struct CreateChatPayload {
     is_branch: Option<bool>,
     parent_chat_id: Option<String>,
     branch_point_message_id: Option<String>,
+    api_key_id: Option<String>,
+    generation_params: Option<GenerationParams>,
+    fallback_chain: Option<Vec<Route>>,
 }
 
 #[derive(Deserialize)]
@@ -30,6 +108,12 @@ pub struct UpdateChatPayload {
     provider: Option<String>,
     model: Option<String>,
     pinned: Option<bool>,
+    // An empty string unpins the chat from its key.
+    api_key_id: Option<String>,
+    // An empty object goes back to the owner's defaults.
+    generation_params: Option<GenerationParams>,
+    // An empty list removes the fallbacks.
+    fallback_chain: Option<Vec<Route>>,
 }
 
 #[derive(Deserialize)]
@@ -48,6 +132,40 @@ pub struct UpdateMessagePayload {
     content: String,
 }
 
+#[derive(Deserialize)]
+pub struct AddChatMemberPayload {
+    email: String,
+    role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateChatMemberPayload {
+    role: String,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMemberResponse {
+    user_id: String,
+    name: String,
+    email: String,
+    role: String,
+    created_at: String,
+}
+
+// The owner first, then collaborators in the order they were invited.
+const CHAT_MEMBERS_SELECT: &str = r#"
+    SELECT * FROM (
+        SELECT users.id AS user_id, users.name, users.email, 'owner' AS role, chats.created_at, 0 AS sort
+        FROM chats JOIN users ON users.id = chats.user_id
+        WHERE chats.id = $1
+        UNION ALL
+        SELECT users.id, users.name, users.email, m.role, m.created_at, 1
+        FROM chat_members m JOIN users ON users.id = m.user_id
+        WHERE m.chat_id = $1
+    )
+"#;
+
 pub async fn create_chat(
     State(pool): State<SqlitePool>,
     claims: Claims,
@@ -82,10 +200,19 @@ pub async fn create_chat(
         }
     };
 
+    let provider = payload.provider.unwrap_or_else(|| "openai".to_string());
+    if let Some(key_id) = &payload.api_key_id {
+        key_handler::ensure_key_usable(&pool, &user_id, &provider, key_id).await?;
+    }
+    let generation_params = payload.generation_params.unwrap_or_default();
+    generation_params.validate()?;
+    let fallback_chain = payload.fallback_chain.unwrap_or_default();
+    Route::validate_chain(&fallback_chain)?;
+
     let chat = sqlx::query_as::<_, Chat>(
         r#"
-        INSERT INTO chats (id, user_id, title, system_prompt, provider, model, pinned, is_branch, parent_chat_id, branch_point_message_id)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        INSERT INTO chats (id, user_id, title, system_prompt, provider, model, pinned, is_branch, parent_chat_id, branch_point_message_id, api_key_id, generation_params, fallback_chain)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
         RETURNING *
         "#,
     )
@@ -93,12 +220,15 @@ pub async fn create_chat(
     .bind(user_id)
     .bind(payload.title)
     .bind(final_system_prompt)
-    .bind(payload.provider.unwrap_or_else(|| "openai".to_string()))
+    .bind(provider)
     .bind(payload.model.unwrap_or_else(|| "gpt-4o".to_string()))
     .bind(false) // pinned defaults to false
     .bind(payload.is_branch.unwrap_or(false))
     .bind(payload.parent_chat_id)
     .bind(payload.branch_point_message_id)
+    .bind(payload.api_key_id)
+    .bind(generation_params.to_column())
+    .bind(Route::chain_to_column(&fallback_chain))
     .fetch_one(&pool)
     .await?;
 
@@ -110,8 +240,14 @@ pub async fn list_chats(
     claims: Claims,
 ) -> Result<Json<Vec<Chat>>, AppError> {
     let user_id = claims.sub;
+    // Own chats and chats shared with the user
     let chats = sqlx::query_as::<_, Chat>(
-        "SELECT * FROM chats WHERE user_id = $1 ORDER BY created_at DESC",
+        r#"
+        SELECT chats.*, COALESCE(m.role, 'owner') AS access FROM chats
+        LEFT JOIN chat_members m ON m.chat_id = chats.id AND m.user_id = $1
+        WHERE chats.user_id = $1 OR m.user_id IS NOT NULL
+        ORDER BY chats.created_at DESC
+        "#,
     )
     .bind(user_id)
     .fetch_all(&pool)
@@ -125,15 +261,7 @@ pub async fn get_messages(
     Path(chat_id): Path<String>,
 ) -> Result<Json<Vec<Message>>, AppError> {
     let user_id = claims.sub;
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Viewer).await?;
 
     let messages = sqlx::query_as::<_, Message>(
         "SELECT * FROM messages WHERE chat_id = $1 ORDER BY created_at ASC",
@@ -152,24 +280,15 @@ pub async fn delete_chat(
 ) -> Result<Json<()>, AppError> {
     let user_id = claims.sub;
 
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Owner).await?;
 
     // Get all child chats (branches) that reference this chat as parent
-    let child_chats: Vec<(String,)> = sqlx::query_as(
-        "SELECT id FROM chats WHERE parent_chat_id = $1 AND user_id = $2"
-    )
-    .bind(&chat_id)
-    .bind(&user_id)
-    .fetch_all(&pool)
-    .await?;
+    let child_chats: Vec<(String,)> =
+        sqlx::query_as("SELECT id FROM chats WHERE parent_chat_id = $1 AND user_id = $2")
+            .bind(&chat_id)
+            .bind(&user_id)
+            .fetch_all(&pool)
+            .await?;
 
     // Recursively delete all child chats first
     for (child_id,) in child_chats {
@@ -178,7 +297,7 @@ pub async fn delete_chat(
             .bind(&child_id)
             .execute(&pool)
             .await?;
-        
+
         // Delete child chat
         sqlx::query("DELETE FROM chats WHERE id = $1")
             .bind(&child_id)
@@ -209,15 +328,17 @@ pub async fn update_chat(
 ) -> Result<Json<Chat>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
+    // Only the owner changes the chat itself
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Owner).await?;
+    let chat_provider: String = sqlx::query_scalar("SELECT provider FROM chats WHERE id = $1")
         .bind(&chat_id)
         .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
+        .await?;
 
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
+    // A pinned key has to belong to the provider the chat ends up with
+    if let Some(key_id) = payload.api_key_id.as_deref().filter(|id| !id.is_empty()) {
+        let provider = payload.provider.as_deref().unwrap_or(&chat_provider);
+        key_handler::ensure_key_usable(&pool, &user_id, provider, key_id).await?;
     }
 
     // Build update query dynamically based on provided fields
@@ -255,6 +376,26 @@ pub async fn update_chat(
         param_index += 1;
     }
 
+    if let Some(api_key_id) = payload.api_key_id {
+        query_parts.push(format!("api_key_id = NULLIF(${}, '')", param_index));
+        params.push(api_key_id);
+        param_index += 1;
+    }
+
+    if let Some(generation_params) = payload.generation_params {
+        generation_params.validate()?;
+        query_parts.push(format!("generation_params = NULLIF(${}, '')", param_index));
+        params.push(generation_params.to_column().unwrap_or_default());
+        param_index += 1;
+    }
+
+    if let Some(fallback_chain) = payload.fallback_chain {
+        Route::validate_chain(&fallback_chain)?;
+        query_parts.push(format!("fallback_chain = NULLIF(${}, '')", param_index));
+        params.push(Route::chain_to_column(&fallback_chain).unwrap_or_default());
+        param_index += 1;
+    }
+
     if query_parts.is_empty() {
         return Err(AppError::BadRequest("No fields to update".to_string()));
     }
@@ -284,16 +425,7 @@ pub async fn bulk_insert_messages(
 ) -> Result<Json<Vec<Message>>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await?;
 
     let mut inserted_messages = Vec::new();
 
@@ -302,12 +434,13 @@ pub async fn bulk_insert_messages(
         let message_id = Uuid::new_v4().to_string();
 
         let message = sqlx::query_as::<_, Message>(
-            "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, $3, $4) RETURNING *",
+            "INSERT INTO messages (id, chat_id, role, content, user_id) VALUES ($1, $2, $3, $4, CASE WHEN $3 = 'user' THEN $5 END) RETURNING *",
         )
         .bind(message_id)
         .bind(&chat_id)
         .bind(message_payload.role)
         .bind(message_payload.content)
+        .bind(&user_id)
         .fetch_one(&pool)
         .await?;
 
@@ -325,16 +458,7 @@ pub async fn update_message(
 ) -> Result<Json<Message>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await?;
 
     // Update the message content
     let updated_message = sqlx::query_as::<_, Message>(
@@ -357,30 +481,20 @@ pub async fn delete_message_and_subsequent(
 ) -> Result<Json<Vec<String>>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await?;
 
     // Get the message being deleted to find its timestamp
-    let target_message: (String,) = sqlx::query_as(
-        "SELECT created_at FROM messages WHERE id = $1 AND chat_id = $2"
-    )
-    .bind(&message_id)
-    .bind(&chat_id)
-    .fetch_one(&pool)
-    .await
-    .map_err(|_| AppError::NotFound)?;
+    let target_message: (String,) =
+        sqlx::query_as("SELECT created_at FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(&message_id)
+            .bind(&chat_id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|_| AppError::NotFound)?;
 
     // Get all messages from this timestamp onwards (including the target message)
     let messages_to_delete: Vec<(String,)> = sqlx::query_as(
-        "SELECT id FROM messages WHERE chat_id = $1 AND created_at >= $2 ORDER BY created_at ASC"
+        "SELECT id FROM messages WHERE chat_id = $1 AND created_at >= $2 ORDER BY created_at ASC",
     )
     .bind(&chat_id)
     .bind(&target_message.0)
@@ -390,13 +504,11 @@ pub async fn delete_message_and_subsequent(
     let deleted_ids: Vec<String> = messages_to_delete.iter().map(|(id,)| id.clone()).collect();
 
     // Delete all messages from the target timestamp onwards
-    sqlx::query(
-        "DELETE FROM messages WHERE chat_id = $1 AND created_at >= $2"
-    )
-    .bind(&chat_id)
-    .bind(&target_message.0)
-    .execute(&pool)
-    .await?;
+    sqlx::query("DELETE FROM messages WHERE chat_id = $1 AND created_at >= $2")
+        .bind(&chat_id)
+        .bind(&target_message.0)
+        .execute(&pool)
+        .await?;
 
     Ok(Json(deleted_ids))
 }
@@ -408,30 +520,20 @@ pub async fn delete_subsequent_messages(
 ) -> Result<Json<Vec<String>>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await?;
 
     // Get the message timestamp
-    let target_message: (String,) = sqlx::query_as(
-        "SELECT created_at FROM messages WHERE id = $1 AND chat_id = $2"
-    )
-    .bind(&message_id)
-    .bind(&chat_id)
-    .fetch_one(&pool)
-    .await
-    .map_err(|_| AppError::NotFound)?;
+    let target_message: (String,) =
+        sqlx::query_as("SELECT created_at FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(&message_id)
+            .bind(&chat_id)
+            .fetch_one(&pool)
+            .await
+            .map_err(|_| AppError::NotFound)?;
 
     // Get all messages AFTER this timestamp (excluding the target message)
     let messages_to_delete: Vec<(String,)> = sqlx::query_as(
-        "SELECT id FROM messages WHERE chat_id = $1 AND created_at > $2 ORDER BY created_at ASC"
+        "SELECT id FROM messages WHERE chat_id = $1 AND created_at > $2 ORDER BY created_at ASC",
     )
     .bind(&chat_id)
     .bind(&target_message.0)
@@ -441,13 +543,11 @@ pub async fn delete_subsequent_messages(
     let deleted_ids: Vec<String> = messages_to_delete.iter().map(|(id,)| id.clone()).collect();
 
     // Delete all messages AFTER the target timestamp (not including the target message)
-    sqlx::query(
-        "DELETE FROM messages WHERE chat_id = $1 AND created_at > $2"
-    )
-    .bind(&chat_id)
-    .bind(&target_message.0)
-    .execute(&pool)
-    .await?;
+    sqlx::query("DELETE FROM messages WHERE chat_id = $1 AND created_at > $2")
+        .bind(&chat_id)
+        .bind(&target_message.0)
+        .execute(&pool)
+        .await?;
 
     Ok(Json(deleted_ids))
 }
@@ -459,25 +559,15 @@ pub async fn delete_single_message(
 ) -> Result<Json<()>, AppError> {
     let user_id = claims.sub;
 
-    // Verify the user owns this chat
-    let chat_owner: (String,) = sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-        .bind(&chat_id)
-        .fetch_one(&pool)
-        .await
-        .map_err(|_| AppError::NotFound)?;
-
-    if chat_owner.0 != user_id {
-        return Err(AppError::Unauthorized);
-    }
+    require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await?;
 
     // Verify the message exists in this chat
-    let message_exists: Result<(String,), sqlx::Error> = sqlx::query_as(
-        "SELECT id FROM messages WHERE id = $1 AND chat_id = $2"
-    )
-    .bind(&message_id)
-    .bind(&chat_id)
-    .fetch_one(&pool)
-    .await;
+    let message_exists: Result<(String,), sqlx::Error> =
+        sqlx::query_as("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+            .bind(&message_id)
+            .bind(&chat_id)
+            .fetch_one(&pool)
+            .await;
 
     if message_exists.is_err() {
         return Err(AppError::NotFound);
@@ -492,3 +582,171 @@ pub async fn delete_single_message(
 
     Ok(Json(()))
 }
+
+pub async fn list_chat_members(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(chat_id): Path<String>,
+) -> Result<Json<Vec<ChatMemberResponse>>, AppError> {
+    require_chat_access(&pool, &chat_id, &claims.sub, ChatAccess::Viewer).await?;
+
+    let members = sqlx::query_as::<_, ChatMemberResponse>(&format!(
+        "{} ORDER BY sort, created_at",
+        CHAT_MEMBERS_SELECT
+    ))
+    .bind(&chat_id)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(members))
+}
+
+pub async fn add_chat_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(chat_id): Path<String>,
+    Json(payload): Json<AddChatMemberPayload>,
+) -> Result<Json<ChatMemberResponse>, AppError> {
+    let pool = &app_state.db_pool;
+    let owner_id = require_chat_access(pool, &chat_id, &claims.sub, ChatAccess::Owner).await?;
+    let role = ChatAccess::parse_member_role(payload.role.as_deref().unwrap_or("viewer"))?;
+
+    // Only people the owner already works with can be found by email, and
+    // everyone else gets the same answer as an unknown address, so the
+    // endpoint can't be used to probe which emails have accounts.
+    let user_id = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT users.id FROM users
+        WHERE users.email = $1 AND (
+            users.id = $2
+            OR users.id IN (
+                SELECT theirs.user_id FROM workspace_members mine
+                JOIN workspace_members theirs ON theirs.workspace_id = mine.workspace_id
+                WHERE mine.user_id = $2
+            )
+            OR users.id IN (
+                SELECT m.user_id FROM chat_members m JOIN chats ON chats.id = m.chat_id
+                WHERE chats.user_id = $2
+                UNION
+                SELECT chats.user_id FROM chat_members m JOIN chats ON chats.id = m.chat_id
+                WHERE m.user_id = $2
+            )
+        )
+        "#,
+    )
+    .bind(payload.email.trim())
+    .bind(&claims.sub)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| {
+        AppError::BadRequest(
+            "chats can only be shared with members of your workspaces or chats".to_string(),
+        )
+    })?;
+    if user_id == owner_id {
+        return Err(AppError::BadRequest(
+            "you already own this chat".to_string(),
+        ));
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO chat_members (chat_id, user_id, role, invited_by) VALUES ($1, $2, $3, $4)
+        ON CONFLICT(chat_id, user_id) DO UPDATE SET role = excluded.role
+        "#,
+    )
+    .bind(&chat_id)
+    .bind(&user_id)
+    .bind(role.as_str())
+    .bind(&claims.sub)
+    .execute(pool)
+    .await?;
+
+    AuditEvent::new(audit::CHAT_SHARED)
+        .user(&claims.sub)
+        .target(&chat_id)
+        .client(&client)
+        .metadata(json!({ "user_id": user_id, "role": role.as_str() }))
+        .record(&app_state)
+        .await?;
+
+    let member = sqlx::query_as::<_, ChatMemberResponse>(&format!(
+        "{} WHERE user_id = $2",
+        CHAT_MEMBERS_SELECT
+    ))
+    .bind(&chat_id)
+    .bind(&user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Json(member))
+}
+
+pub async fn update_chat_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path((chat_id, member_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateChatMemberPayload>,
+) -> Result<Json<()>, AppError> {
+    let pool = &app_state.db_pool;
+    require_chat_access(pool, &chat_id, &claims.sub, ChatAccess::Owner).await?;
+    let role = ChatAccess::parse_member_role(&payload.role)?;
+
+    let result =
+        sqlx::query("UPDATE chat_members SET role = $1 WHERE chat_id = $2 AND user_id = $3")
+            .bind(role.as_str())
+            .bind(&chat_id)
+            .bind(&member_id)
+            .execute(pool)
+            .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::CHAT_SHARE_UPDATED)
+        .user(&claims.sub)
+        .target(&chat_id)
+        .client(&client)
+        .metadata(json!({ "user_id": member_id, "role": role.as_str() }))
+        .record(&app_state)
+        .await?;
+    Ok(Json(()))
+}
+
+// The owner removes collaborators; collaborators can also leave on their own.
+pub async fn remove_chat_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path((chat_id, member_id)): Path<(String, String)>,
+) -> Result<Json<()>, AppError> {
+    let pool = &app_state.db_pool;
+    let required = if member_id == claims.sub {
+        ChatAccess::Viewer
+    } else {
+        ChatAccess::Owner
+    };
+    require_chat_access(pool, &chat_id, &claims.sub, required).await?;
+
+    let result = sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+        .bind(&chat_id)
+        .bind(&member_id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::CHAT_UNSHARED)
+        .user(&claims.sub)
+        .target(&chat_id)
+        .client(&client)
+        .metadata(json!({ "user_id": member_id }))
+        .record(&app_state)
+        .await?;
+    Ok(Json(()))
+}
@@ -1,59 +1,502 @@
-use crate::{auth::Claims, database::UserApiKey, error::AppError, AppState};
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{Claims, ClientInfo},
+    crypto::Keyring,
+    database::{User, UserApiKey},
+    error::AppError,
+    handlers::auth_handler,
+    llm::validate_api_key,
+    AppState,
+};
 use axum::{
     extract::{Path, State},
     Json,
 };
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+pub(crate) const DEFAULT_LABEL: &str = "default";
+
+// Where a key used for a request comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum KeySource {
+    User,
+    Workspace(String),
+    // Set in the server's environment; not stored, so there is nothing to record.
+    Server,
+}
+
+impl KeySource {
+    fn table(&self) -> Option<&'static str> {
+        match self {
+            KeySource::User => Some("user_api_keys"),
+            KeySource::Workspace(_) => Some("workspace_api_keys"),
+            KeySource::Server => None,
+        }
+    }
+
+    pub(crate) fn workspace_id(&self) -> Option<&str> {
+        match self {
+            KeySource::Workspace(id) => Some(id),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Deserialize)]
 pub struct AddKeyPayload {
     provider: String,
+    // Names the key among the user's keys for this provider. Adding a key
+    // under an existing label replaces that key.
+    label: Option<String>,
     api_key: String,
+    // Checks the key against the provider before saving. Off unless asked
+    // for, so clients that predate it keep saving keys without a network call.
+    #[serde(default)]
+    validate: bool,
 }
 
+#[derive(Deserialize)]
+pub struct UpdateKeyPayload {
+    label: Option<String>,
+    // Lower comes first when picking a key and when failing over.
+    priority: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct TestKeyPayload {
+    provider: String,
+    // Tests this key without storing it; a stored key is tested otherwise.
+    api_key: Option<String>,
+    // Which stored key to test; the provider's first key by default.
+    key_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RevealKeyPayload {
+    password: String,
+    // Required when the account has two-factor authentication enabled.
+    code: Option<String>,
+    // Which key to reveal; the provider's first key by default.
+    key_id: Option<String>,
+}
+
+// Stored keys are write-only: responses only carry the last few characters.
 #[derive(Serialize)]
 pub struct ApiKeyResponse {
+    id: String,
     provider: String,
+    label: String,
+    priority: i64,
+    key_hint: Option<String>,
     created_at: String,
+    last_validated_at: Option<String>,
+    last_error: Option<String>,
+    last_used_at: Option<String>,
+}
+
+impl From<UserApiKey> for ApiKeyResponse {
+    fn from(key: UserApiKey) -> Self {
+        Self {
+            id: key.id,
+            provider: key.provider,
+            label: key.label,
+            priority: key.priority,
+            key_hint: key.key_hint,
+            created_at: key.created_at,
+            last_validated_at: key.last_validated_at,
+            last_error: key.last_error,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AvailableProviderResponse {
+    provider: String,
+    sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct TestKeyResponse {
+    provider: String,
+    key_id: Option<String>,
+    valid: bool,
+    error: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct DecryptedApiKeyResponse {
+    id: String,
     provider: String,
+    label: String,
     api_key: String,
     created_at: String,
 }
 
+// Last four characters of a key, enough to tell keys apart. Short values get
+// no hint so most of the secret isn't given away.
+pub(crate) fn key_hint(api_key: &str) -> Option<String> {
+    let chars: Vec<char> = api_key.trim().chars().collect();
+    if chars.len() < 12 {
+        return None;
+    }
+    Some(chars[chars.len() - 4..].iter().collect())
+}
+
+pub(crate) fn normalize_label(label: Option<&str>) -> Result<String, AppError> {
+    let label = label
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .unwrap_or(DEFAULT_LABEL);
+    if label.chars().count() > 64 {
+        return Err(AppError::BadRequest(
+            "key label must be at most 64 characters".to_string(),
+        ));
+    }
+    Ok(label.to_string())
+}
+
+// Keys stored before hints existed get one computed once at startup.
+pub async fn backfill_key_hints(
+    pool: &sqlx::SqlitePool,
+    keyring: &Keyring,
+) -> Result<u64, AppError> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        "SELECT id, encrypted_key FROM user_api_keys WHERE key_hint IS NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut updated = 0;
+    for (key_id, encrypted_key) in rows {
+        let Some(hint) = keyring
+            .decrypt(&encrypted_key)
+            .ok()
+            .and_then(|k| key_hint(&k))
+        else {
+            continue;
+        };
+        sqlx::query("UPDATE user_api_keys SET key_hint = $1 WHERE id = $2")
+            .bind(hint)
+            .bind(&key_id)
+            .execute(pool)
+            .await?;
+        updated += 1;
+    }
+
+    Ok(updated)
+}
+
+// Looks up one of the user's keys: the given one, or the provider's first.
+async fn find_key(
+    pool: &sqlx::SqlitePool,
+    user_id: &str,
+    provider: &str,
+    key_id: Option<&str>,
+) -> Result<UserApiKey, AppError> {
+    sqlx::query_as::<_, UserApiKey>(
+        r#"
+        SELECT * FROM user_api_keys
+        WHERE user_id = $1 AND provider = $2 AND ($3 IS NULL OR id = $3)
+        ORDER BY priority ASC, created_at ASC
+        LIMIT 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(key_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound)
+}
+
+// Used when a chat or model is pinned to a key: it has to be one of the
+// user's own keys or a key of one of their workspaces, for the same provider.
+pub(crate) async fn ensure_key_usable(
+    pool: &sqlx::SqlitePool,
+    user_id: &str,
+    provider: &str,
+    key_id: &str,
+) -> Result<(), AppError> {
+    let usable = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM user_api_keys WHERE user_id = $1 AND provider = $2 AND id = $3
+            UNION ALL
+            SELECT 1 FROM workspace_api_keys k
+            JOIN workspace_members m ON m.workspace_id = k.workspace_id
+            WHERE m.user_id = $1 AND k.provider = $2 AND k.id = $3
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(key_id)
+    .fetch_one(pool)
+    .await?;
+
+    if usable {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!(
+            "no {} key with id '{}'",
+            provider, key_id
+        )))
+    }
+}
+
+// Provider rejections are reported as text the settings page can show as-is.
+pub(crate) fn validation_error(error: &AppError) -> String {
+    match error {
+        AppError::LLMProviderError { message, .. } => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+// A 401/403 from the provider means the key itself is bad (revoked, expired,
+// out of credit on some providers); anything else says nothing about the key.
+fn is_key_rejection(error: &AppError) -> bool {
+    matches!(
+        error,
+        AppError::LLMProviderError {
+            status_code: Some(401 | 403),
+            ..
+        }
+    )
+}
+
+// Records the outcome of a model-listing check. Outages and rate limits say
+// nothing about the key, so only client errors mark it broken (Gemini answers
+// a bad key with a 400).
+pub(crate) async fn record_key_validation(
+    pool: &sqlx::SqlitePool,
+    source: &KeySource,
+    key_id: &str,
+    outcome: Result<(), &AppError>,
+) -> Result<(), AppError> {
+    let Some(table) = source.table() else {
+        return Ok(());
+    };
+    match outcome {
+        Ok(()) => {
+            sqlx::query(&format!(
+                "UPDATE {} SET last_validated_at = strftime('%Y-%m-%d %H:%M:%f', 'now'), last_error = NULL WHERE id = $1",
+                table
+            ))
+            .bind(key_id)
+            .execute(pool)
+            .await?;
+        }
+        Err(
+            e @ AppError::LLMProviderError {
+                status_code: Some(400..=428 | 430..=499),
+                ..
+            },
+        ) => {
+            sqlx::query(&format!(
+                "UPDATE {} SET last_error = $1 WHERE id = $2",
+                table
+            ))
+            .bind(validation_error(e))
+            .bind(key_id)
+            .execute(pool)
+            .await?;
+        }
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+// Called when a generation request fails; only marks the key broken when the
+// provider rejected the key itself.
+pub(crate) async fn record_key_failure(
+    pool: &sqlx::SqlitePool,
+    source: &KeySource,
+    key_id: &str,
+    error: &AppError,
+) {
+    let Some(table) = source.table().filter(|_| is_key_rejection(error)) else {
+        return;
+    };
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET last_error = $1 WHERE id = $2",
+        table
+    ))
+    .bind(validation_error(error))
+    .bind(key_id)
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::warn!("failed to record api key error: {}", e);
+    }
+}
+
+pub(crate) async fn mark_key_used(pool: &sqlx::SqlitePool, source: &KeySource, key_id: &str) {
+    let Some(table) = source.table() else {
+        return;
+    };
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET last_used_at = strftime('%Y-%m-%d %H:%M:%f', 'now') WHERE id = $1",
+        table
+    ))
+    .bind(key_id)
+    .execute(pool)
+    .await;
+    if let Err(e) = result {
+        tracing::warn!("failed to record api key use: {}", e);
+    }
+}
+
 pub async fn add_key(
     State(app_state): State<AppState>,
     claims: Claims,
+    client: ClientInfo,
     Json(payload): Json<AddKeyPayload>,
 ) -> Result<Json<ApiKeyResponse>, AppError> {
     let user_id = claims.sub;
-    let mc = new_magic_crypt!(&app_state.config.encryption_key, 256);
-    let encrypted_key = mc.encrypt_str_to_base64(&payload.api_key);
+    let api_key = payload.api_key.trim();
+    let label = normalize_label(payload.label.as_deref())?;
+
+    if payload.validate {
+        if let Err(e) = validate_api_key(&payload.provider, api_key).await {
+            return Err(AppError::BadRequest(format!(
+                "{} rejected the key: {}",
+                payload.provider,
+                validation_error(&e)
+            )));
+        }
+    }
+
+    let encrypted_key = app_state.keyring.encrypt(api_key);
 
-    // Use UPSERT logic for SQLite
+    // Replacing a key under the same label keeps its id and position, so chats
+    // and models pinned to it keep working.
     let key_record = sqlx::query_as::<_, UserApiKey>(
         r#"
-        INSERT INTO user_api_keys (user_id, provider, encrypted_key)
-        VALUES ($1, $2, $3)
-        ON CONFLICT(user_id, provider) DO UPDATE SET
+        INSERT INTO user_api_keys (id, user_id, provider, label, priority, encrypted_key, key_hint, last_validated_at)
+        VALUES (
+            $1, $2, $3, $4,
+            (SELECT COALESCE(MAX(priority) + 1, 0) FROM user_api_keys WHERE user_id = $2 AND provider = $3),
+            $5, $6,
+            CASE WHEN $7 THEN strftime('%Y-%m-%d %H:%M:%f', 'now') END
+        )
+        ON CONFLICT(user_id, provider, label) DO UPDATE SET
             encrypted_key = excluded.encrypted_key,
-            created_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+            key_hint = excluded.key_hint,
+            created_at = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+            last_validated_at = excluded.last_validated_at,
+            last_error = NULL,
+            last_used_at = NULL
         RETURNING *
         "#,
     )
-    .bind(user_id)
+    .bind(Uuid::new_v4().to_string())
+    .bind(&user_id)
     .bind(payload.provider)
+    .bind(label)
     .bind(encrypted_key)
+    .bind(key_hint(api_key))
+    .bind(payload.validate)
     .fetch_one(&app_state.db_pool)
     .await?;
 
-    Ok(Json(ApiKeyResponse {
-        provider: key_record.provider,
-        created_at: key_record.created_at,
+    AuditEvent::new(audit::API_KEY_ADDED)
+        .user(&user_id)
+        .target(&key_record.provider)
+        .client(&client)
+        .metadata(json!({
+            "key_id": key_record.id,
+            "label": key_record.label,
+            "key_hint": key_record.key_hint,
+        }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(key_record.into()))
+}
+
+pub async fn update_key(
+    State(pool): State<sqlx::SqlitePool>,
+    claims: Claims,
+    Path((provider, key_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateKeyPayload>,
+) -> Result<Json<ApiKeyResponse>, AppError> {
+    let label = match payload.label.as_deref() {
+        Some(label) => Some(normalize_label(Some(label))?),
+        None => None,
+    };
+
+    let key_record = sqlx::query_as::<_, UserApiKey>(
+        r#"
+        UPDATE user_api_keys
+        SET label = COALESCE($1, label),
+            priority = COALESCE($2, priority)
+        WHERE id = $3 AND user_id = $4 AND provider = $5
+        RETURNING *
+        "#,
+    )
+    .bind(label)
+    .bind(payload.priority)
+    .bind(&key_id)
+    .bind(&claims.sub)
+    .bind(&provider)
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_err) if db_err.message().contains("UNIQUE") => {
+            AppError::BadRequest(format!(
+                "you already have a {} key with that label",
+                provider
+            ))
+        }
+        other => other.into(),
+    })?
+    .ok_or(AppError::NotFound)?;
+
+    Ok(Json(key_record.into()))
+}
+
+// Checks a key against the provider. A key in the body is tested as-is and not
+// stored; otherwise a stored key is tested and its health fields updated.
+pub async fn test_key(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    Json(payload): Json<TestKeyPayload>,
+) -> Result<Json<TestKeyResponse>, AppError> {
+    let (key_id, result) = match payload.api_key.as_deref() {
+        Some(api_key) => (
+            None,
+            validate_api_key(&payload.provider, api_key.trim()).await,
+        ),
+        None => {
+            let key = find_key(
+                &app_state.db_pool,
+                &claims.sub,
+                &payload.provider,
+                payload.key_id.as_deref(),
+            )
+            .await?;
+            let api_key = app_state.keyring.decrypt(&key.encrypted_key)?;
+            let result = validate_api_key(&payload.provider, &api_key).await;
+            record_key_validation(
+                &app_state.db_pool,
+                &KeySource::User,
+                &key.id,
+                result.as_ref().map(|_| ()),
+            )
+            .await?;
+            (Some(key.id), result)
+        }
+    };
+
+    Ok(Json(TestKeyResponse {
+        provider: payload.provider,
+        key_id,
+        valid: result.is_ok(),
+        error: result.err().map(|e| validation_error(&e)),
     }))
 }
 
@@ -63,63 +506,238 @@ pub async fn list_keys(
 ) -> Result<Json<Vec<ApiKeyResponse>>, AppError> {
     let user_id = claims.sub;
     let keys = sqlx::query_as::<_, UserApiKey>(
-        "SELECT user_id, provider, encrypted_key, created_at FROM user_api_keys WHERE user_id = $1",
+        "SELECT * FROM user_api_keys WHERE user_id = $1 ORDER BY provider ASC, priority ASC, created_at ASC",
     )
     .bind(user_id)
     .fetch_all(&pool)
     .await?;
 
-    let response = keys
-        .into_iter()
-        .map(|k| ApiKeyResponse {
-            provider: k.provider,
-            created_at: k.created_at,
-        })
-        .collect();
+    Ok(Json(keys.into_iter().map(ApiKeyResponse::from).collect()))
+}
+
+// Providers the user can chat with and where their keys come from ("user",
+// "workspace" or "server"), so the app can offer models without a personal key.
+pub async fn list_available_providers(
+    State(app_state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<AvailableProviderResponse>>, AppError> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        r#"
+        SELECT DISTINCT provider, 'user' FROM user_api_keys WHERE user_id = $1
+        UNION
+        SELECT DISTINCT k.provider, 'workspace' FROM workspace_api_keys k
+        JOIN workspace_members m ON m.workspace_id = k.workspace_id
+        WHERE m.user_id = $1
+        "#,
+    )
+    .bind(&claims.sub)
+    .fetch_all(&app_state.db_pool)
+    .await?;
+
+    let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (provider, source) in rows {
+        providers.entry(provider).or_default().push(source);
+    }
+    for provider in app_state.config.server_api_keys.keys() {
+        providers
+            .entry(provider.clone())
+            .or_default()
+            .push("server".to_string());
+    }
 
-    Ok(Json(response))
+    Ok(Json(
+        providers
+            .into_iter()
+            .map(|(provider, mut sources)| {
+                // user, workspace, server: the order keys are tried in
+                sources
+                    .sort_by_key(|s| ["user", "workspace", "server"].iter().position(|o| o == s));
+                AvailableProviderResponse { provider, sources }
+            })
+            .collect(),
+    ))
 }
 
+// Models admins allowed for the provider's server-wide key. Empty means the
+// key isn't restricted.
+pub(crate) async fn server_key_models(
+    pool: &sqlx::SqlitePool,
+    provider: &str,
+) -> Result<Vec<String>, AppError> {
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT model_id FROM server_key_models WHERE provider = $1 ORDER BY model_id",
+    )
+    .bind(provider)
+    .fetch_all(pool)
+    .await?)
+}
+
+pub(crate) async fn server_key_allows(
+    pool: &sqlx::SqlitePool,
+    provider: &str,
+    model: &str,
+) -> Result<bool, AppError> {
+    let allowed = server_key_models(pool, provider).await?;
+    Ok(allowed.is_empty() || allowed.iter().any(|m| m == model))
+}
+
+// Chats and models pinned to a key the user can no longer use (deleted, or
+// from a workspace they left) fall back to the provider's keys.
+pub(crate) async fn unpin_unusable_keys(
+    conn: &mut sqlx::SqliteConnection,
+    user_id: &str,
+) -> Result<(), AppError> {
+    for table in ["chats", "user_models"] {
+        sqlx::query(&format!(
+            r#"
+            UPDATE {} SET api_key_id = NULL
+            WHERE user_id = $1 AND api_key_id IS NOT NULL AND api_key_id NOT IN (
+                SELECT id FROM user_api_keys WHERE user_id = $1
+                UNION
+                SELECT k.id FROM workspace_api_keys k
+                JOIN workspace_members m ON m.workspace_id = k.workspace_id
+                WHERE m.user_id = $1
+            )
+            "#,
+            table
+        ))
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+// Deletes every key stored for the provider.
 pub async fn delete_key(
-    State(pool): State<sqlx::SqlitePool>,
+    State(app_state): State<AppState>,
     claims: Claims,
+    client: ClientInfo,
     Path(provider): Path<String>,
 ) -> Result<(), AppError> {
     let user_id = claims.sub;
-    let result = sqlx::query("DELETE FROM user_api_keys WHERE user_id = $1 AND provider = $2")
-        .bind(user_id)
-        .bind(provider)
-        .execute(&pool)
-        .await?;
+    let mut tx = app_state.db_pool.begin().await?;
+    let deleted: Vec<String> = sqlx::query_scalar(
+        "DELETE FROM user_api_keys WHERE user_id = $1 AND provider = $2 RETURNING id",
+    )
+    .bind(&user_id)
+    .bind(&provider)
+    .fetch_all(&mut *tx)
+    .await?;
 
-    if result.rows_affected() == 0 {
-        Err(AppError::NotFound)
-    } else {
-        Ok(())
+    if deleted.is_empty() {
+        return Err(AppError::NotFound);
     }
+
+    unpin_unusable_keys(&mut tx, &user_id).await?;
+
+    AuditEvent::new(audit::API_KEY_DELETED)
+        .user(&user_id)
+        .target(&provider)
+        .client(&client)
+        .metadata(json!({ "key_ids": deleted }))
+        .record_in(&app_state, &mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
 }
 
-pub async fn get_key(
+pub async fn delete_key_by_id(
     State(app_state): State<AppState>,
     claims: Claims,
-    Path(provider): Path<String>,
-) -> Result<Json<DecryptedApiKeyResponse>, AppError> {
+    client: ClientInfo,
+    Path((provider, key_id)): Path<(String, String)>,
+) -> Result<(), AppError> {
     let user_id = claims.sub;
+    let mut tx = app_state.db_pool.begin().await?;
     let key_record = sqlx::query_as::<_, UserApiKey>(
-        "SELECT user_id, provider, encrypted_key, created_at FROM user_api_keys WHERE user_id = $1 AND provider = $2",
+        "DELETE FROM user_api_keys WHERE id = $1 AND user_id = $2 AND provider = $3 RETURNING *",
     )
-    .bind(user_id)
+    .bind(&key_id)
+    .bind(&user_id)
     .bind(&provider)
-    .fetch_one(&app_state.db_pool)
-    .await
-    .map_err(|_| AppError::NotFound)?;
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    unpin_unusable_keys(&mut tx, &user_id).await?;
 
-    let mc = new_magic_crypt!(&app_state.config.encryption_key, 256);
-    let decrypted_key = mc.decrypt_base64_to_string(&key_record.encrypted_key)
-        .map_err(|_| AppError::InternalServerError)?;
+    AuditEvent::new(audit::API_KEY_DELETED)
+        .user(&user_id)
+        .target(&provider)
+        .client(&client)
+        .metadata(json!({
+            "key_ids": [key_record.id],
+            "label": key_record.label,
+            "key_hint": key_record.key_hint,
+        }))
+        .record_in(&app_state, &mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Returns a stored key in plaintext. The caller has to re-enter their password
+// (and a 2FA code if enabled) on every reveal, and each attempt is audited.
+pub async fn reveal_key(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(provider): Path<String>,
+    Json(payload): Json<RevealKeyPayload>,
+) -> Result<Json<DecryptedApiKeyResponse>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+
+    let verified = auth_handler::reauthenticate(
+        &app_state,
+        &user,
+        &payload.password,
+        payload.code.as_deref(),
+        "reveal stored keys",
+    )
+    .await?;
+
+    if !verified {
+        AuditEvent::new(audit::API_KEY_REVEAL_DENIED)
+            .user(&user.id)
+            .target(&provider)
+            .client(&client)
+            .record(&app_state)
+            .await?;
+        return Err(AppError::Forbidden("re-authentication failed".to_string()));
+    }
+
+    let key_record = find_key(
+        &app_state.db_pool,
+        &user.id,
+        &provider,
+        payload.key_id.as_deref(),
+    )
+    .await?;
+
+    let decrypted_key = app_state.keyring.decrypt(&key_record.encrypted_key)?;
+
+    AuditEvent::new(audit::API_KEY_REVEALED)
+        .user(&user.id)
+        .target(&provider)
+        .client(&client)
+        .metadata(json!({
+            "key_id": key_record.id,
+            "label": key_record.label,
+            "key_hint": key_record.key_hint,
+        }))
+        .record(&app_state)
+        .await?;
 
     Ok(Json(DecryptedApiKeyResponse {
+        id: key_record.id,
         provider: key_record.provider,
+        label: key_record.label,
         api_key: decrypted_key,
         created_at: key_record.created_at,
     }))
@@ -1,26 +1,36 @@
 use crate::{
     auth::Claims,
-    database::{Chat, Message, UserApiKey},
+    database::{Chat, Message, UserApiKey, WorkspaceApiKey},
     error::AppError,
-    llm::get_llm_client,
+    handlers::chat_handler::{require_chat_access, ChatAccess},
+    handlers::key_handler::{self, KeySource},
+    handlers::settings_handler,
+    llm::{
+        get_llm_client,
+        mock::MOCK_PROVIDER,
+        routing::{RetryPolicy, Route},
+        GenerationParams, LLMClient, StreamChunk,
+    },
+    usage::{record_usage, UsageRecord},
     AppState,
 };
 use async_stream::stream;
 use axum::{
     body::Body,
     extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
     Json,
 };
 use futures_util::{StreamExt, TryStreamExt};
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
 use serde::Deserialize;
 use serde_json::json;
+use std::future::Future;
 use std::sync::Arc;
 use uuid::Uuid;
 
 // Helper function to generate chat title from message content
-fn generate_chat_title(content: &str) -> String {
+pub(crate) fn generate_chat_title(content: &str) -> String {
     if content.is_empty() {
         return "New Chat".to_string();
     }
@@ -78,6 +88,7 @@ pub struct SendMessagePayload {
 pub struct ParallelLLMPayload {
     pub content: String,
     pub models: Vec<ParallelModelConfig>,
+    #[allow(dead_code)]
     pub web_search: Option<bool>,
 }
 
@@ -87,26 +98,371 @@ pub struct ParallelModelConfig {
     pub model: String,
 }
 
-// --- helper function to get decrypted api key ---
-async fn get_decrypted_key(
-    pool: &sqlx::SqlitePool,
+// --- helpers to pick api keys ---
+pub(crate) struct ProviderKey {
+    pub id: String,
+    pub label: String,
+    pub api_key: String,
+    pub source: KeySource,
+}
+
+// Every key the user may use for the provider, in resolution order: their own
+// keys by priority, then keys of the workspaces they belong to (oldest
+// membership first), then the server-wide key from the environment.
+pub(crate) async fn provider_keys(
+    app_state: &AppState,
     user_id: &str,
     provider: &str,
-    encryption_key: &str,
-) -> Result<String, AppError> {
-    let record: UserApiKey =
-        sqlx::query_as("SELECT * FROM user_api_keys WHERE user_id = $1 AND provider = $2")
+) -> Result<Vec<ProviderKey>, AppError> {
+    // The mock provider needs no key, and stored ones don't unlock it.
+    if provider == MOCK_PROVIDER {
+        if !app_state.config.enable_mock_provider {
+            return Ok(Vec::new());
+        }
+        return Ok(vec![ProviderKey {
+            id: "server".to_string(),
+            label: "mock".to_string(),
+            api_key: String::new(),
+            source: KeySource::Server,
+        }]);
+    }
+
+    let pool = &app_state.db_pool;
+    let user_keys = sqlx::query_as::<_, UserApiKey>(
+        "SELECT * FROM user_api_keys WHERE user_id = $1 AND provider = $2 ORDER BY priority ASC, created_at ASC",
+    )
+    .bind(user_id)
+    .bind(provider)
+    .fetch_all(pool)
+    .await?;
+
+    let workspace_keys = sqlx::query_as::<_, WorkspaceApiKey>(
+        r#"
+        SELECT k.* FROM workspace_api_keys k
+        JOIN workspace_members m ON m.workspace_id = k.workspace_id
+        WHERE m.user_id = $1 AND k.provider = $2
+        ORDER BY m.created_at ASC, k.priority ASC, k.created_at ASC
+        "#,
+    )
+    .bind(user_id)
+    .bind(provider)
+    .fetch_all(pool)
+    .await?;
+
+    let mut keys = Vec::with_capacity(user_keys.len() + workspace_keys.len() + 1);
+    for key in user_keys {
+        keys.push(ProviderKey {
+            api_key: app_state.keyring.decrypt(&key.encrypted_key)?,
+            id: key.id,
+            label: key.label,
+            source: KeySource::User,
+        });
+    }
+    for key in workspace_keys {
+        keys.push(ProviderKey {
+            api_key: app_state.keyring.decrypt(&key.encrypted_key)?,
+            id: key.id,
+            label: key.label,
+            source: KeySource::Workspace(key.workspace_id),
+        });
+    }
+    if let Some(api_key) = app_state.config.server_api_keys.get(provider) {
+        keys.push(ProviderKey {
+            id: "server".to_string(),
+            label: "server".to_string(),
+            api_key: api_key.clone(),
+            source: KeySource::Server,
+        });
+    }
+    Ok(keys)
+}
+
+// Keys to use for a request, from `provider_keys`. The server key is dropped
+// when an admin has restricted it to other models. A key pinned to the chat,
+// or else to the model, is moved to the front. Only the first is returned
+// unless the user enabled failover in their settings.
+pub(crate) async fn resolve_api_keys(
+    app_state: &AppState,
+    user_id: &str,
+    provider: &str,
+    model: &str,
+    chat_key_id: Option<&str>,
+) -> Result<Vec<ProviderKey>, AppError> {
+    let pool = &app_state.db_pool;
+    let mut keys = provider_keys(app_state, user_id, provider).await?;
+
+    if keys.last().is_some_and(|k| k.source == KeySource::Server)
+        && !key_handler::server_key_allows(pool, provider, model).await?
+    {
+        keys.pop();
+        if keys.is_empty() {
+            return Err(AppError::BadRequest(format!(
+                "model '{}' is not available with the shared {} key.",
+                model, provider
+            )));
+        }
+    }
+
+    if keys.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "api key for provider '{}' not found.",
+            provider
+        )));
+    }
+
+    let model_key_id = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT api_key_id FROM user_models WHERE user_id = $1 AND provider = $2 AND model_id = $3",
+    )
+    .bind(user_id)
+    .bind(provider)
+    .bind(model)
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    let preferred = chat_key_id.map(str::to_string).or(model_key_id);
+    if let Some(pos) = preferred.and_then(|id| keys.iter().position(|k| k.id == id)) {
+        let key = keys.remove(pos);
+        keys.insert(0, key);
+    }
+
+    let failover =
+        sqlx::query_scalar::<_, bool>("SELECT key_failover FROM user_settings WHERE user_id = $1")
             .bind(user_id)
-            .bind(provider)
             .fetch_optional(pool)
             .await?
-            .ok_or_else(|| {
-                AppError::BadRequest(format!("api key for provider '{}' not found.", provider))
-            })?;
+            .unwrap_or(false);
+    if !failover {
+        keys.truncate(1);
+    }
+
+    Ok(keys)
+}
+
+// Runs `call` with a client for each key in turn. Transient failures (429,
+// 5xx) are retried on the same key as the policy allows, honouring the
+// provider's Retry-After. Once those retries are used up, a key that is
+// rate-limited (429) or rejected (401) gives way to the next key; other errors
+// are returned straight away. Streams are retried and fail over only before
+// the first chunk, since that's where providers report these statuses.
+// Returns the key that succeeded along with the result.
+pub(crate) async fn with_key_failover<'k, T, F, Fut>(
+    pool: &sqlx::SqlitePool,
+    policy: &RetryPolicy,
+    provider: &str,
+    keys: &'k [ProviderKey],
+    mut call: F,
+) -> Result<(T, &'k ProviderKey), AppError>
+where
+    F: FnMut(Box<dyn LLMClient>) -> Fut,
+    Fut: Future<Output = Result<T, AppError>>,
+{
+    let mut last_error = None;
+    for key in keys {
+        let mut attempt = 1;
+        let e = loop {
+            let client = get_llm_client(provider, &key.api_key)?;
+            key_handler::mark_key_used(pool, &key.source, &key.id).await;
+            let e = match call(client).await {
+                Ok(value) => return Ok((value, key)),
+                Err(e) => e,
+            };
+            let retry_after = match &e {
+                AppError::LLMProviderError { retry_after, .. } => *retry_after,
+                _ => None,
+            };
+            let delay = (attempt < policy.max_attempts && RetryPolicy::is_retryable(&e))
+                .then(|| policy.delay(attempt, retry_after))
+                .flatten();
+            let Some(delay) = delay else { break e };
+            tracing::warn!(
+                "{} key '{}' failed (attempt {} of {}), retrying in {:?}: {}",
+                provider,
+                key.label,
+                attempt,
+                policy.max_attempts,
+                delay,
+                e
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        };
+        key_handler::record_key_failure(pool, &key.source, &key.id, &e).await;
+        if !matches!(
+            e,
+            AppError::LLMProviderError {
+                status_code: Some(401 | 429),
+                ..
+            }
+        ) {
+            return Err(e);
+        }
+        tracing::warn!(
+            "{} key '{}' failed, trying the next key: {}",
+            provider,
+            key.label,
+            e
+        );
+        last_error = Some(e);
+    }
+    Err(last_error.unwrap_or_else(|| {
+        AppError::BadRequest(format!("api key for provider '{}' not found.", provider))
+    }))
+}
+
+// The chat's own provider and model followed by its fallback chain.
+fn chat_routes(chat: &Chat) -> Vec<Route> {
+    let mut routes = vec![Route::new(&chat.provider, &chat.model)];
+    routes.extend(Route::chain_from_column(chat.fallback_chain.as_deref()));
+    routes
+}
+
+// Runs `call` on each route in turn (see chat_routes) until one succeeds,
+// moving on when a route fails after its keys and retries are used up.
+// `keys` are those already resolved for the first route; later routes use the
+// owner's keys for their provider and are skipped when there are none. Returns
+// the route and key that succeeded; errors are the last route's.
+pub(crate) async fn with_fallback<T, F, Fut>(
+    app_state: &AppState,
+    owner_id: &str,
+    routes: &[Route],
+    keys: &[ProviderKey],
+    mut call: F,
+) -> Result<(T, Route, Option<String>), AppError>
+where
+    F: FnMut(Box<dyn LLMClient>, String) -> Fut,
+    Fut: Future<Output = Result<T, AppError>>,
+{
+    let pool = &app_state.db_pool;
+    let policy = &app_state.config.llm_retry;
+    let mut last_error = None;
+    for (i, route) in routes.iter().enumerate() {
+        let fallback_keys;
+        let route_keys = if i == 0 {
+            keys
+        } else {
+            match resolve_api_keys(app_state, owner_id, &route.provider, &route.model, None).await {
+                Ok(k) => {
+                    fallback_keys = k;
+                    &fallback_keys[..]
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "skipping fallback {}/{}: {}",
+                        route.provider,
+                        route.model,
+                        e
+                    );
+                    continue;
+                }
+            }
+        };
+        if let Some(previous) = last_error.take() {
+            tracing::warn!(
+                "falling back to {}/{} after: {}",
+                route.provider,
+                route.model,
+                previous
+            );
+        }
+        let result = with_key_failover(pool, policy, &route.provider, route_keys, |client| {
+            call(client, route.model.clone())
+        })
+        .await;
+        match result {
+            Ok((value, key)) => {
+                let workspace_id = key.source.workspace_id().map(str::to_string);
+                return Ok((value, route.clone(), workspace_id));
+            }
+            Err(e @ AppError::DatabaseError(_)) => return Err(e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or(AppError::InternalServerError))
+}
+
+fn conversation_chars(conversation: &[serde_json::Value]) -> usize {
+    conversation
+        .iter()
+        .map(|m| {
+            m.get("content")
+                .and_then(|c| c.as_str())
+                .map_or(0, str::len)
+        })
+        .sum()
+}
+
+// The chat's sampling settings over its owner's defaults.
+async fn generation_params(
+    pool: &sqlx::SqlitePool,
+    chat: &Chat,
+) -> Result<GenerationParams, AppError> {
+    let defaults = settings_handler::default_generation_params(pool, &chat.user_id).await?;
+    Ok(GenerationParams::from_column(chat.generation_params.as_deref()).or(&defaults))
+}
+
+// A piece of a streamed reply as the chat endpoints send it on.
+enum ReplyEvent {
+    // The provider and model answering, sent before the first chunk.
+    Route(Route),
+    Text(String),
+    Reasoning(String),
+    Error(String),
+}
+
+impl From<StreamChunk> for ReplyEvent {
+    fn from(chunk: StreamChunk) -> Self {
+        match chunk {
+            StreamChunk::Text(text) => ReplyEvent::Text(text),
+            StreamChunk::Reasoning(text) => ReplyEvent::Reasoning(text),
+        }
+    }
+}
+
+impl ReplyEvent {
+    // Plain bodies carry only the answer, with errors as "ERROR: message".
+    // Event streams (Accept: text/event-stream) label every piece, so
+    // reasoning can be shown apart from the answer.
+    fn encode(&self, event_stream: bool) -> Option<String> {
+        match (self, event_stream) {
+            (ReplyEvent::Route(_), false) => None,
+            (ReplyEvent::Text(text), false) => Some(text.clone()),
+            (ReplyEvent::Reasoning(_), false) => None,
+            (ReplyEvent::Error(message), false) => Some(format!("ERROR: {}", message)),
+            (ReplyEvent::Route(route), true) => Some(sse_event("route", json!(route))),
+            (ReplyEvent::Text(text), true) => Some(sse_event("text", json!({ "text": text }))),
+            (ReplyEvent::Reasoning(text), true) => {
+                Some(sse_event("reasoning", json!({ "text": text })))
+            }
+            (ReplyEvent::Error(message), true) => {
+                Some(sse_event("error", json!({ "message": message })))
+            }
+        }
+    }
+}
+
+fn sse_event(event: &str, data: serde_json::Value) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
 
-    let mc = new_magic_crypt!(encryption_key, 256);
-    mc.decrypt_base64_to_string(&record.encrypted_key)
-        .map_err(|_| AppError::InternalServerError) // decryption failed
+fn wants_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/event-stream"))
+}
+
+fn reply_response(body: Body, event_stream: bool) -> Response {
+    let mut response = Response::new(body);
+    if event_stream {
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/event-stream"),
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    }
+    response
 }
 
 // --- helper to prepare conversation history ---
@@ -121,10 +477,26 @@ async fn prepare_conversation(
     .fetch_all(pool)
     .await?;
 
+    // Earlier reasoning only goes back to the model if the owner asked for it
+    let reasoning_in_history: bool =
+        sqlx::query_scalar("SELECT reasoning_in_history FROM user_settings WHERE user_id = $1")
+            .bind(&chat.user_id)
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or(false);
+
     let mut conversation: Vec<serde_json::Value> = history
         .into_iter()
         .rev()
-        .map(|msg| json!({ "role": msg.role, "content": msg.content }))
+        .map(|msg| {
+            let content = match msg.reasoning.filter(|_| reasoning_in_history) {
+                Some(reasoning) => {
+                    format!("<thinking>\n{}\n</thinking>\n\n{}", reasoning, msg.content)
+                }
+                None => msg.content,
+            };
+            json!({ "role": msg.role, "content": content })
+        })
         .collect();
 
     // Always use current active system prompts instead of stored ones
@@ -160,19 +532,21 @@ pub async fn send_message(
     let user_id = claims.sub;
     let pool = &app_state.db_pool;
 
-    let chat: Chat = sqlx::query_as("SELECT * FROM chats WHERE id = $1 AND user_id = $2")
+    // Editors of a shared chat can post too; replies use the owner's keys
+    let owner_id = require_chat_access(pool, &chat_id, &user_id, ChatAccess::Editor).await?;
+    let chat: Chat = sqlx::query_as("SELECT * FROM chats WHERE id = $1")
         .bind(&chat_id)
-        .bind(&user_id)
         .fetch_one(pool)
         .await
         .map_err(|_| AppError::NotFound)?;
 
     let user_message = sqlx::query_as::<_, Message>(
-        "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'user', $3) RETURNING *",
+        "INSERT INTO messages (id, chat_id, role, content, user_id) VALUES ($1, $2, 'user', $3, $4) RETURNING *",
     )
     .bind(Uuid::new_v4().to_string())
     .bind(&chat_id)
     .bind(&payload.content)
+    .bind(&user_id)
     .fetch_one(pool)
     .await?;
 
@@ -201,24 +575,55 @@ pub async fn send_message(
     }
 
     let conversation = prepare_conversation(pool, &chat).await?;
+    let params = generation_params(pool, &chat).await?;
 
-    let api_key = get_decrypted_key(
-        pool,
-        &user_id,
+    let keys = resolve_api_keys(
+        &app_state,
+        &owner_id,
         &chat.provider,
-        &app_state.config.encryption_key,
+        &chat.model,
+        chat.api_key_id.as_deref(),
     )
     .await?;
 
-    let llm_client = get_llm_client(&chat.provider, &api_key)?;
-    let assistant_content = llm_client.chat(&chat.model, conversation).await?;
+    let prompt_chars = conversation_chars(&conversation);
+    let routes = chat_routes(&chat);
+    let result = with_fallback(&app_state, &owner_id, &routes, &keys, |client, model| {
+        let conversation = conversation.clone();
+        let params = params.clone();
+        async move { client.chat(&model, conversation, &params).await }
+    })
+    .await;
+
+    // Failed requests are put on the chat's own route and first key.
+    let (route, workspace_id) = match &result {
+        Ok((_, route, workspace_id)) => (route, workspace_id.as_deref()),
+        Err(_) => (&routes[0], keys[0].source.workspace_id()),
+    };
+    record_usage(
+        pool,
+        UsageRecord {
+            user_id: &user_id,
+            workspace_id,
+            provider: &route.provider,
+            model: &route.model,
+            source: "chat",
+            prompt_chars,
+            completion_chars: result.as_ref().map(|(c, _, _)| c.len()).unwrap_or(0),
+            success: result.is_ok(),
+        },
+    )
+    .await;
+    let (assistant_content, route, _) = result?;
 
     let assistant_message = sqlx::query_as::<_, Message>(
-        "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'assistant', $3) RETURNING *",
+        "INSERT INTO messages (id, chat_id, role, content, provider, model) VALUES ($1, $2, 'assistant', $3, $4, $5) RETURNING *",
     )
     .bind(Uuid::new_v4().to_string())
     .bind(&chat_id)
     .bind(assistant_content)
+    .bind(&route.provider)
+    .bind(&route.model)
     .fetch_one(pool)
     .await?;
 
@@ -231,70 +636,67 @@ pub async fn send_message(
 pub async fn stream_message(
     State(app_state): State<AppState>,
     claims: Claims,
+    headers: HeaderMap,
     Path(chat_id): Path<String>,
     Json(payload): Json<SendMessagePayload>,
 ) -> impl IntoResponse {
+    let event_stream = wants_event_stream(&headers);
     let user_id = claims.sub;
     let pool = app_state.db_pool.clone();
     let tx = app_state.tx.clone();
-    let encryption_key = app_state.config.encryption_key.clone();
 
     // --- 1. Fast validation and prep (minimize DB queries before streaming) ---
-    // Validate chat ownership first with minimal query
-    let chat_exists = match sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM chats WHERE id = $1 AND user_id = $2",
-    )
-    .bind(&chat_id)
-    .bind(&user_id)
-    .fetch_one(&pool)
-    .await
-    {
-        Ok(count) => count > 0,
-        Err(_) => false,
+    // Owners and editors can post; replies use the owner's keys
+    let owner_id = match require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await {
+        Ok(owner_id) => owner_id,
+        Err(e) => return e.into_response(),
     };
 
-    if !chat_exists {
-        return AppError::NotFound.into_response();
-    }
-
     // Get essential chat info for streaming
-    let (chat_provider, chat_model) = match sqlx::query_as::<_, (String, String)>(
-        "SELECT provider, model FROM chats WHERE id = $1",
+    let (chat_provider, chat_model, chat_key_id) =
+        match sqlx::query_as::<_, (String, String, Option<String>)>(
+            "SELECT provider, model, api_key_id FROM chats WHERE id = $1",
+        )
+        .bind(&chat_id)
+        .fetch_one(&pool)
+        .await
+        {
+            Ok(row) => row,
+            Err(_) => return AppError::NotFound.into_response(),
+        };
+
+    // Get API keys early
+    let keys = match resolve_api_keys(
+        &app_state,
+        &owner_id,
+        &chat_provider,
+        &chat_model,
+        chat_key_id.as_deref(),
     )
-    .bind(&chat_id)
-    .fetch_one(&pool)
     .await
     {
-        Ok((provider, model)) => (provider, model),
-        Err(_) => return AppError::NotFound.into_response(),
-    };
-
-    // Get API key early
-    let api_key = match get_decrypted_key(&pool, &user_id, &chat_provider, &encryption_key).await {
         Ok(k) => k,
         Err(e) => return e.into_response(),
     };
 
-    // Create LLM client early
+    // Check the provider early
     tracing::info!("Using provider: {}, model: {}", &chat_provider, &chat_model);
-    let llm_client = match get_llm_client(&chat_provider, &api_key) {
-        Ok(c) => c,
-        Err(e) => {
-            tracing::error!(
-                "Failed to create LLM client for provider {}: {:?}",
-                &chat_provider,
-                e
-            );
-            return e.into_response();
-        }
-    };
+    if let Err(e) = get_llm_client(&chat_provider, &keys[0].api_key) {
+        tracing::error!(
+            "Failed to create LLM client for provider {}: {:?}",
+            &chat_provider,
+            e
+        );
+        return e.into_response();
+    }
 
     // --- 2. Async DB operations and stream preparation ---
     let pool_clone = pool.clone();
     let chat_id_clone = chat_id.clone();
     let payload_content = payload.content.clone();
-    let _user_id_clone = user_id.clone();
+    let user_id_clone = user_id.clone();
     let tx_clone = tx.clone();
+    let route_state = app_state.clone();
 
     // --- 3. create the stream ---
     let response_stream = stream! {
@@ -304,11 +706,12 @@ pub async fn stream_message(
         // Start user message insert
         let user_msg_future = async {
             sqlx::query_as::<_, Message>(
-                "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'user', $3) RETURNING *",
+                "INSERT INTO messages (id, chat_id, role, content, user_id) VALUES ($1, $2, 'user', $3, $4) RETURNING *",
             )
             .bind(&user_message_id)
             .bind(&chat_id_clone)
             .bind(&payload_content)
+            .bind(&user_id_clone)
             .fetch_one(&pool_clone)
             .await
         };
@@ -351,6 +754,13 @@ pub async fn stream_message(
                 return;
             }
         };
+        let params = match generation_params(&pool_clone, &chat).await {
+            Ok(p) => p,
+            Err(e) => {
+                yield Err(e);
+                return;
+            }
+        };
 
         // Handle title update asynchronously (don't block streaming)
         if chat.title == "New Chat" || chat.title.contains("New Chat") {
@@ -379,46 +789,76 @@ pub async fn stream_message(
 
         // Start LLM streaming
         let full_response = Arc::new(tokio::sync::Mutex::new(String::new()));
-        let use_web_search = payload.web_search.unwrap_or(false) && llm_client.supports_web_search();
-        let mut llm_stream = if use_web_search {
-            match llm_client.chat_stream_with_web_search(&chat_model, conversation).await {
-                Ok(s) => s,
-                Err(e) => {
-                    // Send error as "ERROR: message" format in the stream
-                    let error_message = format!("ERROR: {}", e);
-                    tracing::error!("LLM web search streaming setup error: {}", e);
-                    yield Ok::<String, AppError>(error_message);
-                    return;
+        let use_web_search = payload.web_search.unwrap_or(false);
+        let routes = chat_routes(&chat);
+        let stream_result = with_fallback(&route_state, &owner_id, &routes, &keys, |client, model| {
+            let conversation = conversation.clone();
+            let params = params.clone();
+            async move {
+                if use_web_search && client.supports_web_search() {
+                    client.chat_stream_with_web_search(&model, conversation, &params).await
+                } else {
+                    client.chat_stream(&model, conversation, &params).await
                 }
             }
-        } else {
-            match llm_client.chat_stream(&chat_model, conversation).await {
-                Ok(s) => s,
-                Err(e) => {
-                    // Send error as "ERROR: message" format in the stream
-                    let error_message = format!("ERROR: {}", e);
-                    tracing::error!("LLM streaming setup error: {}", e);
-                    yield Ok::<String, AppError>(error_message);
-                    return;
-                }
+        })
+        .await;
+        let prompt_chars = conversation_chars(&conversation);
+        let (mut llm_stream, route, workspace_id) = match stream_result {
+            Ok(answered) => answered,
+            Err(e) => {
+                record_usage(
+                    &pool_clone,
+                    UsageRecord {
+                        user_id: &user_id_clone,
+                        workspace_id: keys[0].source.workspace_id(),
+                        provider: &chat_provider,
+                        model: &chat_model,
+                        source: "chat",
+                        prompt_chars,
+                        completion_chars: 0,
+                        success: false,
+                    },
+                )
+                .await;
+                tracing::error!("LLM streaming setup error: {}", e);
+                yield Ok::<ReplyEvent, AppError>(ReplyEvent::Error(e.to_string()));
+                return;
             }
         };
+        yield Ok(ReplyEvent::Route(route.clone()));
 
+        let mut success = true;
         while let Some(chunk_result) = llm_stream.next().await {
             match chunk_result {
                 Ok(chunk) => {
-                    full_response.lock().await.push_str(&chunk);
-                    yield Ok(chunk);
+                    full_response.lock().await.push_str(chunk.text());
+                    yield Ok(ReplyEvent::from(chunk));
                 }
                 Err(e) => {
-                    // Send error as "ERROR: message" format in the stream instead of yielding error
-                    let error_message = format!("ERROR: {}", e);
                     tracing::error!("LLM streaming error: {}", e);
-                    yield Ok::<String, AppError>(error_message);
+                    yield Ok(ReplyEvent::Error(e.to_string()));
+                    success = false;
                     break;
                 }
             }
         }
+
+        let completion_chars = full_response.lock().await.len();
+        record_usage(
+            &pool_clone,
+            UsageRecord {
+                user_id: &user_id_clone,
+                workspace_id: workspace_id.as_deref(),
+                provider: &route.provider,
+                model: &route.model,
+                source: "chat",
+                prompt_chars,
+                completion_chars,
+                success,
+            },
+        )
+        .await;
     };
 
     // Wrap the stream with a Drop guard to ensure content is saved on disconnect.
@@ -431,6 +871,8 @@ pub async fn stream_message(
             // This happens when the stream finishes gracefully OR is cancelled (e.g., client disconnects).
             struct ContentSaver {
                 content: String,
+                reasoning: String,
+                route: Option<Route>,
                 has_streamed: bool,
                 pool: sqlx::SqlitePool,
                 chat_id: String,
@@ -442,6 +884,8 @@ pub async fn stream_message(
                     if self.has_streamed && !self.content.trim().is_empty() {
                         // Clone the data needed for the async task.
                         let content_to_save = self.content.clone();
+                        let reasoning_to_save = self.reasoning.clone();
+                        let route = self.route.clone();
                         let pool = self.pool.clone();
                         let chat_id = self.chat_id.clone();
                         let tx = self.tx.clone();
@@ -449,11 +893,14 @@ pub async fn stream_message(
                         tokio::spawn(async move {
                             tracing::info!("ContentSaver: Saving content on drop (length: {})", content_to_save.len());
                             match sqlx::query_as::<_, Message>(
-                                "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'assistant', $3) RETURNING *",
+                                "INSERT INTO messages (id, chat_id, role, content, reasoning, provider, model) VALUES ($1, $2, 'assistant', $3, NULLIF($4, ''), $5, $6) RETURNING *",
                             )
                             .bind(Uuid::new_v4().to_string())
                             .bind(&chat_id)
                             .bind(&content_to_save)
+                            .bind(&reasoning_to_save)
+                            .bind(route.as_ref().map(|r| r.provider.as_str()))
+                            .bind(route.as_ref().map(|r| r.model.as_str()))
                             .fetch_one(&pool)
                             .await {
                                 Ok(assistant_message) => {
@@ -473,44 +920,61 @@ pub async fn stream_message(
 
             let mut saver = ContentSaver {
                 content: String::new(),
+                reasoning: String::new(),
+                route: None,
                 has_streamed: false,
                 pool: saver_pool,
-                chat_id: chat_id,
+                chat_id,
                 tx: saver_tx,
             };
 
             let mut stream = std::pin::pin!(response_stream);
             while let Some(result) = stream.next().await {
-                if let Ok(chunk) = &result {
-                    if !chunk.starts_with("ERROR:") {
-                        saver.content.push_str(chunk);
+                let event = match result {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
+                        continue;
+                    }
+                };
+                match &event {
+                    ReplyEvent::Route(route) => saver.route = Some(route.clone()),
+                    ReplyEvent::Text(text) => {
+                        saver.content.push_str(text);
                         saver.has_streamed = true;
                     }
+                    ReplyEvent::Reasoning(text) => saver.reasoning.push_str(text),
+                    ReplyEvent::Error(_) => {}
+                }
+                if let Some(encoded) = event.encode(event_stream) {
+                    yield Ok::<String, AppError>(encoded);
                 }
-                yield result;
             }
         }
     };
 
     let body_stream = monitored_stream.map_err(axum::Error::new);
-    Response::new(Body::from_stream(body_stream))
+    reply_response(Body::from_stream(body_stream), event_stream)
 }
 
 // --- regenerate response handler (for message editing) ---
 pub async fn regenerate_response(
     State(app_state): State<AppState>,
     claims: Claims,
+    headers: HeaderMap,
     Path(chat_id): Path<String>,
 ) -> impl IntoResponse {
+    let event_stream = wants_event_stream(&headers);
     let user_id = claims.sub;
     let pool = app_state.db_pool.clone();
-    let tx = app_state.tx.clone();
-    let encryption_key = app_state.config.encryption_key.clone();
 
     // --- 1. initial db operations & validation ---
-    let chat: Chat = match sqlx::query_as("SELECT * FROM chats WHERE id = $1 AND user_id = $2")
+    let owner_id = match require_chat_access(&pool, &chat_id, &user_id, ChatAccess::Editor).await {
+        Ok(owner_id) => owner_id,
+        Err(e) => return e.into_response(),
+    };
+    let chat: Chat = match sqlx::query_as("SELECT * FROM chats WHERE id = $1")
         .bind(&chat_id)
-        .bind(&user_id)
         .fetch_one(&pool)
         .await
     {
@@ -523,54 +987,102 @@ pub async fn regenerate_response(
         Ok(c) => c,
         Err(e) => return e.into_response(),
     };
+    let params = match generation_params(&pool, &chat).await {
+        Ok(p) => p,
+        Err(e) => return e.into_response(),
+    };
 
-    let api_key = match get_decrypted_key(&pool, &user_id, &chat.provider, &encryption_key).await {
+    let keys = match resolve_api_keys(
+        &app_state,
+        &owner_id,
+        &chat.provider,
+        &chat.model,
+        chat.api_key_id.as_deref(),
+    )
+    .await
+    {
         Ok(k) => k,
         Err(e) => return e.into_response(),
     };
 
     tracing::info!("Using provider: {}, model: {}", &chat.provider, &chat.model);
-    let llm_client = match get_llm_client(&chat.provider, &api_key) {
-        Ok(c) => c,
-        Err(e) => {
-            tracing::error!(
-                "Failed to create LLM client for provider {}: {:?}",
-                &chat.provider,
-                e
-            );
-            return e.into_response();
-        }
-    };
+    if let Err(e) = get_llm_client(&chat.provider, &keys[0].api_key) {
+        tracing::error!(
+            "Failed to create LLM client for provider {}: {:?}",
+            &chat.provider,
+            e
+        );
+        return e.into_response();
+    }
 
     // --- 3. create the stream ---
+    let key_pool = pool.clone();
+    let route_state = app_state.clone();
     let response_stream = stream! {
         let full_response = Arc::new(tokio::sync::Mutex::new(String::new()));
-        let mut llm_stream = match llm_client.chat_stream(&chat.model, conversation).await {
-            Ok(s) => s,
+        let routes = chat_routes(&chat);
+        let stream_result = with_fallback(&route_state, &owner_id, &routes, &keys, |client, model| {
+            let conversation = conversation.clone();
+            let params = params.clone();
+            async move { client.chat_stream(&model, conversation, &params).await }
+        })
+        .await;
+        let prompt_chars = conversation_chars(&conversation);
+        let (mut llm_stream, route, workspace_id) = match stream_result {
+            Ok(answered) => answered,
             Err(e) => {
-                // Send error as "ERROR: message" format in the stream
-                let error_message = format!("ERROR: {}", e);
+                record_usage(
+                    &key_pool,
+                    UsageRecord {
+                        user_id: &user_id,
+                        workspace_id: keys[0].source.workspace_id(),
+                        provider: &chat.provider,
+                        model: &chat.model,
+                        source: "chat",
+                        prompt_chars,
+                        completion_chars: 0,
+                        success: false,
+                    },
+                )
+                .await;
                 tracing::error!("LLM regenerate streaming setup error: {}", e);
-                yield Ok::<String, AppError>(error_message);
+                yield Ok::<ReplyEvent, AppError>(ReplyEvent::Error(e.to_string()));
                 return;
             }
         };
+        yield Ok(ReplyEvent::Route(route.clone()));
 
+        let mut success = true;
         while let Some(chunk_result) = llm_stream.next().await {
             match chunk_result {
                 Ok(chunk) => {
-                    full_response.lock().await.push_str(&chunk);
-                    yield Ok(chunk);
+                    full_response.lock().await.push_str(chunk.text());
+                    yield Ok(ReplyEvent::from(chunk));
                 }
                 Err(e) => {
-                    // Send error as "ERROR: message" format in the stream instead of yielding error
-                    let error_message = format!("ERROR: {}", e);
                     tracing::error!("LLM regenerate streaming error: {}", e);
-                    yield Ok::<String, AppError>(error_message);
+                    yield Ok(ReplyEvent::Error(e.to_string()));
+                    success = false;
                     break;
                 }
             }
         }
+
+        let completion_chars = full_response.lock().await.len();
+        record_usage(
+            &key_pool,
+            UsageRecord {
+                user_id: &user_id,
+                workspace_id: workspace_id.as_deref(),
+                provider: &route.provider,
+                model: &route.model,
+                source: "chat",
+                prompt_chars,
+                completion_chars,
+                success,
+            },
+        )
+        .await;
     };
 
     // Wrap the stream with a Drop guard to ensure content is saved on disconnect.
@@ -581,6 +1093,8 @@ pub async fn regenerate_response(
         stream! {
             struct ContentSaver {
                 content: String,
+                reasoning: String,
+                route: Option<Route>,
                 has_streamed: bool,
                 pool: sqlx::SqlitePool,
                 chat_id: String,
@@ -591,6 +1105,8 @@ pub async fn regenerate_response(
                 fn drop(&mut self) {
                     if self.has_streamed && !self.content.trim().is_empty() {
                         let content_to_save = self.content.clone();
+                        let reasoning_to_save = self.reasoning.clone();
+                        let route = self.route.clone();
                         let pool = self.pool.clone();
                         let chat_id = self.chat_id.clone();
                         let tx = self.tx.clone();
@@ -598,11 +1114,14 @@ pub async fn regenerate_response(
                         tokio::spawn(async move {
                             tracing::info!("Regenerate ContentSaver: Saving content on drop (length: {})", content_to_save.len());
                             match sqlx::query_as::<_, Message>(
-                                "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'assistant', $3) RETURNING *",
+                                "INSERT INTO messages (id, chat_id, role, content, reasoning, provider, model) VALUES ($1, $2, 'assistant', $3, NULLIF($4, ''), $5, $6) RETURNING *",
                             )
                             .bind(Uuid::new_v4().to_string())
                             .bind(&chat_id)
                             .bind(&content_to_save)
+                            .bind(&reasoning_to_save)
+                            .bind(route.as_ref().map(|r| r.provider.as_str()))
+                            .bind(route.as_ref().map(|r| r.model.as_str()))
                             .fetch_one(&pool)
                             .await {
                                 Ok(assistant_message) => {
@@ -622,27 +1141,41 @@ pub async fn regenerate_response(
 
             let mut saver = ContentSaver {
                 content: String::new(),
+                reasoning: String::new(),
+                route: None,
                 has_streamed: false,
                 pool: saver_pool,
-                chat_id: chat_id,
+                chat_id,
                 tx: saver_tx,
             };
 
             let mut stream = std::pin::pin!(response_stream);
             while let Some(result) = stream.next().await {
-                if let Ok(chunk) = &result {
-                    if !chunk.starts_with("ERROR:") {
-                        saver.content.push_str(chunk);
+                let event = match result {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
+                        continue;
+                    }
+                };
+                match &event {
+                    ReplyEvent::Route(route) => saver.route = Some(route.clone()),
+                    ReplyEvent::Text(text) => {
+                        saver.content.push_str(text);
                         saver.has_streamed = true;
                     }
+                    ReplyEvent::Reasoning(text) => saver.reasoning.push_str(text),
+                    ReplyEvent::Error(_) => {}
+                }
+                if let Some(encoded) = event.encode(event_stream) {
+                    yield Ok::<String, AppError>(encoded);
                 }
-                yield result;
             }
         }
     };
 
     let body_stream = monitored_stream.map_err(axum::Error::new);
-    Response::new(Body::from_stream(body_stream))
+    reply_response(Body::from_stream(body_stream), event_stream)
 }
 
 // --- parallel llm handler ---
@@ -664,11 +1197,12 @@ pub async fn parallel_llm_query(
 
     // Add user message to parent chat first
     let user_message = sqlx::query_as::<_, Message>(
-        "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, 'user', $3) RETURNING *",
+        "INSERT INTO messages (id, chat_id, role, content, user_id) VALUES ($1, $2, 'user', $3, $4) RETURNING *",
     )
     .bind(Uuid::new_v4().to_string())
     .bind(&chat_id)
     .bind(&payload.content)
+    .bind(&user_id)
     .fetch_one(pool)
     .await?;
 
@@ -713,8 +1247,8 @@ pub async fn parallel_llm_query(
 
         let branch_chat = sqlx::query_as::<_, Chat>(
             r#"
-            INSERT INTO chats (id, user_id, title, system_prompt, provider, model, pinned, is_branch, parent_chat_id, branch_point_message_id)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            INSERT INTO chats (id, user_id, title, system_prompt, provider, model, pinned, is_branch, parent_chat_id, branch_point_message_id, generation_params, fallback_chain)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             RETURNING *
             "#,
         )
@@ -728,6 +1262,8 @@ pub async fn parallel_llm_query(
         .bind(true)  // is_branch is true for branch chats
         .bind(&chat_id)
         .bind(&user_message.id)
+        .bind(&parent_chat.generation_params)
+        .bind(&parent_chat.fallback_chain)
         .fetch_one(pool)
         .await?;
 
@@ -742,12 +1278,13 @@ pub async fn parallel_llm_query(
 
         for msg in history {
             sqlx::query(
-                "INSERT INTO messages (id, chat_id, role, content) VALUES ($1, $2, $3, $4)",
+                "INSERT INTO messages (id, chat_id, role, content, user_id) VALUES ($1, $2, $3, $4, $5)",
             )
             .bind(Uuid::new_v4().to_string())
             .bind(&branch_chat_id)
             .bind(&msg.role)
             .bind(&msg.content)
+            .bind(&msg.user_id)
             .execute(pool)
             .await?;
         }
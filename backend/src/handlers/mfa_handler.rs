@@ -0,0 +1,312 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{hash_token, AdminClaims, ClientInfo, SessionClaims},
+    database::User,
+    error::AppError,
+    handlers::auth_handler,
+    totp, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const TOTP_ISSUER: &str = "neko.chat";
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// Turning 2FA on or off and replacing the recovery codes need the password as
+// well as a current code, so a stolen access token alone can't do any of them.
+#[derive(Deserialize)]
+pub struct MfaReauthPayload {
+    password: String,
+    code: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaSetupResponse {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    // Shown once; only hashes are stored.
+    recovery_codes: Vec<String>,
+}
+
+async fn replace_recovery_codes(pool: &SqlitePool, user_id: &str) -> Result<Vec<String>, AppError> {
+    let codes = totp::generate_recovery_codes(RECOVERY_CODE_COUNT);
+
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    for code in &codes {
+        sqlx::query("INSERT INTO mfa_recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(user_id)
+            .bind(hash_token(code))
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(codes)
+}
+
+// Checks a TOTP code or an unused recovery code for a user with 2FA enabled.
+// Each TOTP step and each recovery code can only be used once.
+pub(crate) async fn verify_second_factor(
+    app_state: &AppState,
+    user_id: &str,
+    code: &str,
+) -> Result<bool, AppError> {
+    let encrypted_secret = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT totp_secret FROM users WHERE id = $1 AND totp_enabled = true",
+    )
+    .bind(user_id)
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("two-factor authentication is not enabled".to_string()))?;
+
+    if let Some(encrypted_secret) = encrypted_secret {
+        let secret = app_state.keyring.decrypt(&encrypted_secret)?;
+
+        if let Some(step) = totp::verify(&secret, code, Utc::now().timestamp()) {
+            let result = sqlx::query(
+                "UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+            )
+            .bind(step)
+            .bind(user_id)
+            .execute(&app_state.db_pool)
+            .await?;
+            return Ok(result.rows_affected() == 1);
+        }
+    }
+
+    let result = sqlx::query(
+        r#"
+        UPDATE mfa_recovery_codes SET used_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash_token(&code.trim().to_lowercase()))
+    .execute(&app_state.db_pool)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+// Starts enrolment: stores a new (not yet active) secret and returns it.
+pub async fn setup_mfa(
+    State(app_state): State<AppState>,
+    SessionClaims(claims): SessionClaims,
+) -> Result<Json<MfaSetupResponse>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+
+    if user.totp_enabled {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let secret = totp::generate_secret();
+    sqlx::query("UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2")
+        .bind(app_state.keyring.encrypt(&secret))
+        .bind(&user.id)
+        .execute(&app_state.db_pool)
+        .await?;
+
+    Ok(Json(MfaSetupResponse {
+        otpauth_uri: totp::otpauth_uri(TOTP_ISSUER, &user.email, &secret),
+        secret,
+    }))
+}
+
+// Finishes enrolment once the user proves their app produces valid codes.
+pub async fn enable_mfa(
+    State(app_state): State<AppState>,
+    SessionClaims(claims): SessionClaims,
+    client: ClientInfo,
+    Json(payload): Json<MfaReauthPayload>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+
+    if user.totp_enabled {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let encrypted_secret =
+        sqlx::query_scalar::<_, Option<String>>("SELECT totp_secret FROM users WHERE id = $1")
+            .bind(&user.id)
+            .fetch_one(&app_state.db_pool)
+            .await?
+            .ok_or_else(|| {
+                AppError::BadRequest("start two-factor setup before enabling it".to_string())
+            })?;
+
+    let verified = auth_handler::reauthenticate(
+        &app_state,
+        &user,
+        &payload.password,
+        None,
+        "turn on two-factor authentication",
+    )
+    .await?;
+    if !verified {
+        return Err(AppError::Forbidden("re-authentication failed".to_string()));
+    }
+
+    let secret = app_state.keyring.decrypt(&encrypted_secret)?;
+
+    let step = totp::verify(&secret, &payload.code, Utc::now().timestamp())
+        .ok_or_else(|| AppError::BadRequest("invalid verification code".to_string()))?;
+
+    sqlx::query("UPDATE users SET totp_enabled = true, totp_last_step = $1 WHERE id = $2")
+        .bind(step)
+        .bind(&claims.sub)
+        .execute(&app_state.db_pool)
+        .await?;
+
+    let recovery_codes = replace_recovery_codes(&app_state.db_pool, &claims.sub).await?;
+
+    AuditEvent::new(audit::MFA_ENABLED)
+        .user(&claims.sub)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Re-authenticates with password and second factor; failures count towards
+// the login lockout.
+async fn reauthenticate_mfa_user(
+    app_state: &AppState,
+    user_id: &str,
+    payload: &MfaReauthPayload,
+    purpose: &str,
+) -> Result<(), AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&app_state.db_pool)
+        .await?;
+    if !user.totp_enabled {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is not enabled".to_string(),
+        ));
+    }
+
+    let verified = auth_handler::reauthenticate(
+        app_state,
+        &user,
+        &payload.password,
+        Some(&payload.code),
+        purpose,
+    )
+    .await?;
+    if !verified {
+        return Err(AppError::Forbidden("re-authentication failed".to_string()));
+    }
+    Ok(())
+}
+
+async fn clear_mfa(pool: &SqlitePool, user_id: &str) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_enabled = false, totp_secret = NULL, totp_last_step = NULL WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn disable_mfa(
+    State(app_state): State<AppState>,
+    SessionClaims(claims): SessionClaims,
+    client: ClientInfo,
+    Json(payload): Json<MfaReauthPayload>,
+) -> Result<Json<Value>, AppError> {
+    reauthenticate_mfa_user(
+        &app_state,
+        &claims.sub,
+        &payload,
+        "turn off two-factor authentication",
+    )
+    .await?;
+
+    clear_mfa(&app_state.db_pool, &claims.sub).await?;
+
+    AuditEvent::new(audit::MFA_DISABLED)
+        .user(&claims.sub)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(app_state): State<AppState>,
+    SessionClaims(claims): SessionClaims,
+    client: ClientInfo,
+    Json(payload): Json<MfaReauthPayload>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    reauthenticate_mfa_user(
+        &app_state,
+        &claims.sub,
+        &payload,
+        "replace your recovery codes",
+    )
+    .await?;
+
+    let recovery_codes = replace_recovery_codes(&app_state.db_pool, &claims.sub).await?;
+
+    AuditEvent::new(audit::RECOVERY_CODES_REGENERATED)
+        .user(&claims.sub)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Lets an admin turn off 2FA for a user who lost both their device and codes.
+pub async fn admin_reset_mfa(
+    State(app_state): State<AppState>,
+    AdminClaims(claims): AdminClaims,
+    client: ClientInfo,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, AppError> {
+    if clear_mfa(&app_state.db_pool, &user_id).await? == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::ADMIN_MFA_RESET)
+        .user(&claims.sub)
+        .target(&user_id)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "success": true })))
+}
@@ -1,6 +1,13 @@
+pub mod account_handler;
+pub mod admin_handler;
 pub mod auth_handler;
 pub mod chat_handler;
 pub mod key_handler;
 pub mod llm_handler;
+pub mod mfa_handler;
+pub mod oidc_handler;
+pub mod openai_handler;
 pub mod settings_handler;
+pub mod token_handler;
+pub mod workspace_handler;
 pub mod ws_handler;
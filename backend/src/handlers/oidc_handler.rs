@@ -0,0 +1,491 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{self, Claims, ClientInfo},
+    config::{Config, OidcProvider},
+    database::{format_db_timestamp, User, UserIdentity},
+    error::AppError,
+    handlers::auth_handler::{self, LoginResponse},
+    oidc::{self, OidcClient},
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+const OAUTH_STATE_TTL_MINUTES: i64 = 10;
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+type WithStateCookie<T> = ([(header::HeaderName, String); 1], Json<T>);
+
+#[derive(Deserialize)]
+pub struct OidcAuthQuery {
+    invite_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    code: String,
+    state: String,
+}
+
+#[derive(Serialize)]
+pub struct ProviderSummary {
+    id: String,
+    name: String,
+}
+
+fn find_provider<'a>(config: &'a Config, id: &str) -> Result<&'a OidcProvider, AppError> {
+    config
+        .oidc_providers
+        .iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| AppError::BadRequest(format!("sign-in provider {} is not configured", id)))
+}
+
+// Binds a flow to the browser that started it: the callback only accepts a
+// state that matches this cookie.
+fn state_cookie(config: &Config, state: &str, max_age: i64) -> String {
+    let secure = if config.app_base_url.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{}={}; Path=/api/auth; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        OAUTH_STATE_COOKIE, state, max_age, secure
+    )
+}
+
+fn cookie_state(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (name, value) = pair.trim().split_once('=')?;
+            (name == OAUTH_STATE_COOKIE).then_some(value)
+        })
+}
+
+// PKCE S256 challenge: base64url(sha256(verifier)) without padding.
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+pub async fn list_providers(State(config): State<Config>) -> Json<Vec<ProviderSummary>> {
+    Json(
+        config
+            .oidc_providers
+            .iter()
+            .map(|p| ProviderSummary {
+                id: p.id.clone(),
+                name: p.display_name.clone(),
+            })
+            .collect(),
+    )
+}
+
+// Stores a fresh state/nonce/verifier and builds the provider's authorization URL.
+// `link_user_id` is set when a logged-in user is attaching an identity to their
+// account; `invite_code` is kept for the callback in case the login turns into a signup.
+// Returns the URL along with the state cookie to set on the browser.
+async fn begin_oidc(
+    app_state: &AppState,
+    provider_id: &str,
+    link_user_id: Option<&str>,
+    invite_code: Option<&str>,
+) -> Result<(String, String), AppError> {
+    let provider = find_provider(&app_state.config, provider_id)?;
+    let metadata = app_state.oidc.metadata(provider).await?;
+
+    sqlx::query(
+        "DELETE FROM oauth_states WHERE expires_at <= strftime('%Y-%m-%d %H:%M:%f', 'now')",
+    )
+    .execute(&app_state.db_pool)
+    .await?;
+
+    let state = auth::generate_token("");
+    let nonce = auth::generate_token("");
+    let code_verifier = format!("{}{}", auth::generate_token(""), auth::generate_token(""));
+    let expires_at = Utc::now() + Duration::minutes(OAUTH_STATE_TTL_MINUTES);
+
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_states (state, provider, code_verifier, nonce, link_user_id, invite_code, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(&state)
+    .bind(&provider.id)
+    .bind(&code_verifier)
+    .bind(&nonce)
+    .bind(link_user_id)
+    .bind(invite_code)
+    .bind(format_db_timestamp(expires_at))
+    .execute(&app_state.db_pool)
+    .await?;
+
+    let auth_url = OidcClient::authorization_url(
+        &metadata,
+        provider,
+        &state,
+        &nonce,
+        &pkce_challenge(&code_verifier),
+    );
+    let cookie = state_cookie(&app_state.config, &state, OAUTH_STATE_TTL_MINUTES * 60);
+    Ok((auth_url, cookie))
+}
+
+pub async fn oidc_auth_url(
+    State(app_state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(params): Query<OidcAuthQuery>,
+) -> Result<WithStateCookie<Value>, AppError> {
+    let (auth_url, cookie) =
+        begin_oidc(&app_state, &provider, None, params.invite_code.as_deref()).await?;
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({ "auth_url": auth_url })),
+    ))
+}
+
+pub async fn oidc_link_url(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    Path(provider): Path<String>,
+) -> Result<WithStateCookie<Value>, AppError> {
+    let (auth_url, cookie) = begin_oidc(&app_state, &provider, Some(&claims.sub), None).await?;
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({ "auth_url": auth_url })),
+    ))
+}
+
+pub async fn list_identities(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+) -> Result<Json<Vec<UserIdentity>>, AppError> {
+    let identities = sqlx::query_as::<_, UserIdentity>(
+        "SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(&claims.sub)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(identities))
+}
+
+pub async fn oidc_unlink(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(provider): Path<String>,
+) -> Result<Json<User>, AppError> {
+    let pool = &app_state.db_pool;
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&claims.sub)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let other_identities = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM user_identities WHERE user_id = $1 AND provider != $2",
+    )
+    .bind(&user.id)
+    .bind(&provider)
+    .fetch_one(pool)
+    .await?;
+
+    // Without a password or another identity the user would have no way left to sign in.
+    if user.password_hash.is_none() && other_identities == 0 {
+        return Err(AppError::BadRequest(
+            "set a password before unlinking your last sign-in method".to_string(),
+        ));
+    }
+
+    let result = sqlx::query("DELETE FROM user_identities WHERE user_id = $1 AND provider = $2")
+        .bind(&user.id)
+        .bind(&provider)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest(format!(
+            "no {} account is linked",
+            provider
+        )));
+    }
+
+    AuditEvent::new(audit::IDENTITY_UNLINKED)
+        .user(&user.id)
+        .target(&provider)
+        .client(&client)
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(user))
+}
+
+// The provider is taken from the stored state, so every provider can share
+// one redirect URI.
+pub async fn oidc_callback(
+    State(app_state): State<AppState>,
+    client_info: ClientInfo,
+    session: Option<Claims>,
+    headers: HeaderMap,
+    Query(params): Query<OidcCallbackQuery>,
+) -> Result<WithStateCookie<LoginResponse>, AppError> {
+    // A callback from a flow this browser never started (login CSRF) is refused.
+    if cookie_state(&headers) != Some(params.state.as_str()) {
+        return Err(AppError::BadRequest(
+            "invalid or expired OAuth state".to_string(),
+        ));
+    }
+
+    // States are single-use: consume it before doing anything else.
+    let (provider_id, code_verifier, nonce, link_user_id, invite_code) = sqlx::query_as::<
+        _,
+        (
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ),
+    >(
+        r#"
+        DELETE FROM oauth_states
+        WHERE state = $1 AND expires_at > strftime('%Y-%m-%d %H:%M:%f', 'now')
+        RETURNING provider, code_verifier, nonce, link_user_id, invite_code
+        "#,
+    )
+    .bind(&params.state)
+    .fetch_optional(&app_state.db_pool)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("invalid or expired OAuth state".to_string()))?;
+
+    let nonce =
+        nonce.ok_or_else(|| AppError::BadRequest("invalid or expired OAuth state".to_string()))?;
+    let provider = find_provider(&app_state.config, &provider_id)?;
+
+    // A link is only completed for the account that started it, signed in.
+    if let Some(link_user_id) = &link_user_id {
+        if session.map(|claims| claims.sub).as_ref() != Some(link_user_id) {
+            return Err(AppError::BadRequest(format!(
+                "sign in to the account you are linking {} to",
+                provider.display_name
+            )));
+        }
+    }
+
+    let tokens = app_state
+        .oidc
+        .exchange_code(provider, &params.code, &code_verifier)
+        .await?;
+    let id_token = tokens.id_token.ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "{} did not return an ID token",
+            provider.display_name
+        ))
+    })?;
+
+    let mut claims = app_state
+        .oidc
+        .validate_id_token(provider, &id_token, &nonce)
+        .await?;
+    if !claims.contains_key("email") {
+        app_state
+            .oidc
+            .merge_userinfo(provider, &tokens.access_token, &mut claims)
+            .await?;
+    }
+    let identity = oidc::identity_from_claims(provider, &claims)?;
+
+    let linked_user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT users.* FROM user_identities
+        JOIN users ON users.id = user_identities.user_id
+        WHERE user_identities.provider = $1 AND user_identities.subject = $2
+        "#,
+    )
+    .bind(&provider.id)
+    .bind(&identity.subject)
+    .fetch_optional(&app_state.db_pool)
+    .await?;
+
+    let user = if let Some(link_user_id) = link_user_id {
+        // Linking flow: attach the identity to the account that started it.
+        match linked_user {
+            Some(u) if u.id != link_user_id => {
+                return Err(AppError::BadRequest(format!(
+                    "this {} account is already linked to another user",
+                    provider.display_name
+                )));
+            }
+            Some(u) => u,
+            None => {
+                let already_linked = sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM user_identities WHERE user_id = $1 AND provider = $2",
+                )
+                .bind(&link_user_id)
+                .bind(&provider.id)
+                .fetch_one(&app_state.db_pool)
+                .await?;
+                if already_linked > 0 {
+                    return Err(AppError::BadRequest(format!(
+                        "a different {} account is already linked, unlink it first",
+                        provider.display_name
+                    )));
+                }
+
+                insert_identity(&app_state.db_pool, &link_user_id, provider, &identity).await?;
+
+                sqlx::query_as::<_, User>(
+                    "UPDATE users SET avatar_url = COALESCE(avatar_url, $1) WHERE id = $2 RETURNING *",
+                )
+                .bind(&identity.picture)
+                .bind(&link_user_id)
+                .fetch_optional(&app_state.db_pool)
+                .await?
+                .ok_or(AppError::NotFound)?
+            }
+        }
+    } else if let Some(user) = linked_user {
+        sqlx::query(
+            r#"
+            UPDATE user_identities
+            SET email = $1, last_login_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+            WHERE provider = $2 AND subject = $3
+            "#,
+        )
+        .bind(&identity.email)
+        .bind(&provider.id)
+        .bind(&identity.subject)
+        .execute(&app_state.db_pool)
+        .await?;
+
+        // Keep the role in sync with the provider's groups on every login.
+        match &identity.role {
+            Some(role) if *role != user.role => {
+                sqlx::query_as::<_, User>("UPDATE users SET role = $1 WHERE id = $2 RETURNING *")
+                    .bind(role)
+                    .bind(&user.id)
+                    .fetch_one(&app_state.db_pool)
+                    .await?
+            }
+            _ => user,
+        }
+    } else {
+        // Password signups keep the address as typed; identity emails are
+        // already lowercase.
+        let email_taken =
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE lower(email) = $1")
+                .bind(&identity.email)
+                .fetch_one(&app_state.db_pool)
+                .await?;
+        if email_taken > 0 {
+            // Never attach an identity to an existing account by email alone.
+            return Err(AppError::BadRequest(format!(
+                "an account with this email already exists. log in with your password and link {} from your profile",
+                provider.display_name
+            )));
+        }
+
+        auth_handler::check_signup_policy(
+            &app_state.config,
+            &identity.email,
+            invite_code.as_deref(),
+        )?;
+
+        let user_id = Uuid::new_v4().to_string();
+        let mut tx = app_state.db_pool.begin().await?;
+        sqlx::query(
+            r#"
+            INSERT INTO users (id, email, name, avatar_url, role, email_verified)
+            VALUES ($1, $2, $3, $4, $5, true)
+            "#,
+        )
+        .bind(&user_id)
+        .bind(&identity.email)
+        .bind(&identity.name)
+        .bind(&identity.picture)
+        .bind(identity.role.as_deref().unwrap_or("user"))
+        .execute(&mut *tx)
+        .await?;
+
+        insert_identity(&mut *tx, &user_id, provider, &identity).await?;
+
+        if let (true, Some(code)) = (app_state.config.require_invite_code, invite_code.as_deref()) {
+            auth_handler::redeem_invite_code(&mut tx, code, &user_id).await?;
+        }
+        tx.commit().await?;
+
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&app_state.db_pool)
+            .await?
+    };
+
+    Ok((
+        [(header::SET_COOKIE, state_cookie(&app_state.config, "", 0))],
+        Json(auth_handler::start_session(&app_state, user, &client_info, None).await?),
+    ))
+}
+
+async fn insert_identity<'e, E>(
+    executor: E,
+    user_id: &str,
+    provider: &OidcProvider,
+    identity: &oidc::OidcIdentity,
+) -> Result<(), AppError>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (id, user_id, provider, subject, email, last_login_at)
+        VALUES ($1, $2, $3, $4, $5, strftime('%Y-%m-%d %H:%M:%f', 'now'))
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(&provider.id)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// The original Google endpoints, kept for existing clients.
+pub async fn google_auth_url(
+    State(app_state): State<AppState>,
+    Query(params): Query<OidcAuthQuery>,
+) -> Result<WithStateCookie<Value>, AppError> {
+    oidc_auth_url(State(app_state), Path("google".to_string()), Query(params)).await
+}
+
+pub async fn google_link_url(
+    State(app_state): State<AppState>,
+    claims: Claims,
+) -> Result<WithStateCookie<Value>, AppError> {
+    oidc_link_url(State(app_state), claims, Path("google".to_string())).await
+}
+
+pub async fn google_unlink(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+) -> Result<Json<User>, AppError> {
+    oidc_unlink(State(app_state), claims, client, Path("google".to_string())).await
+}
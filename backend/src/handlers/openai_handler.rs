@@ -0,0 +1,498 @@
+use crate::{
+    auth::Claims,
+    database::{format_db_timestamp, parse_db_timestamp, Chat, Message, UserModel},
+    error::AppError,
+    handlers::llm_handler::{generate_chat_title, resolve_api_keys, with_key_failover},
+    handlers::settings_handler::{self, SystemPrompt},
+    llm::{get_llm_client, routing::Route, GenerationParams, StreamChunk},
+    usage::{record_usage, UsageRecord},
+    AppState,
+};
+use async_stream::stream;
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{Duration, Utc};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// Header alternative to the `neko_save_chat` body field, for tools that only
+// let you configure extra headers.
+const SAVE_CHAT_HEADER: &str = "x-neko-save-chat";
+const CHAT_ID_HEADER: &str = "x-neko-chat-id";
+
+#[derive(Deserialize)]
+pub struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<Value>,
+    #[serde(default)]
+    stream: bool,
+    neko_save_chat: Option<bool>,
+    // temperature, top_p, max_tokens, stop, seed and the penalties, under
+    // their OpenAI names.
+    #[serde(flatten)]
+    params: GenerationParams,
+}
+
+// Errors on the /v1 routes use the OpenAI error envelope so existing clients
+// can surface them.
+pub struct OpenAiError(AppError);
+
+impl From<AppError> for OpenAiError {
+    fn from(e: AppError) -> Self {
+        OpenAiError(e)
+    }
+}
+
+impl IntoResponse for OpenAiError {
+    fn into_response(self) -> Response {
+        let message = self.0.to_string();
+        let status = self.0.into_response().status();
+        let error_type = match status {
+            StatusCode::UNAUTHORIZED => "authentication_error",
+            StatusCode::FORBIDDEN => "permission_error",
+            StatusCode::NOT_FOUND => "not_found_error",
+            StatusCode::TOO_MANY_REQUESTS => "rate_limit_error",
+            s if s.is_client_error() => "invalid_request_error",
+            _ => "api_error",
+        };
+        let body = Json(json!({
+            "error": {
+                "message": message,
+                "type": error_type,
+                "code": status.as_u16(),
+            }
+        }));
+        (status, body).into_response()
+    }
+}
+
+// Model ids are exposed as "provider/model", e.g. "openrouter/anthropic/claude-3.5-sonnet".
+fn split_model(model: &str) -> Result<(&str, &str), AppError> {
+    model
+        .split_once('/')
+        .filter(|(provider, model)| !provider.is_empty() && !model.is_empty())
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "model '{}' must be in the form 'provider/model'",
+                model
+            ))
+        })
+}
+
+// Clients may send content as an array of typed parts; the providers behind
+// `LLMClient` expect plain strings, so only the text parts are kept.
+fn normalize_message(message: &Value) -> Result<Value, AppError> {
+    let role = message
+        .get("role")
+        .and_then(|r| r.as_str())
+        .ok_or_else(|| AppError::BadRequest("every message needs a role".to_string()))?;
+
+    let content = match message.get("content") {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(Value::Null) | None => String::new(),
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "message content must be a string or an array of parts".to_string(),
+            ))
+        }
+    };
+
+    // "developer" is the newer OpenAI name for the system role.
+    let role = if role == "developer" { "system" } else { role };
+    Ok(json!({ "role": role, "content": content }))
+}
+
+fn message_text(message: &Value) -> &str {
+    message
+        .get("content")
+        .and_then(|c| c.as_str())
+        .unwrap_or_default()
+}
+
+fn wants_saved_chat(payload: &ChatCompletionRequest, headers: &HeaderMap) -> bool {
+    payload.neko_save_chat.unwrap_or_else(|| {
+        headers
+            .get(SAVE_CHAT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false)
+    })
+}
+
+// Persists the incoming conversation as a new chat so it shows up in the UI.
+async fn create_saved_chat(
+    pool: &sqlx::SqlitePool,
+    user_id: &str,
+    provider: &str,
+    model: &str,
+    messages: &[Value],
+    params: &GenerationParams,
+) -> Result<Chat, AppError> {
+    let first_user_message = messages
+        .iter()
+        .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))
+        .map(message_text)
+        .unwrap_or_default();
+
+    let mut tx = pool.begin().await?;
+
+    let chat = sqlx::query_as::<_, Chat>(
+        r#"
+        INSERT INTO chats (id, user_id, title, provider, model, generation_params)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(generate_chat_title(first_user_message))
+    .bind(provider)
+    .bind(model)
+    .bind(params.to_column())
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Explicit, strictly increasing timestamps keep the original order even
+    // when several rows are written within the same millisecond.
+    let base = Utc::now();
+    for (i, message) in messages
+        .iter()
+        .filter(|m| m.get("role").and_then(|r| r.as_str()) != Some("system"))
+        .enumerate()
+    {
+        sqlx::query(
+            "INSERT INTO messages (id, chat_id, role, content, created_at, user_id) VALUES ($1, $2, $3, $4, $5, CASE WHEN $3 = 'user' THEN $6 END)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(&chat.id)
+        .bind(message.get("role").and_then(|r| r.as_str()).unwrap_or("user"))
+        .bind(message_text(message))
+        .bind(format_db_timestamp(base + Duration::milliseconds(i as i64)))
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(chat)
+}
+
+async fn save_assistant_reply(
+    app_state: &AppState,
+    chat_id: &str,
+    route: &Route,
+    content: &str,
+    reasoning: Option<&str>,
+) {
+    let result = sqlx::query_as::<_, Message>(
+        "INSERT INTO messages (id, chat_id, role, content, reasoning, provider, model) VALUES ($1, $2, 'assistant', $3, $4, $5, $6) RETURNING *",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(chat_id)
+    .bind(content)
+    .bind(reasoning)
+    .bind(&route.provider)
+    .bind(&route.model)
+    .fetch_one(&app_state.db_pool)
+    .await;
+
+    match result {
+        Ok(message) => {
+            let _ = app_state.tx.send(message);
+        }
+        Err(e) => tracing::error!("failed to save proxied assistant reply: {}", e),
+    }
+}
+
+pub async fn list_models(
+    State(app_state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Value>, OpenAiError> {
+    let models = sqlx::query_as::<_, UserModel>(
+        "SELECT * FROM user_models WHERE user_id = $1 AND is_enabled = true ORDER BY display_order ASC",
+    )
+    .bind(&claims.sub)
+    .fetch_all(&app_state.db_pool)
+    .await
+    .map_err(AppError::from)?;
+
+    let data: Vec<Value> = models
+        .iter()
+        .map(|m| {
+            json!({
+                "id": format!("{}/{}", m.provider, m.model_id),
+                "object": "model",
+                "created": parse_db_timestamp(&m.created_at).map(|t| t.timestamp()).unwrap_or(0),
+                "owned_by": m.provider,
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({ "object": "list", "data": data })))
+}
+
+pub async fn chat_completions(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    headers: HeaderMap,
+    Json(payload): Json<ChatCompletionRequest>,
+) -> Result<Response, OpenAiError> {
+    let user_id = claims.sub;
+    let pool = &app_state.db_pool;
+    let (provider, model) = split_model(&payload.model)?;
+
+    let mut messages = payload
+        .messages
+        .iter()
+        .map(normalize_message)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if messages.is_empty() {
+        return Err(AppError::BadRequest("messages must not be empty".to_string()).into());
+    }
+    payload.params.validate()?;
+    let params = payload
+        .params
+        .clone()
+        .or(&settings_handler::default_generation_params(pool, &user_id).await?);
+
+    // The saved chat gets the client's conversation as normalized, without
+    // the user's own system prompts added below.
+    let conversation = wants_saved_chat(&payload, &headers).then(|| messages.clone());
+
+    // The user's active system prompts go first, ahead of anything the client sent.
+    let active_prompts = sqlx::query_as::<_, SystemPrompt>(
+        "SELECT * FROM system_prompts WHERE user_id = $1 AND is_default = true ORDER BY created_at ASC",
+    )
+    .bind(&user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(AppError::from)?;
+
+    if !active_prompts.is_empty() {
+        let combined = active_prompts
+            .iter()
+            .map(|p| p.prompt.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n\n---\n\n");
+        messages.insert(0, json!({ "role": "system", "content": combined }));
+    }
+
+    let keys = resolve_api_keys(&app_state, &user_id, provider, model, None).await?;
+    get_llm_client(provider, &keys[0].api_key)?;
+
+    let saved_chat = match &conversation {
+        Some(conversation) => Some(
+            create_saved_chat(
+                pool,
+                &user_id,
+                provider,
+                model,
+                conversation,
+                &payload.params,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+
+    let prompt_chars: usize = messages.iter().map(|m| message_text(m).len()).sum();
+    let completion_id = format!("chatcmpl-{}", Uuid::new_v4().simple());
+    let created = Utc::now().timestamp();
+
+    if !payload.stream {
+        let result = with_key_failover(
+            pool,
+            &app_state.config.llm_retry,
+            provider,
+            &keys,
+            |client| {
+                let messages = messages.clone();
+                let model = model.to_string();
+                let params = params.clone();
+                async move { client.chat(&model, messages, &params).await }
+            },
+        )
+        .await;
+
+        // Failed requests are put on the workspace whose key was tried first.
+        let key = result.as_ref().map_or(&keys[0], |(_, key)| *key);
+        record_usage(
+            pool,
+            UsageRecord {
+                user_id: &user_id,
+                workspace_id: key.source.workspace_id(),
+                provider,
+                model,
+                source: "openai_proxy",
+                prompt_chars,
+                completion_chars: result.as_ref().map(|(c, _)| c.len()).unwrap_or(0),
+                success: result.is_ok(),
+            },
+        )
+        .await;
+
+        let (content, _) = result?;
+        if let Some(chat) = &saved_chat {
+            save_assistant_reply(
+                &app_state,
+                &chat.id,
+                &Route::new(provider, model),
+                &content,
+                None,
+            )
+            .await;
+        }
+
+        let mut response = Json(json!({
+            "id": completion_id,
+            "object": "chat.completion",
+            "created": created,
+            "model": payload.model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop",
+            }],
+        }))
+        .into_response();
+        if let Some(chat) = &saved_chat {
+            if let Ok(value) = HeaderValue::from_str(&chat.id) {
+                response.headers_mut().insert(CHAT_ID_HEADER, value);
+            }
+        }
+        return Ok(response);
+    }
+
+    let stream_result = with_key_failover(
+        pool,
+        &app_state.config.llm_retry,
+        provider,
+        &keys,
+        |client| {
+            let messages = messages.clone();
+            let model = model.to_string();
+            let params = params.clone();
+            async move { client.chat_stream(&model, messages, &params).await }
+        },
+    )
+    .await;
+    let (mut llm_stream, workspace_id) = match stream_result {
+        Ok((s, key)) => (s, key.source.workspace_id().map(str::to_string)),
+        Err(e) => {
+            record_usage(
+                pool,
+                UsageRecord {
+                    user_id: &user_id,
+                    workspace_id: keys[0].source.workspace_id(),
+                    provider,
+                    model,
+                    source: "openai_proxy",
+                    prompt_chars,
+                    completion_chars: 0,
+                    success: false,
+                },
+            )
+            .await;
+            return Err(e.into());
+        }
+    };
+
+    let chunk = move |delta: Value, finish_reason: Option<&str>, id: &str, model: &str| {
+        let event = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        format!("data: {}\n\n", event)
+    };
+
+    let chat_id = saved_chat.as_ref().map(|c| c.id.clone());
+    let response_model = payload.model.clone();
+    let provider = provider.to_string();
+    let model = model.to_string();
+    let stream_state = app_state.clone();
+
+    let sse_stream = stream! {
+        yield Ok::<String, AppError>(chunk(json!({ "role": "assistant", "content": "" }), None, &completion_id, &response_model));
+
+        let mut full_response = String::new();
+        let mut full_reasoning = String::new();
+        let mut success = true;
+        while let Some(chunk_result) = llm_stream.next().await {
+            match chunk_result {
+                Ok(StreamChunk::Text(text)) => {
+                    full_response.push_str(&text);
+                    yield Ok(chunk(json!({ "content": text }), None, &completion_id, &response_model));
+                }
+                // Same field the reasoning APIs of xAI and DeepSeek use
+                Ok(StreamChunk::Reasoning(text)) => {
+                    full_reasoning.push_str(&text);
+                    yield Ok(chunk(json!({ "reasoning_content": text }), None, &completion_id, &response_model));
+                }
+                Err(e) => {
+                    tracing::error!("openai proxy streaming error: {}", e);
+                    success = false;
+                    let error = json!({ "error": { "message": e.to_string(), "type": "api_error" } });
+                    yield Ok(format!("data: {}\n\n", error));
+                    break;
+                }
+            }
+        }
+
+        if success {
+            yield Ok(chunk(json!({}), Some("stop"), &completion_id, &response_model));
+        }
+        yield Ok("data: [DONE]\n\n".to_string());
+
+        record_usage(
+            &stream_state.db_pool,
+            UsageRecord {
+                user_id: &user_id,
+                workspace_id: workspace_id.as_deref(),
+                provider: &provider,
+                model: &model,
+                source: "openai_proxy",
+                prompt_chars,
+                completion_chars: full_response.len() + full_reasoning.len(),
+                success,
+            },
+        )
+        .await;
+
+        if let Some(chat_id) = &chat_id {
+            if !full_response.trim().is_empty() {
+                let reasoning = Some(full_reasoning.as_str()).filter(|r| !r.is_empty());
+                save_assistant_reply(&stream_state, chat_id, &Route::new(&provider, &model), &full_response, reasoning).await;
+            }
+        }
+    };
+
+    let mut response = Response::new(Body::from_stream(sse_stream));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/event-stream"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Some(chat) = &saved_chat {
+        if let Ok(value) = HeaderValue::from_str(&chat.id) {
+            headers.insert(CHAT_ID_HEADER, value);
+        }
+    }
+    Ok(response)
+}
@@ -1,9 +1,16 @@
-use crate::{auth::Claims, error::AppError, database::UserModel, llm::{fetch_available_models, NormalizedModel}, AppState};
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{Claims, ClientInfo},
+    database::{serialize_json_text, UserApiKey, UserModel},
+    error::AppError,
+    handlers::{key_handler, llm_handler},
+    llm::{fetch_available_models, params::GeminiSafetySetting, GenerationParams, NormalizedModel},
+    AppState,
+};
 use axum::{extract::State, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 use uuid::Uuid;
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
 
 #[derive(Serialize, Deserialize, sqlx::FromRow)]
 pub struct SystemPrompt {
@@ -45,6 +52,19 @@ pub struct UserSettings {
     pub auto_save: bool,
     pub created_at: String,
     pub updated_at: String,
+    // Try the provider's other keys when one is rate-limited or rejected.
+    pub key_failover: bool,
+    // Default sampling settings for the user's chats, as JSON.
+    #[sqlx(default)]
+    #[serde(serialize_with = "serialize_json_text")]
+    pub generation_params: Option<String>,
+    // Send earlier replies' reasoning back to the model with the history.
+    #[sqlx(default)]
+    pub reasoning_in_history: bool,
+    // Safety settings sent with every Gemini request, as JSON.
+    #[sqlx(default)]
+    #[serde(serialize_with = "serialize_json_text")]
+    pub gemini_safety_settings: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -54,6 +74,12 @@ pub struct UpdateSettingsPayload {
     pub font_size: Option<i32>,
     pub notifications_enabled: Option<bool>,
     pub auto_save: Option<bool>,
+    pub key_failover: Option<bool>,
+    // An empty object clears the defaults.
+    pub generation_params: Option<GenerationParams>,
+    pub reasoning_in_history: Option<bool>,
+    // An empty list goes back to Gemini's defaults.
+    pub gemini_safety_settings: Option<Vec<GeminiSafetySetting>>,
 }
 
 pub async fn get_settings(
@@ -90,11 +116,24 @@ pub async fn get_settings(
 }
 
 pub async fn update_settings(
-    State(pool): State<SqlitePool>,
+    State(app_state): State<AppState>,
     claims: Claims,
+    client: ClientInfo,
     Json(payload): Json<UpdateSettingsPayload>,
 ) -> Result<Json<UserSettings>, AppError> {
+    let pool = &app_state.db_pool;
     let user_id = claims.sub;
+    if let Some(params) = &payload.generation_params {
+        params.validate()?;
+    }
+    if let Some(safety_settings) = &payload.gemini_safety_settings {
+        GeminiSafetySetting::validate_all(safety_settings)?;
+    }
+    let gemini_safety_settings = payload
+        .gemini_safety_settings
+        .as_ref()
+        .filter(|settings| !settings.is_empty())
+        .and_then(|settings| serde_json::to_string(settings).ok());
 
     let settings = sqlx::query_as::<_, UserSettings>(
         r#"
@@ -104,8 +143,12 @@ pub async fn update_settings(
             font_size = COALESCE($3, font_size),
             notifications_enabled = COALESCE($4, notifications_enabled),
             auto_save = COALESCE($5, auto_save),
+            key_failover = COALESCE($6, key_failover),
+            generation_params = CASE WHEN $7 THEN $8 ELSE generation_params END,
+            reasoning_in_history = COALESCE($9, reasoning_in_history),
+            gemini_safety_settings = CASE WHEN $10 THEN $11 ELSE gemini_safety_settings END,
             updated_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
-        WHERE user_id = $6
+        WHERE user_id = $12
         RETURNING *
         "#,
     )
@@ -114,13 +157,53 @@ pub async fn update_settings(
     .bind(payload.font_size)
     .bind(payload.notifications_enabled)
     .bind(payload.auto_save)
-    .bind(user_id)
-    .fetch_one(&pool)
+    .bind(payload.key_failover)
+    .bind(payload.generation_params.is_some())
+    .bind(
+        payload
+            .generation_params
+            .as_ref()
+            .and_then(GenerationParams::to_column),
+    )
+    .bind(payload.reasoning_in_history)
+    .bind(payload.gemini_safety_settings.is_some())
+    .bind(gemini_safety_settings)
+    .bind(&user_id)
+    .fetch_one(pool)
     .await?;
 
+    // Only the key failover switch changes how stored keys get used
+    if let Some(key_failover) = payload.key_failover {
+        AuditEvent::new(audit::SETTINGS_UPDATED)
+            .user(&user_id)
+            .client(&client)
+            .metadata(serde_json::json!({ "key_failover": key_failover }))
+            .record(&app_state)
+            .await?;
+    }
+
     Ok(Json(settings))
 }
 
+// A user's default sampling settings, plus their Gemini safety settings;
+// empty when they have none.
+pub(crate) async fn default_generation_params(
+    pool: &SqlitePool,
+    user_id: &str,
+) -> Result<GenerationParams, AppError> {
+    let stored: Option<(Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT generation_params, gemini_safety_settings FROM user_settings WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    let (params, safety_settings) = stored.unwrap_or_default();
+    Ok(GenerationParams {
+        gemini_safety_settings: safety_settings.and_then(|s| serde_json::from_str(&s).ok()),
+        ..GenerationParams::from_column(params.as_deref())
+    })
+}
+
 pub async fn get_system_prompts(
     State(pool): State<SqlitePool>,
     claims: Claims,
@@ -199,7 +282,7 @@ pub async fn update_system_prompt(
             .await?;
 
     if let Some((owner_id,)) = owner_check {
-        if owner_id != user_id.to_string() {
+        if owner_id != user_id {
             return Err(AppError::Unauthorized);
         }
     } else {
@@ -268,7 +351,7 @@ pub async fn set_active_prompt(
             .await?;
 
     if let Some((owner_id,)) = owner_check {
-        if owner_id != user_id.to_string() {
+        if owner_id != user_id {
             return Err(AppError::Unauthorized);
         }
     } else {
@@ -306,10 +389,10 @@ pub async fn toggle_active_prompt(
             .await?;
 
     if let Some((owner_id, is_currently_active)) = prompt_check {
-        if owner_id != user_id.to_string() {
+        if owner_id != user_id {
             return Err(AppError::Unauthorized);
         }
-        
+
         // Toggle the active state
         let new_state = !is_currently_active;
         sqlx::query("UPDATE system_prompts SET is_default = $1 WHERE id = $2 AND user_id = $3")
@@ -319,9 +402,9 @@ pub async fn toggle_active_prompt(
             .execute(&pool)
             .await?;
 
-        Ok(Json(serde_json::json!({ 
-            "success": true, 
-            "is_active": new_state 
+        Ok(Json(serde_json::json!({
+            "success": true,
+            "is_active": new_state
         })))
     } else {
         Err(AppError::NotFound)
@@ -334,19 +417,21 @@ pub async fn get_api_keys(
 ) -> Result<Json<Vec<serde_json::Value>>, AppError> {
     let user_id = claims.sub;
 
-    let keys = sqlx::query_as::<_, (String, String)>(
-        "SELECT provider, created_at FROM user_api_keys WHERE user_id = $1",
-    )
-    .bind(&user_id)
-    .fetch_all(&pool)
-    .await?;
+    let keys = sqlx::query_as::<_, UserApiKey>("SELECT * FROM user_api_keys WHERE user_id = $1")
+        .bind(&user_id)
+        .fetch_all(&pool)
+        .await?;
 
     let response: Vec<serde_json::Value> = keys
         .iter()
-        .map(|(provider, created_at)| {
+        .map(|key| {
             serde_json::json!({
-                "provider": provider,
-                "created_at": created_at,
+                "provider": key.provider,
+                "key_hint": key.key_hint,
+                "created_at": key.created_at,
+                "last_validated_at": key.last_validated_at,
+                "last_error": key.last_error,
+                "last_used_at": key.last_used_at,
                 "has_key": true
             })
         })
@@ -372,6 +457,8 @@ pub struct UserModelUpdate {
     pub model_name: String,
     pub is_enabled: bool,
     pub display_order: i32,
+    // Keeps the model's current key when omitted.
+    pub api_key_id: Option<String>,
 }
 
 pub async fn fetch_models_for_provider(
@@ -382,26 +469,34 @@ pub async fn fetch_models_for_provider(
     let user_id = claims.sub;
     let pool = &app_state.db_pool;
 
-    // Get the encrypted API key for this provider
-    let api_key_record = sqlx::query_as::<_, (String,)>(
-        "SELECT encrypted_key FROM user_api_keys WHERE user_id = $1 AND provider = $2"
-    )
-    .bind(&user_id)
-    .bind(&payload.provider)
-    .fetch_optional(pool)
-    .await?;
-
-    let encrypted_key = api_key_record
-        .ok_or_else(|| AppError::BadRequest(format!("No API key found for provider '{}'", payload.provider)))?
-        .0;
+    // The first key the user can use for the provider: their own, a
+    // workspace's, or the server's
+    let key = llm_handler::provider_keys(&app_state, &user_id, &payload.provider)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "No API key found for provider '{}'",
+                payload.provider
+            ))
+        })?;
+
+    // Fetch models from the provider; this doubles as a health check of the key
+    let result = fetch_available_models(&payload.provider, &key.api_key).await;
+    key_handler::record_key_validation(pool, &key.source, &key.id, result.as_ref().map(|_| ()))
+        .await?;
 
-    // Decrypt the API key
-    let mc = new_magic_crypt!(&app_state.config.encryption_key, 256);
-    let api_key = mc.decrypt_base64_to_string(&encrypted_key)
-        .map_err(|_| AppError::InternalServerError)?;
+    // A rejected key must not come back as a 401, which the frontend treats as an expired session
+    let mut models = result.map_err(|e| AppError::BadRequest(e.to_string()))?;
 
-    // Fetch models from the provider
-    let models = fetch_available_models(&payload.provider, &api_key).await?;
+    // Only offer the models admins allowed for the shared server key
+    if key.source == key_handler::KeySource::Server {
+        let allowed = key_handler::server_key_models(pool, &payload.provider).await?;
+        if !allowed.is_empty() {
+            models.retain(|m| allowed.contains(&m.id));
+        }
+    }
 
     Ok(Json(models))
 }
@@ -413,7 +508,7 @@ pub async fn get_user_models(
     let user_id = claims.sub;
 
     let models = sqlx::query_as::<_, UserModel>(
-        "SELECT * FROM user_models WHERE user_id = $1 ORDER BY display_order ASC, created_at ASC"
+        "SELECT * FROM user_models WHERE user_id = $1 ORDER BY display_order ASC, created_at ASC",
     )
     .bind(&user_id)
     .fetch_all(&pool)
@@ -432,6 +527,17 @@ pub async fn update_user_model_preferences(
     // Start a transaction
     let mut tx = pool.begin().await?;
 
+    // Remember which key each model is pinned to, so saving the model list doesn't unpin them
+    let pinned_keys: std::collections::HashMap<(String, String), String> = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT provider, model_id, api_key_id FROM user_models WHERE user_id = $1 AND api_key_id IS NOT NULL"
+    )
+    .bind(&user_id)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|(provider, model_id, key_id)| ((provider, model_id), key_id))
+    .collect();
+
     // Clear existing preferences for this user
     sqlx::query("DELETE FROM user_models WHERE user_id = $1")
         .bind(&user_id)
@@ -440,10 +546,15 @@ pub async fn update_user_model_preferences(
 
     // Insert new preferences
     for model in payload.models {
+        let api_key_id = model.api_key_id.clone().or_else(|| {
+            pinned_keys
+                .get(&(model.provider.clone(), model.model_id.clone()))
+                .cloned()
+        });
         sqlx::query(
             r#"
-            INSERT INTO user_models (user_id, provider, model_id, model_name, is_enabled, display_order)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO user_models (user_id, provider, model_id, model_name, is_enabled, display_order, api_key_id)
+            VALUES ($1, $2, $3, $4, $5, $6, NULLIF($7, ''))
             "#
         )
         .bind(&user_id)
@@ -452,6 +563,7 @@ pub async fn update_user_model_preferences(
         .bind(&model.model_name)
         .bind(model.is_enabled)
         .bind(model.display_order)
+        .bind(api_key_id)
         .execute(&mut *tx)
         .await?;
     }
@@ -494,7 +606,7 @@ pub async fn toggle_model_enabled(
 
     // Check if model preference exists
     let existing = sqlx::query_as::<_, UserModel>(
-        "SELECT * FROM user_models WHERE user_id = $1 AND provider = $2 AND model_id = $3"
+        "SELECT * FROM user_models WHERE user_id = $1 AND provider = $2 AND model_id = $3",
     )
     .bind(&user_id)
     .bind(&payload.provider)
@@ -531,13 +643,60 @@ pub async fn toggle_model_enabled(
         true
     };
 
-    tracing::info!("Model toggled: {} {} - enabled: {}", payload.provider, payload.model_id, is_enabled);
-    
-    Ok(Json(serde_json::json!({ 
-        "success": true, 
+    tracing::info!(
+        "Model toggled: {} {} - enabled: {}",
+        payload.provider,
+        payload.model_id,
+        is_enabled
+    );
+
+    Ok(Json(serde_json::json!({
+        "success": true,
         "is_enabled": is_enabled,
         "model_id": payload.model_id,
         "provider": payload.provider
     })))
 }
 
+#[derive(Deserialize)]
+pub struct SetModelKeyPayload {
+    pub provider: String,
+    pub model_id: String,
+    // None unpins the model, so it uses the provider's first key again.
+    pub api_key_id: Option<String>,
+}
+
+pub async fn set_model_key(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Json(payload): Json<SetModelKeyPayload>,
+) -> Result<Json<UserModel>, AppError> {
+    let pool = &app_state.db_pool;
+    let user_id = claims.sub;
+
+    if let Some(key_id) = &payload.api_key_id {
+        key_handler::ensure_key_usable(pool, &user_id, &payload.provider, key_id).await?;
+    }
+
+    let model = sqlx::query_as::<_, UserModel>(
+        "UPDATE user_models SET api_key_id = $1 WHERE user_id = $2 AND provider = $3 AND model_id = $4 RETURNING *"
+    )
+    .bind(&payload.api_key_id)
+    .bind(&user_id)
+    .bind(&payload.provider)
+    .bind(&payload.model_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    AuditEvent::new(audit::API_KEY_PINNED)
+        .user(&user_id)
+        .target(&payload.provider)
+        .client(&client)
+        .metadata(serde_json::json!({ "model_id": payload.model_id, "key_id": payload.api_key_id }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(model))
+}
@@ -0,0 +1,155 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{
+        generate_token, hash_token, Claims, ClientInfo, TokenScope, PERSONAL_ACCESS_TOKEN_PREFIX,
+    },
+    database::{expiry_from_days, ApiToken},
+    error::AppError,
+    AppState,
+};
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateTokenPayload {
+    name: String,
+    scopes: Vec<String>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedTokenResponse {
+    // The plaintext token is only returned once, at creation time.
+    token: String,
+    #[serde(flatten)]
+    record: ApiToken,
+}
+
+pub async fn create_token(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Json(payload): Json<CreateTokenPayload>,
+) -> Result<Json<CreatedTokenResponse>, AppError> {
+    let pool = &app_state.db_pool;
+    let user_id = claims.sub;
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("token name is required".to_string()));
+    }
+
+    if payload.scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "at least one scope is required".to_string(),
+        ));
+    }
+
+    let mut scopes = Vec::new();
+    for scope in &payload.scopes {
+        let parsed = TokenScope::parse(scope).ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "unknown scope '{}'. valid scopes: {}",
+                scope,
+                TokenScope::ALL.map(|s| s.as_str()).join(", ")
+            ))
+        })?;
+        if !scopes.contains(&parsed) {
+            scopes.push(parsed);
+        }
+    }
+
+    let expires_at = expiry_from_days(payload.expires_in_days)?;
+
+    let token = generate_token(PERSONAL_ACCESS_TOKEN_PREFIX);
+    let token_prefix: String = token
+        .chars()
+        .take(PERSONAL_ACCESS_TOKEN_PREFIX.len() + 4)
+        .collect();
+
+    let record = sqlx::query_as::<_, ApiToken>(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&user_id)
+    .bind(name)
+    .bind(hash_token(&token))
+    .bind(token_prefix)
+    .bind(
+        scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await?;
+
+    AuditEvent::new(audit::ACCESS_TOKEN_CREATED)
+        .user(&user_id)
+        .target(&record.id)
+        .client(&client)
+        .metadata(json!({
+            "name": record.name,
+            "scopes": record.scopes,
+            "expires_at": record.expires_at,
+        }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(CreatedTokenResponse { token, record }))
+}
+
+pub async fn list_tokens(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+) -> Result<Json<Vec<ApiToken>>, AppError> {
+    let user_id = claims.sub;
+
+    let tokens = sqlx::query_as::<_, ApiToken>(
+        "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(tokens))
+}
+
+pub async fn revoke_token(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(token_id): Path<String>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    let user_id = claims.sub;
+
+    let result = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(&token_id)
+        .bind(&user_id)
+        .execute(pool)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+
+    AuditEvent::new(audit::ACCESS_TOKEN_REVOKED)
+        .user(&user_id)
+        .target(&token_id)
+        .client(&client)
+        .record(&app_state)
+        .await
+}
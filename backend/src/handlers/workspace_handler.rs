@@ -0,0 +1,649 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{Claims, ClientInfo},
+    database::{Workspace, WorkspaceApiKey},
+    error::AppError,
+    handlers::key_handler::{self, normalize_label, validation_error},
+    llm::validate_api_key,
+    AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{SqliteConnection, SqlitePool};
+use uuid::Uuid;
+
+// Owners manage everything, admins manage members and the shared keys,
+// members can only use the keys.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WorkspaceRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+impl WorkspaceRole {
+    fn parse(role: &str) -> Result<Self, AppError> {
+        match role {
+            "member" => Ok(WorkspaceRole::Member),
+            "admin" => Ok(WorkspaceRole::Admin),
+            "owner" => Ok(WorkspaceRole::Owner),
+            _ => Err(AppError::BadRequest(format!(
+                "unknown workspace role '{}'. valid roles: owner, admin, member",
+                role
+            ))),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            WorkspaceRole::Member => "member",
+            WorkspaceRole::Admin => "admin",
+            WorkspaceRole::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateWorkspacePayload {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateWorkspacePayload {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddMemberPayload {
+    email: String,
+    role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemberPayload {
+    role: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddWorkspaceKeyPayload {
+    provider: String,
+    label: Option<String>,
+    api_key: String,
+    #[serde(default)]
+    validate: bool,
+}
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    days: Option<i64>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceResponse {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    workspace: Workspace,
+    role: String,
+    member_count: i64,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceMemberResponse {
+    user_id: String,
+    name: String,
+    email: String,
+    role: String,
+    created_at: String,
+}
+
+// What members see of a shared key: enough to pick it, never the key itself.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceKeyResponse {
+    id: String,
+    provider: String,
+    label: String,
+    priority: i64,
+    key_hint: Option<String>,
+    created_at: String,
+    last_validated_at: Option<String>,
+    last_error: Option<String>,
+    last_used_at: Option<String>,
+}
+
+impl From<WorkspaceApiKey> for WorkspaceKeyResponse {
+    fn from(key: WorkspaceApiKey) -> Self {
+        Self {
+            id: key.id,
+            provider: key.provider,
+            label: key.label,
+            priority: key.priority,
+            key_hint: key.key_hint,
+            created_at: key.created_at,
+            last_validated_at: key.last_validated_at,
+            last_error: key.last_error,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceUsageRow {
+    user_id: String,
+    name: Option<String>,
+    email: Option<String>,
+    provider: String,
+    model: String,
+    requests: i64,
+    failed_requests: i64,
+    prompt_chars: i64,
+    completion_chars: i64,
+}
+
+const WORKSPACE_SELECT: &str = r#"
+    SELECT workspaces.*, m.role,
+        (SELECT COUNT(*) FROM workspace_members WHERE workspace_id = workspaces.id) AS member_count
+    FROM workspaces
+    JOIN workspace_members m ON m.workspace_id = workspaces.id
+"#;
+
+// Fails with NotFound for non-members, so workspace ids can't be probed.
+async fn require_role(
+    pool: &SqlitePool,
+    workspace_id: &str,
+    user_id: &str,
+    required: WorkspaceRole,
+) -> Result<WorkspaceRole, AppError> {
+    let role = sqlx::query_scalar::<_, String>(
+        "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound)?;
+    let role = WorkspaceRole::parse(&role)?;
+
+    if role < required {
+        return Err(AppError::Forbidden(format!(
+            "this requires the {} role in the workspace",
+            required.as_str()
+        )));
+    }
+    Ok(role)
+}
+
+async fn fetch_workspace(
+    pool: &SqlitePool,
+    workspace_id: &str,
+    user_id: &str,
+) -> Result<WorkspaceResponse, AppError> {
+    sqlx::query_as::<_, WorkspaceResponse>(&format!(
+        "{} WHERE workspaces.id = $1 AND m.user_id = $2",
+        WORKSPACE_SELECT
+    ))
+    .bind(workspace_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::NotFound)
+}
+
+async fn member_ids(pool: &SqlitePool, workspace_id: &str) -> Result<Vec<String>, AppError> {
+    Ok(sqlx::query_scalar::<_, String>(
+        "SELECT user_id FROM workspace_members WHERE workspace_id = $1",
+    )
+    .bind(workspace_id)
+    .fetch_all(pool)
+    .await?)
+}
+
+// Clears pins to workspace keys the given users can no longer use.
+async fn unpin_for(conn: &mut SqliteConnection, user_ids: &[String]) -> Result<(), AppError> {
+    for user_id in user_ids {
+        key_handler::unpin_unusable_keys(conn, user_id).await?;
+    }
+    Ok(())
+}
+
+// A workspace always keeps at least one owner.
+async fn ensure_other_owner(
+    pool: &SqlitePool,
+    workspace_id: &str,
+    user_id: &str,
+) -> Result<(), AppError> {
+    let owners = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM workspace_members WHERE workspace_id = $1 AND role = 'owner' AND user_id != $2",
+    )
+    .bind(workspace_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    if owners == 0 {
+        return Err(AppError::BadRequest(
+            "a workspace needs at least one owner".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn create_workspace(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Json(payload): Json<CreateWorkspacePayload>,
+) -> Result<Json<WorkspaceResponse>, AppError> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name is required".to_string()));
+    }
+
+    let workspace_id = Uuid::new_v4().to_string();
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO workspaces (id, name, created_by) VALUES ($1, $2, $3)")
+        .bind(&workspace_id)
+        .bind(name)
+        .bind(&claims.sub)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, 'owner')",
+    )
+    .bind(&workspace_id)
+    .bind(&claims.sub)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(
+        fetch_workspace(&pool, &workspace_id, &claims.sub).await?,
+    ))
+}
+
+pub async fn list_workspaces(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+) -> Result<Json<Vec<WorkspaceResponse>>, AppError> {
+    let workspaces = sqlx::query_as::<_, WorkspaceResponse>(&format!(
+        "{} WHERE m.user_id = $1 ORDER BY m.created_at ASC",
+        WORKSPACE_SELECT
+    ))
+    .bind(&claims.sub)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(workspaces))
+}
+
+pub async fn get_workspace(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+) -> Result<Json<WorkspaceResponse>, AppError> {
+    Ok(Json(
+        fetch_workspace(&pool, &workspace_id, &claims.sub).await?,
+    ))
+}
+
+pub async fn update_workspace(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+    Json(payload): Json<UpdateWorkspacePayload>,
+) -> Result<Json<WorkspaceResponse>, AppError> {
+    require_role(&pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("name cannot be empty".to_string()));
+    }
+
+    sqlx::query("UPDATE workspaces SET name = $1 WHERE id = $2")
+        .bind(name)
+        .bind(&workspace_id)
+        .execute(&pool)
+        .await?;
+
+    Ok(Json(
+        fetch_workspace(&pool, &workspace_id, &claims.sub).await?,
+    ))
+}
+
+pub async fn delete_workspace(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+) -> Result<(), AppError> {
+    require_role(&pool, &workspace_id, &claims.sub, WorkspaceRole::Owner).await?;
+    let members = member_ids(&pool, &workspace_id).await?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM workspaces WHERE id = $1")
+        .bind(&workspace_id)
+        .execute(&mut *tx)
+        .await?;
+
+    unpin_for(&mut tx, &members).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list_members(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+) -> Result<Json<Vec<WorkspaceMemberResponse>>, AppError> {
+    require_role(&pool, &workspace_id, &claims.sub, WorkspaceRole::Member).await?;
+
+    let members = sqlx::query_as::<_, WorkspaceMemberResponse>(
+        r#"
+        SELECT m.user_id, users.name, users.email, m.role, m.created_at
+        FROM workspace_members m
+        JOIN users ON users.id = m.user_id
+        WHERE m.workspace_id = $1
+        ORDER BY m.created_at ASC
+        "#,
+    )
+    .bind(&workspace_id)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(members))
+}
+
+pub async fn add_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(workspace_id): Path<String>,
+    Json(payload): Json<AddMemberPayload>,
+) -> Result<Json<WorkspaceMemberResponse>, AppError> {
+    let pool = &app_state.db_pool;
+    let own_role = require_role(pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let role = WorkspaceRole::parse(payload.role.as_deref().unwrap_or("member"))?;
+    if role > own_role {
+        return Err(AppError::Forbidden(
+            "you cannot grant a role above your own".to_string(),
+        ));
+    }
+
+    let user_id = sqlx::query_scalar::<_, String>("SELECT id FROM users WHERE email = $1")
+        .bind(payload.email.trim())
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("no user with this email address".to_string()))?;
+
+    let result = sqlx::query(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(&workspace_id)
+    .bind(&user_id)
+    .bind(role.as_str())
+    .execute(pool)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest(
+            "this user is already a member".to_string(),
+        ));
+    }
+
+    AuditEvent::new(audit::WORKSPACE_MEMBER_ADDED)
+        .user(&claims.sub)
+        .target(&workspace_id)
+        .client(&client)
+        .metadata(json!({ "user_id": user_id, "role": role.as_str() }))
+        .record(&app_state)
+        .await?;
+
+    let member = sqlx::query_as::<_, WorkspaceMemberResponse>(
+        r#"
+        SELECT m.user_id, users.name, users.email, m.role, m.created_at
+        FROM workspace_members m
+        JOIN users ON users.id = m.user_id
+        WHERE m.workspace_id = $1 AND m.user_id = $2
+        "#,
+    )
+    .bind(&workspace_id)
+    .bind(&user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Json(member))
+}
+
+pub async fn update_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path((workspace_id, user_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateMemberPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let pool = &app_state.db_pool;
+    let own_role = require_role(pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let role = WorkspaceRole::parse(&payload.role)?;
+    let current = require_role(pool, &workspace_id, &user_id, WorkspaceRole::Member).await?;
+
+    // Admins can't touch owners or hand out ownership.
+    if role.max(current) > own_role {
+        return Err(AppError::Forbidden(
+            "only owners can change owner roles".to_string(),
+        ));
+    }
+    if current == WorkspaceRole::Owner && role != WorkspaceRole::Owner {
+        ensure_other_owner(pool, &workspace_id, &user_id).await?;
+    }
+
+    sqlx::query("UPDATE workspace_members SET role = $1 WHERE workspace_id = $2 AND user_id = $3")
+        .bind(role.as_str())
+        .bind(&workspace_id)
+        .bind(&user_id)
+        .execute(pool)
+        .await?;
+
+    AuditEvent::new(audit::WORKSPACE_MEMBER_UPDATED)
+        .user(&claims.sub)
+        .target(&workspace_id)
+        .client(&client)
+        .metadata(json!({ "user_id": user_id, "from": current.as_str(), "role": role.as_str() }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(json!({ "userId": user_id, "role": role.as_str() })))
+}
+
+// Admins remove members; anyone can leave a workspace on their own.
+pub async fn remove_member(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path((workspace_id, user_id)): Path<(String, String)>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    let own_role = require_role(pool, &workspace_id, &claims.sub, WorkspaceRole::Member).await?;
+    let current = require_role(pool, &workspace_id, &user_id, WorkspaceRole::Member).await?;
+
+    if user_id != claims.sub && (own_role < WorkspaceRole::Admin || current > own_role) {
+        return Err(AppError::Forbidden(
+            "you cannot remove this member".to_string(),
+        ));
+    }
+    if current == WorkspaceRole::Owner {
+        ensure_other_owner(pool, &workspace_id, &user_id).await?;
+    }
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2")
+        .bind(&workspace_id)
+        .bind(&user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    unpin_for(&mut tx, std::slice::from_ref(&user_id)).await?;
+
+    AuditEvent::new(audit::WORKSPACE_MEMBER_REMOVED)
+        .user(&claims.sub)
+        .target(&workspace_id)
+        .client(&client)
+        .metadata(json!({ "user_id": user_id, "role": current.as_str() }))
+        .record_in(&app_state, &mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn list_workspace_keys(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+) -> Result<Json<Vec<WorkspaceKeyResponse>>, AppError> {
+    require_role(&pool, &workspace_id, &claims.sub, WorkspaceRole::Member).await?;
+
+    let keys = sqlx::query_as::<_, WorkspaceApiKey>(
+        "SELECT * FROM workspace_api_keys WHERE workspace_id = $1 ORDER BY provider, priority ASC, created_at ASC",
+    )
+    .bind(&workspace_id)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(
+        keys.into_iter().map(WorkspaceKeyResponse::from).collect(),
+    ))
+}
+
+pub async fn add_workspace_key(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path(workspace_id): Path<String>,
+    Json(payload): Json<AddWorkspaceKeyPayload>,
+) -> Result<Json<WorkspaceKeyResponse>, AppError> {
+    let pool = &app_state.db_pool;
+    require_role(pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let api_key = payload.api_key.trim();
+    let label = normalize_label(payload.label.as_deref())?;
+
+    if payload.validate {
+        if let Err(e) = validate_api_key(&payload.provider, api_key).await {
+            return Err(AppError::BadRequest(format!(
+                "{} rejected the key: {}",
+                payload.provider,
+                validation_error(&e)
+            )));
+        }
+    }
+
+    // Same upsert as personal keys: replacing a label keeps the key's id.
+    let key = sqlx::query_as::<_, WorkspaceApiKey>(
+        r#"
+        INSERT INTO workspace_api_keys (id, workspace_id, provider, label, priority, encrypted_key, key_hint, added_by, last_validated_at)
+        VALUES (
+            $1, $2, $3, $4,
+            (SELECT COALESCE(MAX(priority) + 1, 0) FROM workspace_api_keys WHERE workspace_id = $2 AND provider = $3),
+            $5, $6, $7,
+            CASE WHEN $8 THEN strftime('%Y-%m-%d %H:%M:%f', 'now') END
+        )
+        ON CONFLICT(workspace_id, provider, label) DO UPDATE SET
+            encrypted_key = excluded.encrypted_key,
+            key_hint = excluded.key_hint,
+            added_by = excluded.added_by,
+            created_at = strftime('%Y-%m-%d %H:%M:%f', 'now'),
+            last_validated_at = excluded.last_validated_at,
+            last_error = NULL,
+            last_used_at = NULL
+        RETURNING *
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&workspace_id)
+    .bind(&payload.provider)
+    .bind(&label)
+    .bind(app_state.keyring.encrypt(api_key))
+    .bind(key_handler::key_hint(api_key))
+    .bind(&claims.sub)
+    .bind(payload.validate)
+    .fetch_one(pool)
+    .await?;
+
+    AuditEvent::new(audit::WORKSPACE_KEY_ADDED)
+        .user(&claims.sub)
+        .target(&workspace_id)
+        .client(&client)
+        .metadata(json!({ "key_id": key.id, "provider": key.provider, "label": key.label }))
+        .record(&app_state)
+        .await?;
+
+    Ok(Json(key.into()))
+}
+
+pub async fn delete_workspace_key(
+    State(app_state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Path((workspace_id, key_id)): Path<(String, String)>,
+) -> Result<(), AppError> {
+    let pool = &app_state.db_pool;
+    require_role(pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let members = member_ids(pool, &workspace_id).await?;
+
+    let mut tx = pool.begin().await?;
+    let key = sqlx::query_as::<_, WorkspaceApiKey>(
+        "DELETE FROM workspace_api_keys WHERE id = $1 AND workspace_id = $2 RETURNING *",
+    )
+    .bind(&key_id)
+    .bind(&workspace_id)
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::NotFound)?;
+
+    unpin_for(&mut tx, &members).await?;
+
+    AuditEvent::new(audit::WORKSPACE_KEY_DELETED)
+        .user(&claims.sub)
+        .target(&workspace_id)
+        .client(&client)
+        .metadata(json!({ "key_id": key.id, "provider": key.provider, "label": key.label }))
+        .record_in(&app_state, &mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Per-member, per-model totals for requests made with the workspace's keys.
+pub async fn get_workspace_usage(
+    State(pool): State<SqlitePool>,
+    claims: Claims,
+    Path(workspace_id): Path<String>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<Vec<WorkspaceUsageRow>>, AppError> {
+    require_role(&pool, &workspace_id, &claims.sub, WorkspaceRole::Admin).await?;
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+
+    let rows = sqlx::query_as::<_, WorkspaceUsageRow>(
+        r#"
+        SELECT u.user_id, users.name, users.email, u.provider, u.model,
+            COUNT(*) AS requests,
+            SUM(CASE WHEN u.success THEN 0 ELSE 1 END) AS failed_requests,
+            SUM(u.prompt_chars) AS prompt_chars,
+            SUM(u.completion_chars) AS completion_chars
+        FROM usage_logs u
+        LEFT JOIN users ON users.id = u.user_id
+        WHERE u.workspace_id = $1
+            AND u.created_at >= strftime('%Y-%m-%d %H:%M:%f', 'now', '-' || $2 || ' days')
+        GROUP BY u.user_id, u.provider, u.model
+        ORDER BY requests DESC
+        "#,
+    )
+    .bind(&workspace_id)
+    .bind(days)
+    .fetch_all(&pool)
+    .await?;
+
+    Ok(Json(rows))
+}
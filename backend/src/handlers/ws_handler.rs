@@ -1,13 +1,16 @@
 use crate::{auth, error::AppError, AppState};
 use axum::{
     extract::{
-        ws::{Message as WsMessage, WebSocket},
+        ws::{close_code, CloseFrame, Message as WsMessage, WebSocket},
         Query, State, WebSocketUpgrade,
     },
     response::IntoResponse,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::Deserialize;
+use std::time::Duration;
+
+// How often an idle socket re-checks that its session is still active.
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(15);
 
 #[derive(Deserialize)]
 pub struct WsAuthQuery {
@@ -15,12 +18,11 @@ pub struct WsAuthQuery {
 }
 
 // Authenticates the WebSocket connection via a query parameter token.
-async fn authenticate_ws(app_state: &AppState, token: &str) -> Result<String, AppError> {
-    let decoding_key = DecodingKey::from_secret(app_state.config.jwt_secret.as_ref());
-    let validation = Validation::default();
-    let token_data = decode::<auth::Claims>(token, &decoding_key, &validation)
-        .map_err(|_| AppError::Unauthorized)?;
-    Ok(token_data.claims.sub)
+// Returns the user and session ids.
+async fn authenticate_ws(app_state: &AppState, token: &str) -> Result<(String, String), AppError> {
+    let claims = auth::decode_access_token(app_state, token).await?;
+    let session_id = claims.sid.ok_or(AppError::Unauthorized)?;
+    Ok((claims.sub, session_id))
 }
 
 pub async fn websocket_handler(
@@ -28,29 +30,59 @@ pub async fn websocket_handler(
     State(state): State<AppState>,
     Query(auth): Query<WsAuthQuery>,
 ) -> impl IntoResponse {
-    let user_id = match authenticate_ws(&state, &auth.token).await {
-        Ok(id) => id,
+    let (user_id, session_id) = match authenticate_ws(&state, &auth.token).await {
+        Ok(ids) => ids,
         Err(e) => return e.into_response(),
     };
 
-    ws.on_upgrade(move |socket| handle_socket(socket, state, user_id))
+    ws.on_upgrade(move |socket| handle_socket(socket, state, user_id, session_id))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: AppState, user_id: String) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: AppState,
+    user_id: String,
+    session_id: String,
+) {
     let mut rx = state.tx.subscribe();
+    let mut session_check = tokio::time::interval(SESSION_CHECK_INTERVAL);
 
     // This task listens for new messages on the broadcast channel
-    // and sends them to the client if they belong to the user.
+    // and sends them to the client if the user owns the chat or it has been
+    // shared with them. The session is checked again before every message and
+    // on a timer, so logging out or revoking it closes the socket.
     tokio::spawn(async move {
-        while let Ok(msg) = rx.recv().await {
-            let chat_owner_id: Result<(String,), sqlx::Error> =
-                sqlx::query_as("SELECT user_id FROM chats WHERE id = $1")
-                    .bind(&msg.chat_id)
-                    .fetch_one(&state.db_pool)
+        loop {
+            let msg = tokio::select! {
+                received = rx.recv() => match received {
+                    Ok(msg) => Some(msg),
+                    Err(_) => break,
+                },
+                _ = session_check.tick() => None,
+            };
+
+            let active = auth::session_is_active(&state.db_pool, &session_id, &user_id).await;
+            if !matches!(active, Ok(true)) {
+                let _ = socket
+                    .send(WsMessage::Close(Some(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "session ended".into(),
+                    })))
                     .await;
+                break;
+            }
+            let Some(msg) = msg else { continue };
+
+            let participant: Result<bool, sqlx::Error> = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM chats WHERE id = $1 AND user_id = $2 UNION ALL SELECT 1 FROM chat_members WHERE chat_id = $1 AND user_id = $2)",
+            )
+            .bind(&msg.chat_id)
+            .bind(&user_id)
+            .fetch_one(&state.db_pool)
+            .await;
 
-            if let Ok((owner_id,)) = chat_owner_id {
-                if owner_id == user_id {
+            if let Ok(is_participant) = participant {
+                if is_participant {
                     let payload = serde_json::to_string(&msg).unwrap();
                     if socket.send(WsMessage::Text(payload)).await.is_err() {
                         // Client disconnected
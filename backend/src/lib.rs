@@ -0,0 +1,69 @@
+pub mod audit;
+pub mod auth;
+pub mod config;
+pub mod crypto;
+pub mod database;
+pub mod error;
+pub mod handlers;
+pub mod llm;
+pub mod mailer;
+pub mod oidc;
+pub mod rate_limit;
+pub mod routes;
+pub mod totp;
+pub mod usage;
+
+use axum::extract::FromRef;
+use config::Config;
+use database::Message;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Clone)]
+pub struct AppState {
+    db_pool: SqlitePool,
+    config: Config,
+    tx: broadcast::Sender<Message>,
+    mailer: Arc<dyn mailer::Mailer>,
+    rate_limits: Arc<rate_limit::RateLimits>,
+    oidc: Arc<oidc::OidcClient>,
+    keyring: Arc<crypto::Keyring>,
+    audit_sink: Option<Arc<audit::FileSink>>,
+}
+
+impl AppState {
+    // Everything the handlers share, set up from the config. The pool must
+    // already have the current schema (see database::init_schema).
+    pub fn new(db_pool: SqlitePool, config: Config, keyring: Arc<crypto::Keyring>) -> Self {
+        let (tx, _) = broadcast::channel::<Message>(100);
+        Self {
+            db_pool,
+            tx,
+            mailer: mailer::from_config(&config),
+            rate_limits: Arc::new(rate_limit::RateLimits::from_config(&config)),
+            oidc: Arc::new(oidc::OidcClient::new()),
+            keyring,
+            audit_sink: audit::sink_from_config(&config),
+            config,
+        }
+    }
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(app_state: &AppState) -> SqlitePool {
+        app_state.db_pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(app_state: &AppState) -> Config {
+        app_state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for broadcast::Sender<Message> {
+    fn from_ref(app_state: &AppState) -> broadcast::Sender<Message> {
+        app_state.tx.clone()
+    }
+}
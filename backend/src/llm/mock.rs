@@ -0,0 +1,235 @@
+use super::{GenerationParams, LLMClient, StreamChunk};
+use crate::error::AppError;
+use async_trait::async_trait;
+use futures_util::Stream;
+use serde_json::Value;
+use std::pin::Pin;
+use std::time::Duration;
+
+pub const MOCK_PROVIDER: &str = "mock";
+
+// What the mock provider should do, read from the model name as
+// `;`-separated directives:
+//   echo              answer with the last user message (the default)
+//   text=<answer>     answer with fixed text; takes the rest of the name, so
+//                     it has to come last and may itself contain `;`
+//   reasoning=<text>  stream this as thinking before the answer
+//   delay=<ms>        wait before each chunk
+//   error=<status>    fail with this HTTP status before any output
+//   fail_after=<n>    fail mid-stream after n chunks of the answer
+// e.g. "delay=20;fail_after=3;text=one two three four".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MockScript {
+    pub text: Option<String>,
+    pub reasoning: Option<String>,
+    pub delay: Duration,
+    pub error: Option<u16>,
+    pub fail_after: Option<usize>,
+}
+
+impl MockScript {
+    pub fn parse(model: &str) -> Self {
+        let mut script = Self::default();
+        let mut rest = model;
+        while !rest.is_empty() {
+            if let Some(text) = rest.strip_prefix("text=") {
+                script.text = Some(text.to_string());
+                break;
+            }
+            let (directive, next) = rest.split_once(';').unwrap_or((rest, ""));
+            rest = next;
+            let (key, value) = directive.split_once('=').unwrap_or((directive, ""));
+            match key.trim() {
+                "echo" => script.text = None,
+                "reasoning" => script.reasoning = Some(value.to_string()),
+                "delay" => script.delay = Duration::from_millis(value.parse().unwrap_or(0)),
+                "error" => script.error = value.parse().ok(),
+                "fail_after" => script.fail_after = value.parse().ok(),
+                _ => {}
+            }
+        }
+        script
+    }
+
+    fn answer(&self, messages: &[Value]) -> String {
+        if let Some(text) = &self.text {
+            return text.clone();
+        }
+        messages
+            .iter()
+            .rev()
+            .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))
+            .and_then(|m| m.get("content"))
+            .and_then(|c| c.as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn scripted_error(&self) -> Result<(), AppError> {
+        match self.error {
+            Some(status) => Err(AppError::LLMProviderError {
+                provider: "Mock".to_string(),
+                status_code: Some(status),
+                message: "scripted failure".to_string(),
+                retry_after: None,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+// Chunks the way providers do: a word at a time, whitespace kept.
+fn words(text: &str) -> Vec<String> {
+    text.split_inclusive(' ').map(str::to_string).collect()
+}
+
+// A provider that never leaves the process, for tests and local development.
+// Only reachable when ENABLE_MOCK_PROVIDER is set (see llm_handler::provider_keys).
+pub struct MockClient;
+
+#[async_trait]
+impl LLMClient for MockClient {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        _params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        let script = MockScript::parse(model);
+        script.scripted_error()?;
+        tokio::time::sleep(script.delay).await;
+        Ok(script.answer(&messages))
+    }
+
+    async fn chat_stream(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        _params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        let script = MockScript::parse(model);
+        script.scripted_error()?;
+        let reasoning = script.reasoning.as_deref().map(words).unwrap_or_default();
+        let answer = words(&script.answer(&messages));
+
+        Ok(Box::pin(async_stream::stream! {
+            for chunk in reasoning {
+                tokio::time::sleep(script.delay).await;
+                yield Ok(StreamChunk::Reasoning(chunk));
+            }
+            for (sent, chunk) in answer.into_iter().enumerate() {
+                if script.fail_after == Some(sent) {
+                    yield Err(AppError::LLMProviderError {
+                        provider: "Mock".to_string(),
+                        status_code: None,
+                        message: format!("scripted failure after {} chunks", sent),
+                        retry_after: None,
+                    });
+                    return;
+                }
+                tokio::time::sleep(script.delay).await;
+                yield Ok(StreamChunk::Text(chunk));
+            }
+        }))
+    }
+
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        self.chat(model, messages, params).await
+    }
+
+    async fn chat_stream_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        self.chat_stream(model, messages, params).await
+    }
+
+    fn supports_web_search(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    #[test]
+    fn parses_scripts() {
+        assert_eq!(MockScript::parse(""), MockScript::default());
+        assert_eq!(MockScript::parse("echo"), MockScript::default());
+        assert_eq!(
+            MockScript::parse("delay=5;error=503;fail_after=2;reasoning=hmm;text=a;b=c"),
+            MockScript {
+                text: Some("a;b=c".to_string()),
+                reasoning: Some("hmm".to_string()),
+                delay: Duration::from_millis(5),
+                error: Some(503),
+                fail_after: Some(2),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn echoes_last_user_message() {
+        let messages = vec![
+            json!({ "role": "user", "content": "first" }),
+            json!({ "role": "assistant", "content": "reply" }),
+            json!({ "role": "user", "content": "second" }),
+        ];
+        let reply = MockClient
+            .chat("echo", messages, &GenerationParams::default())
+            .await
+            .unwrap();
+        assert_eq!(reply, "second");
+    }
+
+    #[tokio::test]
+    async fn streams_words_then_fails_when_scripted() {
+        let stream = MockClient
+            .chat_stream(
+                "reasoning=so;fail_after=2;text=one two three",
+                vec![],
+                &GenerationParams::default(),
+            )
+            .await
+            .unwrap();
+        let chunks: Vec<Result<StreamChunk, AppError>> = stream.collect().await;
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(
+            chunks[0].as_ref().unwrap(),
+            &StreamChunk::Reasoning("so".to_string())
+        );
+        assert_eq!(
+            chunks[1].as_ref().unwrap(),
+            &StreamChunk::Text("one ".to_string())
+        );
+        assert_eq!(
+            chunks[2].as_ref().unwrap(),
+            &StreamChunk::Text("two ".to_string())
+        );
+        assert!(chunks[3].is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_before_output_when_scripted() {
+        let result = MockClient
+            .chat_stream("error=429", vec![], &GenerationParams::default())
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::LLMProviderError {
+                status_code: Some(429),
+                ..
+            })
+        ));
+    }
+}
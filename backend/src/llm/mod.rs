@@ -6,23 +6,60 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::pin::Pin;
 
+pub mod mock;
+pub mod params;
+pub mod routing;
+pub mod sse;
+
+pub use params::GenerationParams;
+use params::OpenAiDialect;
+
+// A piece of a streamed reply. Reasoning ("thinking") text is kept apart from
+// the answer so it can be shown and stored on its own.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StreamChunk {
+    Text(String),
+    Reasoning(String),
+}
+
+impl StreamChunk {
+    pub fn text(&self) -> &str {
+        match self {
+            StreamChunk::Text(text) | StreamChunk::Reasoning(text) => text,
+        }
+    }
+}
+
 #[async_trait]
 pub trait LLMClient: Send + Sync {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError>;
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError>;
 
     async fn chat_stream(
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError>;
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError>;
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError>;
+    #[allow(dead_code)]
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError>;
 
     async fn chat_stream_with_web_search(
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError>;
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError>;
 
     fn supports_web_search(&self) -> bool;
 }
@@ -34,6 +71,7 @@ pub fn get_llm_client(provider: &str, api_key: &str) -> Result<Box<dyn LLMClient
         "openrouter" => Ok(Box::new(OpenRouterClient::new(api_key))),
         "xai" => Ok(Box::new(XaiClient::new(api_key))),
         "gemini" => Ok(Box::new(GeminiClient::new(api_key))),
+        mock::MOCK_PROVIDER => Ok(Box::new(mock::MockClient)),
         _ => Err(AppError::BadRequest(format!(
             "provider '{}' is not supported.",
             provider
@@ -58,6 +96,74 @@ pub async fn fetch_available_models(
     }
 }
 
+// Cheapest authenticated call for each provider, used to check a key before it
+// is saved. OpenRouter serves its model list without auth, so its key endpoint
+// is asked instead.
+pub async fn validate_api_key(provider: &str, api_key: &str) -> Result<(), AppError> {
+    if provider != "openrouter" {
+        return fetch_available_models(provider, api_key).await.map(|_| ());
+    }
+
+    let response = Client::new()
+        .get("https://openrouter.ai/api/v1/key")
+        .bearer_auth(api_key)
+        .send()
+        .await
+        .map_err(|e| models_request_failed("openrouter", e))?;
+
+    if !response.status().is_success() {
+        return Err(provider_error("openrouter", response).await);
+    }
+    Ok(())
+}
+
+fn models_request_failed(provider: &str, error: reqwest::Error) -> AppError {
+    tracing::warn!("{} model listing request failed: {}", provider, error);
+    AppError::LLMProviderError {
+        provider: provider.to_string(),
+        status_code: None,
+        message: "could not reach the provider".to_string(),
+        retry_after: None,
+    }
+}
+
+// Keeps the upstream status and message so callers can tell a rejected key
+// from an outage.
+async fn provider_error(provider: &str, response: reqwest::Response) -> AppError {
+    let status = response.status().as_u16();
+    let retry_after = routing::retry_after(response.headers());
+    let error_text = response.text().await.unwrap_or_default();
+    tracing::error!(
+        "{} api error: status {}, body: {}",
+        provider,
+        status,
+        error_text
+    );
+
+    let message = serde_json::from_str::<Value>(&error_text)
+        .ok()
+        .and_then(|body| {
+            let error = body.get("error")?;
+            error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .or_else(|| error.as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| match status {
+            401 | 403 => "Invalid API key".to_string(),
+            429 => "Rate limit exceeded".to_string(),
+            _ => format!("HTTP {}", status),
+        });
+
+    AppError::LLMProviderError {
+        provider: provider.to_string(),
+        status_code: Some(status),
+        message,
+        retry_after,
+    }
+}
+
 async fn fetch_openai_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppError> {
     let client = Client::new();
     let response = client
@@ -65,10 +171,10 @@ async fn fetch_openai_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppE
         .bearer_auth(api_key)
         .send()
         .await
-        .map_err(|_| AppError::InternalServerError)?;
+        .map_err(|e| models_request_failed("openai", e))?;
 
     if !response.status().is_success() {
-        return Err(AppError::InternalServerError);
+        return Err(provider_error("openai", response).await);
     }
 
     let models_response: ModelsResponse = response
@@ -117,7 +223,7 @@ async fn fetch_openai_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppE
         })
         .collect();
 
-    normalized_models.sort_by(|a, b| b.created.cmp(&a.created));
+    normalized_models.sort_by_key(|m| std::cmp::Reverse(m.created));
     Ok(normalized_models)
 }
 
@@ -129,10 +235,10 @@ async fn fetch_anthropic_models(api_key: &str) -> Result<Vec<NormalizedModel>, A
         .header("anthropic-version", "2023-06-01")
         .send()
         .await
-        .map_err(|_| AppError::InternalServerError)?;
+        .map_err(|e| models_request_failed("anthropic", e))?;
 
     if !response.status().is_success() {
-        return Err(AppError::InternalServerError);
+        return Err(provider_error("anthropic", response).await);
     }
 
     let models_response: AnthropicModelsResponse = response
@@ -170,10 +276,10 @@ async fn fetch_xai_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppErro
         .bearer_auth(api_key)
         .send()
         .await
-        .map_err(|_| AppError::InternalServerError)?;
+        .map_err(|e| models_request_failed("xai", e))?;
 
     if !response.status().is_success() {
-        return Err(AppError::InternalServerError);
+        return Err(provider_error("xai", response).await);
     }
 
     let models_response: ModelsResponse = response
@@ -200,16 +306,16 @@ async fn fetch_xai_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppErro
 async fn fetch_gemini_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppError> {
     let client = Client::new();
     let response = client
-        .get(&format!(
+        .get(format!(
             "https://generativelanguage.googleapis.com/v1beta/models?key={}",
             api_key
         ))
         .send()
         .await
-        .map_err(|_| AppError::InternalServerError)?;
+        .map_err(|e| models_request_failed("gemini", e))?;
 
     if !response.status().is_success() {
-        return Err(AppError::InternalServerError);
+        return Err(provider_error("gemini", response).await);
     }
 
     let models_response: GeminiModelsResponse = response
@@ -229,7 +335,7 @@ async fn fetch_gemini_models(api_key: &str) -> Result<Vec<NormalizedModel>, AppE
             let model_id = model
                 .name
                 .split('/')
-                .last()
+                .next_back()
                 .unwrap_or(&model.name)
                 .to_string();
             NormalizedModel {
@@ -256,10 +362,10 @@ async fn fetch_openrouter_models(api_key: &str) -> Result<Vec<NormalizedModel>,
         .bearer_auth(api_key)
         .send()
         .await
-        .map_err(|_| AppError::InternalServerError)?;
+        .map_err(|e| models_request_failed("openrouter", e))?;
 
     if !response.status().is_success() {
-        return Err(AppError::InternalServerError);
+        return Err(provider_error("openrouter", response).await);
     }
 
     let models: Vec<OpenRouterModel> = response
@@ -319,12 +425,113 @@ pub struct OpenAiStreamChoice {
 #[derive(Deserialize)]
 pub struct OpenAiStreamDelta {
     pub content: Option<String>,
+    // OpenRouter sends reasoning as `reasoning`, xAI as `reasoning_content`.
+    #[serde(alias = "reasoning_content")]
+    pub reasoning: Option<String>,
 }
 #[derive(Deserialize)]
 pub struct OpenAiStreamResponse {
     pub choices: Vec<OpenAiStreamChoice>,
 }
 
+fn openai_chunks(data: &str) -> Vec<StreamChunk> {
+    serde_json::from_str::<OpenAiStreamResponse>(data)
+        .map(OpenAiStreamResponse::into_chunks)
+        .unwrap_or_default()
+}
+
+fn anthropic_chunks(data: &str) -> Vec<StreamChunk> {
+    let Ok(event) = serde_json::from_str::<Value>(data) else {
+        tracing::warn!("Failed to parse Anthropic JSON: {}", data);
+        return Vec::new();
+    };
+    if event.get("type").and_then(|t| t.as_str()) != Some("content_block_delta") {
+        return Vec::new();
+    }
+    let delta = event.get("delta");
+    if let Some(text) = delta.and_then(|d| d.get("text")).and_then(|t| t.as_str()) {
+        vec![StreamChunk::Text(text.to_string())]
+    } else if let Some(thinking) = delta
+        .and_then(|d| d.get("thinking"))
+        .and_then(|t| t.as_str())
+    {
+        vec![StreamChunk::Reasoning(thinking.to_string())]
+    } else {
+        Vec::new()
+    }
+}
+
+fn gemini_chunks(data: &str) -> Vec<StreamChunk> {
+    let Ok(event) = serde_json::from_str::<Value>(data) else {
+        tracing::warn!("Failed to parse Gemini JSON: {}", data);
+        return Vec::new();
+    };
+    let parts = event
+        .pointer("/candidates/0/content/parts")
+        .and_then(|p| p.as_array());
+    parts
+        .into_iter()
+        .flatten()
+        .filter_map(|part| {
+            let text = part
+                .get("text")
+                .and_then(|t| t.as_str())
+                .filter(|t| !t.is_empty())?;
+            // Thought summaries come flagged with "thought": true
+            if part
+                .get("thought")
+                .and_then(|t| t.as_bool())
+                .unwrap_or(false)
+            {
+                Some(StreamChunk::Reasoning(text.to_string()))
+            } else {
+                Some(StreamChunk::Text(text.to_string()))
+            }
+        })
+        .collect()
+}
+
+// Reads a provider's event-stream response, turning each event's data into
+// reply chunks with `parse`. A `[DONE]` event ends the reply.
+fn sse_chunk_stream(
+    response: reqwest::Response,
+    parse: fn(&str) -> Vec<StreamChunk>,
+) -> Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>> {
+    Box::pin(async_stream::stream! {
+        let mut events = std::pin::pin!(sse::sse_events(response.bytes_stream()));
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) if event.data == "[DONE]" => return,
+                Ok(event) => {
+                    for chunk in parse(&event.data) {
+                        yield Ok(chunk);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+impl OpenAiStreamResponse {
+    fn into_chunks(self) -> Vec<StreamChunk> {
+        let Some(choice) = self.choices.into_iter().next() else {
+            return Vec::new();
+        };
+        let mut chunks = Vec::new();
+        if let Some(reasoning) = choice.delta.reasoning.filter(|r| !r.is_empty()) {
+            chunks.push(StreamChunk::Reasoning(reasoning));
+        }
+        if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+            chunks.push(StreamChunk::Text(content));
+        }
+        chunks
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Model {
     pub id: String,
@@ -384,24 +591,33 @@ pub struct GeminiModelsResponse {
 
 #[async_trait]
 impl LLMClient for OpenAIClient {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let response = self
             .client
             .post("https://api.openai.com/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model,
-                "messages": messages,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model,
+                    "messages": messages,
+                }),
+                OpenAiDialect::OpenAi,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!("openai api error: {:?}", error_text);
-            
+
             // Parse error message from OpenAI API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -423,11 +639,12 @@ impl LLMClient for OpenAIClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "OpenAI".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
@@ -438,7 +655,7 @@ impl LLMClient for OpenAIClient {
 
         Ok(openai_response
             .choices
-            .get(0)
+            .first()
             .map(|c| c.message.content.clone())
             .unwrap_or_default())
     }
@@ -447,25 +664,34 @@ impl LLMClient for OpenAIClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let response = self
             .client
             .post("https://api.openai.com/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model,
-                "messages": messages,
-                "stream": true,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model,
+                    "messages": messages,
+                    "stream": true,
+                }),
+                OpenAiDialect::OpenAi,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
-            tracing::error!("OpenAI streaming API error: status {}, body: {}", status, error_text);
-            
+            tracing::error!(
+                "OpenAI streaming API error: status {}, body: {}",
+                status,
+                error_text
+            );
+
             // Parse error message from OpenAI API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -487,76 +713,34 @@ impl LLMClient for OpenAIClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "OpenAI".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            let mut buffer = Vec::new();
-            
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                buffer.extend_from_slice(&chunk);
-                
-                // Process complete lines from buffer
-                let mut start = 0;
-                while let Some(newline_pos) = buffer[start..].iter().position(|&b| b == b'\n') {
-                    let line_end = start + newline_pos;
-                    let line = &buffer[start..line_end];
-                    
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        if data == b"[DONE]" {
-                            return;
-                        }
-                        if let Ok(parsed) = serde_json::from_slice::<OpenAiStreamResponse>(data) {
-                            if let Some(content) = parsed.choices.get(0).and_then(|c| c.delta.content.as_ref()) {
-                                if !content.is_empty() {
-                                    yield Ok(content.clone());
-                                }
-                            }
-                        }
-                    }
-                    start = line_end + 1;
-                }
-                
-                // Keep remaining incomplete data in buffer
-                if start < buffer.len() {
-                    buffer.drain(..start);
-                } else {
-                    buffer.clear();
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, openai_chunks))
     }
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
-        self.chat(model, messages).await
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        self.chat(model, messages, params).await
     }
 
     async fn chat_stream_with_web_search(
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        self.chat_stream(model, messages).await
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        self.chat_stream(model, messages, params).await
     }
 
     fn supports_web_search(&self) -> bool {
@@ -599,7 +783,12 @@ impl AnthropicClient {
 
 #[async_trait]
 impl LLMClient for AnthropicClient {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let (system_prompt, user_messages) = self.separate_system_messages(messages);
 
         let mut request_body = serde_json::json!({
@@ -611,6 +800,7 @@ impl LLMClient for AnthropicClient {
         if let Some(system) = system_prompt {
             request_body["system"] = serde_json::Value::String(system);
         }
+        let request_body = params.with_anthropic_fields(request_body);
 
         let response = self
             .client
@@ -624,9 +814,14 @@ impl LLMClient for AnthropicClient {
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
-            tracing::error!("Anthropic API error: status {}, body: {}", status, error_text);
-            
+            tracing::error!(
+                "Anthropic API error: status {}, body: {}",
+                status,
+                error_text
+            );
+
             // Parse error message from Anthropic API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -648,11 +843,12 @@ impl LLMClient for AnthropicClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Anthropic".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
@@ -676,18 +872,13 @@ impl LLMClient for AnthropicClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        let mut max_tokens = 4096;
-        if model.contains("reasoning") {
-            tracing::info!("Using Anthropic reasoning model: {}", model);
-            max_tokens = 8192;
-        }
-
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let (system_prompt, user_messages) = self.separate_system_messages(messages);
 
         let mut request_body = serde_json::json!({
             "model": model,
-            "max_tokens": max_tokens,
+            "max_tokens": 4096,
             "messages": user_messages,
             "stream": true,
         });
@@ -695,6 +886,7 @@ impl LLMClient for AnthropicClient {
         if let Some(system) = system_prompt {
             request_body["system"] = serde_json::Value::String(system);
         }
+        let request_body = params.with_anthropic_fields(request_body);
 
         let response = self
             .client
@@ -708,13 +900,14 @@ impl LLMClient for AnthropicClient {
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!(
                 "Anthropic streaming API error: status {}, body: {}",
                 status,
                 error_text
             );
-            
+
             // Parse error message from Anthropic API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -736,60 +929,24 @@ impl LLMClient for AnthropicClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Anthropic".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("Anthropic stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        let data_str = String::from_utf8_lossy(data);
-                        // tracing::debug!("Anthropic stream data: {}", data_str);
-
-                        if data == b"[DONE]" {
-                            break;
-                        }
-
-                        if let Ok(parsed) = serde_json::from_slice::<Value>(data) {
-                            // tracing::debug!("Anthropic parsed JSON: {:?}", parsed);
-
-                            if let Some(event_type) = parsed.get("type").and_then(|t| t.as_str()) {
-                                if event_type == "content_block_delta" {
-                                    if let Some(delta) = parsed.get("delta").and_then(|d| d.get("text")).and_then(|t| t.as_str()) {
-                                        yield Ok(delta.to_string());
-                                    }
-                                }
-                            }
-                        } else {
-                            tracing::warn!("Failed to parse Anthropic JSON: {}", data_str);
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, anthropic_chunks))
     }
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let (system_prompt, user_messages) = self.separate_system_messages(messages);
 
         let mut request_body = serde_json::json!({
@@ -808,6 +965,7 @@ impl LLMClient for AnthropicClient {
         if let Some(system) = system_prompt {
             request_body["system"] = serde_json::Value::String(system);
         }
+        let request_body = params.with_anthropic_fields(request_body);
 
         let response = self
             .client
@@ -820,8 +978,7 @@ impl LLMClient for AnthropicClient {
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            tracing::error!("anthropic web search api error: {:?}", response.text().await);
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("Anthropic", response).await);
         }
 
         let response_json: Value = response
@@ -844,18 +1001,13 @@ impl LLMClient for AnthropicClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        let mut max_tokens = 4096;
-        if model.contains("reasoning") {
-            tracing::info!("Using Anthropic reasoning model: {}", model);
-            max_tokens = 8192;
-        }
-
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let (system_prompt, user_messages) = self.separate_system_messages(messages);
 
         let mut request_body = serde_json::json!({
             "model": model,
-            "max_tokens": max_tokens,
+            "max_tokens": 4096,
             "messages": user_messages,
             "stream": true,
             "tools": [
@@ -870,6 +1022,7 @@ impl LLMClient for AnthropicClient {
         if let Some(system) = system_prompt {
             request_body["system"] = serde_json::Value::String(system);
         }
+        let request_body = params.with_anthropic_fields(request_body);
 
         let response = self
             .client
@@ -882,56 +1035,10 @@ impl LLMClient for AnthropicClient {
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            tracing::error!(
-                "Anthropic web search streaming API error: status {}, body: {}",
-                status,
-                error_text
-            );
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("Anthropic", response).await);
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("Anthropic web search stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        let data_str = String::from_utf8_lossy(data);
-
-                        if data == b"[DONE]" {
-                            break;
-                        }
-
-                        if let Ok(parsed) = serde_json::from_slice::<Value>(data) {
-                            if let Some(event_type) = parsed.get("type").and_then(|t| t.as_str()) {
-                                if event_type == "content_block_delta" {
-                                    if let Some(delta) = parsed.get("delta").and_then(|d| d.get("text")).and_then(|t| t.as_str()) {
-                                        yield Ok(delta.to_string());
-                                    }
-                                }
-                            }
-                        } else {
-                            tracing::warn!("Failed to parse Anthropic web search JSON: {}", data_str);
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, anthropic_chunks))
     }
 
     fn supports_web_search(&self) -> bool {
@@ -955,21 +1062,29 @@ impl OpenRouterClient {
 
 #[async_trait]
 impl LLMClient for OpenRouterClient {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let response = self
             .client
             .post("https://openrouter.ai/api/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model,
-                "messages": messages,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model,
+                    "messages": messages,
+                }),
+                OpenAiDialect::OpenRouter,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("OpenRouter", response).await);
         }
 
         let openai_response = response.json::<OpenAiResponse>().await.map_err(|e| {
@@ -979,7 +1094,7 @@ impl LLMClient for OpenRouterClient {
 
         Ok(openai_response
             .choices
-            .get(0)
+            .first()
             .map(|c| c.message.content.clone())
             .unwrap_or_default())
     }
@@ -988,58 +1103,37 @@ impl LLMClient for OpenRouterClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let response = self
             .client
             .post("https://openrouter.ai/api/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model,
-                "messages": messages,
-                "stream": true,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model,
+                    "messages": messages,
+                    "stream": true,
+                }),
+                OpenAiDialect::OpenRouter,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("OpenRouter", response).await);
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        if data == b"[DONE]" {
-                            break;
-                        }
-                        if let Ok(parsed) = serde_json::from_slice::<OpenAiStreamResponse>(data) {
-                            if let Some(content) = parsed.choices.get(0).and_then(|c| c.delta.content.as_ref()) {
-                                yield Ok(content.clone());
-                            }
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, openai_chunks))
     }
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let model_with_web = if model.contains(":online") {
             model.to_string()
         } else {
@@ -1050,17 +1144,19 @@ impl LLMClient for OpenRouterClient {
             .client
             .post("https://openrouter.ai/api/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model_with_web,
-                "messages": messages,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model_with_web,
+                    "messages": messages,
+                }),
+                OpenAiDialect::OpenRouter,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            tracing::error!("openrouter web search api error: {:?}", response.text().await);
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("OpenRouter", response).await);
         }
 
         let openai_response = response.json::<OpenAiResponse>().await.map_err(|e| {
@@ -1070,7 +1166,7 @@ impl LLMClient for OpenRouterClient {
 
         Ok(openai_response
             .choices
-            .get(0)
+            .first()
             .map(|c| c.message.content.clone())
             .unwrap_or_default())
     }
@@ -1079,7 +1175,8 @@ impl LLMClient for OpenRouterClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let model_with_web = if model.contains(":online") {
             model.to_string()
         } else {
@@ -1090,51 +1187,23 @@ impl LLMClient for OpenRouterClient {
             .client
             .post("https://openrouter.ai/api/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model_with_web,
-                "messages": messages,
-                "stream": true,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model_with_web,
+                    "messages": messages,
+                    "stream": true,
+                }),
+                OpenAiDialect::OpenRouter,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            tracing::error!("openrouter web search streaming api error: {:?}", response.text().await);
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("OpenRouter", response).await);
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("openrouter web search stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        if data == b"[DONE]" {
-                            break;
-                        }
-                        if let Ok(parsed) = serde_json::from_slice::<OpenAiStreamResponse>(data) {
-                            if let Some(content) = parsed.choices.get(0).and_then(|c| c.delta.content.as_ref()) {
-                                yield Ok(content.clone());
-                            }
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, openai_chunks))
     }
 
     fn supports_web_search(&self) -> bool {
@@ -1158,22 +1227,29 @@ impl XaiClient {
 
 #[async_trait]
 impl LLMClient for XaiClient {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
         let response = self
             .client
             .post("https://api.x.ai/v1/chat/completions")
             .bearer_auth(&self.api_key)
-            .json(&serde_json::json!({
-                "model": model,
-                "messages": messages,
-            }))
+            .json(&params.with_openai_fields(
+                serde_json::json!({
+                    "model": model,
+                    "messages": messages,
+                }),
+                OpenAiDialect::Xai,
+            ))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            tracing::error!("xai api error: {:?}", response.text().await);
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("xAI", response).await);
         }
 
         let openai_response = response.json::<OpenAiResponse>().await.map_err(|e| {
@@ -1183,7 +1259,7 @@ impl LLMClient for XaiClient {
 
         Ok(openai_response
             .choices
-            .get(0)
+            .first()
             .map(|c| c.message.content.clone())
             .unwrap_or_default())
     }
@@ -1192,16 +1268,14 @@ impl LLMClient for XaiClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
         let request_json = serde_json::json!({
             "model": model,
             "messages": messages,
             "stream": true,
         });
-
-        if model.contains("reasoning") || model.contains("mini") {
-            tracing::info!("Using reasoning model: {}", model);
-        }
+        let request_json = params.with_openai_fields(request_json, OpenAiDialect::Xai);
 
         let response = self
             .client
@@ -1213,59 +1287,28 @@ impl LLMClient for XaiClient {
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            tracing::error!(
-                "xAI streaming API error: status {}, body: {}",
-                status,
-                error_text
-            );
-            return Err(AppError::InternalServerError);
+            return Err(provider_error("xAI", response).await);
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        if data == b"[DONE]" {
-                            break;
-                        }
-                        if let Ok(parsed) = serde_json::from_slice::<OpenAiStreamResponse>(data) {
-                            if let Some(content) = parsed.choices.get(0).and_then(|c| c.delta.content.as_ref()) {
-                                yield Ok(content.clone());
-                            }
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, openai_chunks))
     }
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
-        self.chat(model, messages).await
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        self.chat(model, messages, params).await
     }
 
     async fn chat_stream_with_web_search(
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        self.chat_stream(model, messages).await
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        self.chat_stream(model, messages, params).await
     }
 
     fn supports_web_search(&self) -> bool {
@@ -1285,6 +1328,45 @@ impl GeminiClient {
             client: Client::new(),
         }
     }
+
+    // Gemini has no system role: system messages go into one
+    // systemInstruction, and the rest become user/model turns. Consecutive
+    // messages from the same side are merged into one turn, since Gemini
+    // expects the two to alternate.
+    fn to_contents(messages: Vec<Value>) -> (Option<Value>, Vec<Value>) {
+        let mut system_parts = Vec::new();
+        let mut contents: Vec<Value> = Vec::new();
+
+        for message in messages {
+            let (Some(role), Some(content)) = (
+                message.get("role").and_then(|r| r.as_str()),
+                message.get("content").and_then(|c| c.as_str()),
+            ) else {
+                continue;
+            };
+            if role == "system" {
+                system_parts.push(serde_json::json!({ "text": content }));
+                continue;
+            }
+            let gemini_role = if role == "user" { "user" } else { "model" };
+            let part = serde_json::json!({ "text": content });
+            match contents.last_mut() {
+                Some(last) if last["role"] == gemini_role => {
+                    if let Some(parts) = last["parts"].as_array_mut() {
+                        parts.push(part);
+                    }
+                }
+                _ => contents.push(serde_json::json!({
+                    "role": gemini_role,
+                    "parts": [part]
+                })),
+            }
+        }
+
+        let system_instruction =
+            (!system_parts.is_empty()).then(|| serde_json::json!({ "parts": system_parts }));
+        (system_instruction, contents)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -1309,43 +1391,37 @@ struct GeminiResponse {
 
 #[async_trait]
 impl LLMClient for GeminiClient {
-    async fn chat(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
-        let mut contents = Vec::new();
-
-        for message in messages {
-            if let (Some(role), Some(content)) = (
-                message.get("role").and_then(|r| r.as_str()),
-                message.get("content").and_then(|c| c.as_str()),
-            ) {
-                if role == "system" {
-                    continue;
-                }
-                let gemini_role = if role == "user" { "user" } else { "model" };
-                contents.push(serde_json::json!({
-                    "role": gemini_role,
-                    "parts": [{"text": content}]
-                }));
-            }
+    async fn chat(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        let (system_instruction, contents) = GeminiClient::to_contents(messages);
+        let mut request_body = serde_json::json!({
+            "contents": contents,
+        });
+        if let Some(system) = system_instruction {
+            request_body["systemInstruction"] = system;
         }
 
         let response = self
             .client
-            .post(&format!(
+            .post(format!(
                 "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
                 model, self.api_key
             ))
-            .json(&serde_json::json!({
-                "contents": contents,
-            }))
+            .json(&params.with_gemini_fields(request_body))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!("gemini api error: {:?}", error_text);
-            
+
             // Parse error message from Gemini API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -1367,11 +1443,12 @@ impl LLMClient for GeminiClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Gemini".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
@@ -1382,54 +1459,48 @@ impl LLMClient for GeminiClient {
 
         Ok(gemini_response
             .candidates
-            .get(0)
-            .and_then(|c| c.content.parts.get(0))
+            .first()
+            .and_then(|c| c.content.parts.first())
             .map(|p| p.text.clone())
             .unwrap_or_default())
     }
 
-    async fn chat_with_web_search(&self, model: &str, messages: Vec<Value>) -> Result<String, AppError> {
-        let mut contents = Vec::new();
-
-        for message in messages {
-            if let (Some(role), Some(content)) = (
-                message.get("role").and_then(|r| r.as_str()),
-                message.get("content").and_then(|c| c.as_str()),
-            ) {
-                if role == "system" {
-                    continue;
+    async fn chat_with_web_search(
+        &self,
+        model: &str,
+        messages: Vec<Value>,
+        params: &GenerationParams,
+    ) -> Result<String, AppError> {
+        let (system_instruction, contents) = GeminiClient::to_contents(messages);
+        let mut request_body = serde_json::json!({
+            "contents": contents,
+            "tools": [
+                {
+                    "google_search": {}
                 }
-                let gemini_role = if role == "user" { "user" } else { "model" };
-                contents.push(serde_json::json!({
-                    "role": gemini_role,
-                    "parts": [{"text": content}]
-                }));
-            }
+            ]
+        });
+        if let Some(system) = system_instruction {
+            request_body["systemInstruction"] = system;
         }
 
         let response = self
             .client
-            .post(&format!(
+            .post(format!(
                 "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
                 model, self.api_key
             ))
-            .json(&serde_json::json!({
-                "contents": contents,
-                "tools": [
-                    {
-                        "google_search": {}
-                    }
-                ]
-            }))
+            .json(&params.with_gemini_fields(request_body))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!("gemini web search api error: {:?}", error_text);
-            
+
             // Parse error message from Gemini API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -1451,11 +1522,12 @@ impl LLMClient for GeminiClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Gemini".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
@@ -1466,8 +1538,8 @@ impl LLMClient for GeminiClient {
 
         Ok(gemini_response
             .candidates
-            .get(0)
-            .and_then(|c| c.content.parts.get(0))
+            .first()
+            .and_then(|c| c.content.parts.first())
             .map(|p| p.text.clone())
             .unwrap_or_default())
     }
@@ -1480,50 +1552,40 @@ impl LLMClient for GeminiClient {
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        let mut contents = Vec::new();
-
-        for message in messages {
-            if let (Some(role), Some(content)) = (
-                message.get("role").and_then(|r| r.as_str()),
-                message.get("content").and_then(|c| c.as_str()),
-            ) {
-                if role == "system" {
-                    continue;
-                }
-                let gemini_role = if role == "user" { "user" } else { "model" };
-                contents.push(serde_json::json!({
-                    "role": gemini_role,
-                    "parts": [{"text": content}]
-                }));
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        let (system_instruction, contents) = GeminiClient::to_contents(messages);
+        let mut request_body = serde_json::json!({
+            "contents": contents,
+            "generationConfig": {
+                "temperature": 0.7,
+                "topP": 0.8,
+                "topK": 40,
+                "maxOutputTokens": 8192,
             }
+        });
+        if let Some(system) = system_instruction {
+            request_body["systemInstruction"] = system;
         }
 
         let response = self
             .client
-            .post(&format!("https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?alt=sse&key={}", model, self.api_key))
-            .json(&serde_json::json!({
-                "contents": contents,
-                "generationConfig": {
-                    "temperature": 0.7,
-                    "topP": 0.8,
-                    "topK": 40,
-                    "maxOutputTokens": 8192,
-                }
-            }))
+            .post(format!("https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?alt=sse&key={}", model, self.api_key))
+            .json(&params.with_gemini_fields(request_body))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!(
                 "Gemini streaming API error: status {}, body: {}",
                 status,
                 error_text
             );
-            
+
             // Parse error message from Gemini API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -1545,125 +1607,61 @@ impl LLMClient for GeminiClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Gemini".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("Gemini stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        let data_str = String::from_utf8_lossy(data);
-
-                        if data.is_empty() || data == b"[DONE]" {
-                            continue;
-                        }
-
-                        // tracing::debug!("Gemini stream data: {}", data_str);
-
-                        if let Ok(parsed) = serde_json::from_slice::<Value>(data) {
-                            // tracing::debug!("Gemini parsed JSON: {:?}", parsed);
-
-                            if let Some(candidates) = parsed.get("candidates").and_then(|c| c.as_array()) {
-                                if let Some(candidate) = candidates.get(0) {
-                                    if let Some(content) = candidate.get("content") {
-                                        if let Some(parts) = content.get("parts").and_then(|p| p.as_array()) {
-                                            if let Some(part) = parts.get(0) {
-                                                if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                                                    if !text.is_empty() {
-                                                        yield Ok(text.to_string());
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            // Only warn if it's not just grounding metadata
-                            if !data_str.contains("groundingMetadata") {
-                                tracing::warn!("Failed to parse Gemini JSON: {}", data_str);
-                            }
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, gemini_chunks))
     }
 
     async fn chat_stream_with_web_search(
         &self,
         model: &str,
         messages: Vec<Value>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<String, AppError>> + Send>>, AppError> {
-        let mut contents = Vec::new();
-
-        for message in messages {
-            if let (Some(role), Some(content)) = (
-                message.get("role").and_then(|r| r.as_str()),
-                message.get("content").and_then(|c| c.as_str()),
-            ) {
-                if role == "system" {
-                    continue;
+        params: &GenerationParams,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamChunk, AppError>> + Send>>, AppError> {
+        let (system_instruction, contents) = GeminiClient::to_contents(messages);
+        let mut request_body = serde_json::json!({
+            "contents": contents,
+            "tools": [
+                {
+                    "google_search": {}
                 }
-                let gemini_role = if role == "user" { "user" } else { "model" };
-                contents.push(serde_json::json!({
-                    "role": gemini_role,
-                    "parts": [{"text": content}]
-                }));
+            ],
+            "generationConfig": {
+                "temperature": 0.7,
+                "topP": 0.8,
+                "topK": 40,
+                "maxOutputTokens": 8192,
             }
+        });
+        if let Some(system) = system_instruction {
+            request_body["systemInstruction"] = system;
         }
 
         let response = self
             .client
-            .post(&format!("https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?alt=sse&key={}", model, self.api_key))
-            .json(&serde_json::json!({
-                "contents": contents,
-                "tools": [
-                    {
-                        "google_search": {}
-                    }
-                ],
-                "generationConfig": {
-                    "temperature": 0.7,
-                    "topP": 0.8,
-                    "topK": 40,
-                    "maxOutputTokens": 8192,
-                }
-            }))
+            .post(format!("https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?alt=sse&key={}", model, self.api_key))
+            .json(&params.with_gemini_fields(request_body))
             .send()
             .await
             .map_err(|_| AppError::InternalServerError)?;
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = routing::retry_after(response.headers());
             let error_text = response.text().await.unwrap_or_default();
             tracing::error!(
                 "Gemini web search streaming API error: status {}, body: {}",
                 status,
                 error_text
             );
-            
+
             // Parse error message from Gemini API response
             let error_message = if !error_text.is_empty() {
                 if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
@@ -1685,65 +1683,15 @@ impl LLMClient for GeminiClient {
                     _ => format!("HTTP {}", status.as_u16()),
                 }
             };
-            
+
             return Err(AppError::LLMProviderError {
                 provider: "Gemini".to_string(),
                 status_code: Some(status.as_u16()),
                 message: error_message,
+                retry_after,
             });
         }
 
-        let byte_stream = response.bytes_stream();
-
-        let stream = async_stream::stream! {
-            let mut inner_stream = byte_stream;
-
-            while let Some(chunk_result) = inner_stream.next().await {
-                let chunk = match chunk_result {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("Gemini web search stream chunk error: {}", e);
-                        yield Err(AppError::InternalServerError);
-                        break;
-                    }
-                };
-
-                for line in chunk.split(|&b| b == b'\n') {
-                    if line.starts_with(b"data: ") {
-                        let data = &line[6..];
-                        let data_str = String::from_utf8_lossy(data);
-
-                        if data.is_empty() || data == b"[DONE]" {
-                            continue;
-                        }
-
-                        if let Ok(parsed) = serde_json::from_slice::<Value>(data) {
-                            if let Some(candidates) = parsed.get("candidates").and_then(|c| c.as_array()) {
-                                if let Some(candidate) = candidates.get(0) {
-                                    if let Some(content) = candidate.get("content") {
-                                        if let Some(parts) = content.get("parts").and_then(|p| p.as_array()) {
-                                            if let Some(part) = parts.get(0) {
-                                                if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
-                                                    if !text.is_empty() {
-                                                        yield Ok(text.to_string());
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            // Only warn if it's not just grounding metadata
-                            if !data_str.contains("groundingMetadata") {
-                                tracing::warn!("Failed to parse Gemini web search JSON: {}", data_str);
-                            }
-                        }
-                    }
-                }
-            }
-        };
-
-        Ok(Box::pin(stream))
+        Ok(sse_chunk_stream(response, gemini_chunks))
     }
 }
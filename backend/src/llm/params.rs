@@ -0,0 +1,606 @@
+use crate::error::AppError;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+
+// Anthropic rejects thinking budgets below this.
+const ANTHROPIC_MIN_THINKING_BUDGET: u32 = 1024;
+// Room left for the answer when a thinking budget would use up max_tokens.
+const ANTHROPIC_ANSWER_TOKENS: u32 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+
+    // Token budget standing in for an effort on providers that take budgets.
+    fn budget_tokens(self) -> u32 {
+        match self {
+            ReasoningEffort::Low => 1024,
+            ReasoningEffort::Medium => 4096,
+            ReasoningEffort::High => 16384,
+        }
+    }
+}
+
+const GEMINI_HARM_CATEGORIES: &[&str] = &[
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+    "HARM_CATEGORY_CIVIC_INTEGRITY",
+];
+const GEMINI_BLOCK_THRESHOLDS: &[&str] = &[
+    "BLOCK_NONE",
+    "BLOCK_ONLY_HIGH",
+    "BLOCK_MEDIUM_AND_ABOVE",
+    "BLOCK_LOW_AND_ABOVE",
+    "OFF",
+];
+
+// One of Gemini's safety filters and how strictly it blocks, in Gemini's own
+// names (e.g. HARM_CATEGORY_HARASSMENT / BLOCK_ONLY_HIGH).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeminiSafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+impl GeminiSafetySetting {
+    pub fn validate_all(settings: &[GeminiSafetySetting]) -> Result<(), AppError> {
+        for (i, setting) in settings.iter().enumerate() {
+            if !GEMINI_HARM_CATEGORIES.contains(&setting.category.as_str()) {
+                return Err(AppError::BadRequest(format!(
+                    "unknown safety category '{}'. valid categories: {}",
+                    setting.category,
+                    GEMINI_HARM_CATEGORIES.join(", ")
+                )));
+            }
+            if !GEMINI_BLOCK_THRESHOLDS.contains(&setting.threshold.as_str()) {
+                return Err(AppError::BadRequest(format!(
+                    "unknown safety threshold '{}'. valid thresholds: {}",
+                    setting.threshold,
+                    GEMINI_BLOCK_THRESHOLDS.join(", ")
+                )));
+            }
+            if settings[..i].iter().any(|s| s.category == setting.category) {
+                return Err(AppError::BadRequest(format!(
+                    "safety category '{}' is listed twice",
+                    setting.category
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Which OpenAI-compatible API a request goes to; they differ in how the token
+// limit and reasoning are spelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OpenAiDialect {
+    OpenAi,
+    OpenRouter,
+    Xai,
+}
+
+// Sampling settings for a request. Every field is optional: unset fields are
+// left out of the request so the provider's own default applies. Stored as
+// JSON per chat (chats.generation_params) and as a per-user default
+// (user_settings.generation_params); a chat's values win field by field.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(
+        default,
+        alias = "max_completion_tokens",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_tokens: Option<u32>,
+    // OpenAI-style clients may send a single string.
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    // Asks reasoning models to think before answering. Providers that take a
+    // token budget instead get one derived from the effort.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+    // Reasoning token budget, for providers that take one (Anthropic, Gemini,
+    // OpenRouter). Wins over reasoning_effort there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_budget: Option<u32>,
+    // The owner's Gemini safety settings (user_settings.gemini_safety_settings).
+    // Configured separately and never stored with the parameters above.
+    #[serde(skip)]
+    pub gemini_safety_settings: Option<Vec<GeminiSafetySetting>>,
+}
+
+fn string_or_list<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(
+        Option::<StringOrList>::deserialize(deserializer)?.map(|value| match value {
+            StringOrList::One(stop) => vec![stop],
+            StringOrList::Many(stops) => stops,
+        }),
+    )
+}
+
+fn check_range(name: &str, value: Option<f64>, min: f64, max: f64) -> Result<(), AppError> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => Err(AppError::BadRequest(format!(
+            "{} must be between {} and {}",
+            name, min, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+impl GenerationParams {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        check_range("temperature", self.temperature, 0.0, 2.0)?;
+        check_range("top_p", self.top_p, 0.0, 1.0)?;
+        check_range("presence_penalty", self.presence_penalty, -2.0, 2.0)?;
+        check_range("frequency_penalty", self.frequency_penalty, -2.0, 2.0)?;
+        if self.max_tokens == Some(0) {
+            return Err(AppError::BadRequest(
+                "max_tokens must be positive".to_string(),
+            ));
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > 4 || stop.iter().any(|s| s.is_empty()) {
+                return Err(AppError::BadRequest(
+                    "stop takes up to 4 non-empty sequences".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Reads a stored JSON column; unreadable values count as unset.
+    pub fn from_column(value: Option<&str>) -> Self {
+        value
+            .and_then(|v| serde_json::from_str(v).ok())
+            .unwrap_or_default()
+    }
+
+    // NULL rather than "{}" when nothing is set.
+    pub fn to_column(&self) -> Option<String> {
+        if self.is_empty() {
+            None
+        } else {
+            serde_json::to_string(self).ok()
+        }
+    }
+
+    // Fields set here win, the rest come from `defaults`.
+    pub fn or(self, defaults: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            stop: self.stop.or_else(|| defaults.stop.clone()),
+            seed: self.seed.or(defaults.seed),
+            presence_penalty: self.presence_penalty.or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+            reasoning_effort: self.reasoning_effort.or(defaults.reasoning_effort),
+            reasoning_budget: self.reasoning_budget.or(defaults.reasoning_budget),
+            gemini_safety_settings: self
+                .gemini_safety_settings
+                .or_else(|| defaults.gemini_safety_settings.clone()),
+        }
+    }
+
+    fn reasoning_budget_tokens(&self) -> Option<u32> {
+        self.reasoning_budget
+            .or_else(|| self.reasoning_effort.map(ReasoningEffort::budget_tokens))
+    }
+
+    fn warn_unsupported(&self, provider: &str, unsupported: &[&str]) {
+        let set = serde_json::to_value(self).unwrap_or_default();
+        let dropped: Vec<&str> = unsupported
+            .iter()
+            .copied()
+            .filter(|name| set.get(*name).is_some())
+            .collect();
+        if !dropped.is_empty() {
+            tracing::warn!(
+                "{} does not support {}, not sending them",
+                provider,
+                dropped.join(", ")
+            );
+        }
+    }
+
+    // OpenAI-compatible chat completions take the sampling fields under their
+    // own names. Newer OpenAI models want the token limit as
+    // `max_completion_tokens`, and each API asks for reasoning differently.
+    pub(crate) fn with_openai_fields(&self, mut body: Value, dialect: OpenAiDialect) -> Value {
+        let max_tokens_field = match dialect {
+            OpenAiDialect::OpenAi => "max_completion_tokens",
+            OpenAiDialect::OpenRouter | OpenAiDialect::Xai => "max_tokens",
+        };
+        let reasoning = match dialect {
+            // OpenRouter takes either an effort or a budget, and sends the
+            // reasoning back unless told not to.
+            OpenAiDialect::OpenRouter => match (self.reasoning_budget, self.reasoning_effort) {
+                (Some(budget), _) => Some(("reasoning", json!({ "max_tokens": budget }))),
+                (None, Some(effort)) => Some(("reasoning", json!({ "effort": effort.as_str() }))),
+                (None, None) => None,
+            },
+            OpenAiDialect::OpenAi => {
+                self.warn_unsupported("openai", &["reasoning_budget"]);
+                self.reasoning_effort
+                    .map(|effort| ("reasoning_effort", json!(effort.as_str())))
+            }
+            OpenAiDialect::Xai => {
+                self.warn_unsupported("xai", &["reasoning_budget"]);
+                // xAI only knows low and high.
+                self.reasoning_effort.map(|effort| {
+                    let effort = match effort {
+                        ReasoningEffort::High => "high",
+                        ReasoningEffort::Low | ReasoningEffort::Medium => "low",
+                    };
+                    ("reasoning_effort", json!(effort))
+                })
+            }
+        };
+        if let Some(fields) = body.as_object_mut() {
+            let mut set = |name: &str, value: Option<Value>| {
+                if let Some(value) = value {
+                    fields.insert(name.to_string(), value);
+                }
+            };
+            set("temperature", self.temperature.map(Value::from));
+            set("top_p", self.top_p.map(Value::from));
+            set(max_tokens_field, self.max_tokens.map(Value::from));
+            set("stop", self.stop.clone().map(Value::from));
+            set("seed", self.seed.map(Value::from));
+            set("presence_penalty", self.presence_penalty.map(Value::from));
+            set("frequency_penalty", self.frequency_penalty.map(Value::from));
+            if let Some((name, value)) = reasoning {
+                set(name, Some(value));
+            }
+        }
+        body
+    }
+
+    // Anthropic has no seed or penalties, and only takes temperatures up to 1.
+    // The body already carries the client's default max_tokens, which a set
+    // limit replaces. Reasoning turns on extended thinking, which has to fit
+    // inside max_tokens and doesn't allow changing temperature or top_p.
+    // Values Anthropic would reject are left out with a warning rather than
+    // changed.
+    pub(crate) fn with_anthropic_fields(&self, mut body: Value) -> Value {
+        self.warn_unsupported(
+            "anthropic",
+            &["seed", "presence_penalty", "frequency_penalty"],
+        );
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(budget) = self.anthropic_thinking_budget() {
+            self.warn_unsupported("anthropic with thinking", &["temperature", "top_p"]);
+            body["thinking"] = json!({ "type": "enabled", "budget_tokens": budget });
+            // Only the client's default limit is raised to make room; a limit
+            // the user set has already been checked to fit.
+            if self.max_tokens.is_none() {
+                let max_tokens = body["max_tokens"].as_u64().unwrap_or(0);
+                if max_tokens <= u64::from(budget) {
+                    body["max_tokens"] = json!(budget + ANTHROPIC_ANSWER_TOKENS);
+                }
+            }
+            return self.with_stop_sequences(body);
+        }
+        match self.temperature {
+            Some(temperature) if temperature > 1.0 => tracing::warn!(
+                "anthropic only accepts temperatures up to 1, not sending temperature {}",
+                temperature
+            ),
+            Some(temperature) => body["temperature"] = json!(temperature),
+            None => {}
+        }
+        if let Some(top_p) = self.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        self.with_stop_sequences(body)
+    }
+
+    // The thinking budget to send Anthropic, if thinking is wanted and can be
+    // done: the budget has to be at least the minimum and below max_tokens.
+    fn anthropic_thinking_budget(&self) -> Option<u32> {
+        let budget = self.reasoning_budget_tokens()?;
+        if budget < ANTHROPIC_MIN_THINKING_BUDGET {
+            tracing::warn!(
+                "anthropic needs a thinking budget of at least {}, not enabling thinking with {}",
+                ANTHROPIC_MIN_THINKING_BUDGET,
+                budget
+            );
+            return None;
+        }
+        match self.max_tokens {
+            Some(max_tokens) if max_tokens <= budget => {
+                tracing::warn!(
+                    "anthropic needs max_tokens above the thinking budget, not enabling thinking with a budget of {} and max_tokens {}",
+                    budget,
+                    max_tokens
+                );
+                None
+            }
+            _ => Some(budget),
+        }
+    }
+
+    fn with_stop_sequences(&self, mut body: Value) -> Value {
+        if let Some(stop) = &self.stop {
+            body["stop_sequences"] = json!(stop);
+        }
+        body
+    }
+
+    // Gemini takes everything inside generationConfig, in camelCase, on top
+    // of whatever defaults the body already sets there. Thoughts are only
+    // sent back when asked for. Safety settings sit next to the config.
+    pub(crate) fn with_gemini_fields(&self, mut body: Value) -> Value {
+        let mut config = match body.get("generationConfig") {
+            Some(Value::Object(config)) => config.clone(),
+            _ => Map::new(),
+        };
+        let mut set = |name: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                config.insert(name.to_string(), value);
+            }
+        };
+        set("temperature", self.temperature.map(Value::from));
+        set("topP", self.top_p.map(Value::from));
+        set("maxOutputTokens", self.max_tokens.map(Value::from));
+        set("stopSequences", self.stop.clone().map(Value::from));
+        set("seed", self.seed.map(Value::from));
+        set("presencePenalty", self.presence_penalty.map(Value::from));
+        set("frequencyPenalty", self.frequency_penalty.map(Value::from));
+        if let Some(budget) = self.reasoning_budget_tokens() {
+            set(
+                "thinkingConfig",
+                Some(json!({ "thinkingBudget": budget, "includeThoughts": true })),
+            );
+        }
+        if !config.is_empty() {
+            body["generationConfig"] = Value::Object(config);
+        }
+        if let Some(safety_settings) = &self.gemini_safety_settings {
+            body["safetySettings"] = json!(safety_settings);
+        }
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(value: Value) -> GenerationParams {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn openai_takes_max_completion_tokens_and_an_effort() {
+        let params = params(json!({
+            "temperature": 0.2,
+            "max_tokens": 500,
+            "stop": "END",
+            "seed": 7,
+            "reasoning_effort": "medium",
+            "reasoning_budget": 2000,
+        }));
+        let body = params.with_openai_fields(json!({ "model": "o3" }), OpenAiDialect::OpenAi);
+
+        assert_eq!(
+            body,
+            json!({
+                "model": "o3",
+                "temperature": 0.2,
+                "max_completion_tokens": 500,
+                "stop": ["END"],
+                "seed": 7,
+                "reasoning_effort": "medium",
+            })
+        );
+    }
+
+    #[test]
+    fn openrouter_and_xai_spell_things_their_own_way() {
+        let params = params(json!({ "max_tokens": 500, "reasoning_effort": "medium" }));
+
+        let body = params.with_openai_fields(json!({}), OpenAiDialect::OpenRouter);
+        assert_eq!(
+            body,
+            json!({ "max_tokens": 500, "reasoning": { "effort": "medium" } })
+        );
+
+        let body = params.with_openai_fields(json!({}), OpenAiDialect::Xai);
+        assert_eq!(
+            body,
+            json!({ "max_tokens": 500, "reasoning_effort": "low" })
+        );
+
+        let budget = GenerationParams {
+            reasoning_budget: Some(3000),
+            ..params
+        };
+        let body = budget.with_openai_fields(json!({}), OpenAiDialect::OpenRouter);
+        assert_eq!(body["reasoning"], json!({ "max_tokens": 3000 }));
+    }
+
+    #[test]
+    fn anthropic_drops_what_it_does_not_support() {
+        let params = params(json!({
+            "temperature": 0.5,
+            "top_p": 0.9,
+            "max_tokens": 800,
+            "stop": ["END"],
+            "seed": 7,
+            "presence_penalty": 1.0,
+        }));
+        let body = params.with_anthropic_fields(json!({ "max_tokens": 4096 }));
+
+        assert_eq!(
+            body,
+            json!({
+                "max_tokens": 800,
+                "temperature": 0.5,
+                "top_p": 0.9,
+                "stop_sequences": ["END"],
+            })
+        );
+
+        // Above Anthropic's range: left out, not clamped.
+        let hot = GenerationParams {
+            temperature: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(
+            hot.with_anthropic_fields(json!({ "max_tokens": 4096 })),
+            json!({ "max_tokens": 4096 })
+        );
+    }
+
+    #[test]
+    fn anthropic_thinking_fits_inside_max_tokens() {
+        // The client default is raised to leave room for the answer.
+        let params = params(json!({ "reasoning_effort": "high", "temperature": 0.5 }));
+        assert_eq!(
+            params.with_anthropic_fields(json!({ "max_tokens": 4096 })),
+            json!({
+                "max_tokens": 16384 + ANTHROPIC_ANSWER_TOKENS,
+                "thinking": { "type": "enabled", "budget_tokens": 16384 },
+            })
+        );
+
+        // A limit the user set is kept, and thinking that can't fit is left off.
+        let params = params_with_limit(2000, 4000);
+        assert_eq!(
+            params.with_anthropic_fields(json!({ "max_tokens": 4096 })),
+            json!({ "max_tokens": 2000 })
+        );
+        let params = params_with_limit(8000, 4000);
+        assert_eq!(
+            params.with_anthropic_fields(json!({ "max_tokens": 4096 })),
+            json!({
+                "max_tokens": 8000,
+                "thinking": { "type": "enabled", "budget_tokens": 4000 },
+            })
+        );
+
+        // Budgets under Anthropic's minimum aren't raised.
+        let params = params_with_limit(8000, 100);
+        assert_eq!(
+            params.with_anthropic_fields(json!({ "max_tokens": 4096 })),
+            json!({ "max_tokens": 8000 })
+        );
+    }
+
+    fn params_with_limit(max_tokens: u32, budget: u32) -> GenerationParams {
+        GenerationParams {
+            max_tokens: Some(max_tokens),
+            reasoning_budget: Some(budget),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn gemini_merges_into_generation_config() {
+        let mut params = params(json!({
+            "temperature": 0.3,
+            "max_tokens": 1000,
+            "stop": ["END"],
+            "reasoning_budget": 2048,
+        }));
+        params.gemini_safety_settings = Some(vec![GeminiSafetySetting {
+            category: "HARM_CATEGORY_HARASSMENT".to_string(),
+            threshold: "BLOCK_ONLY_HIGH".to_string(),
+        }]);
+        let body = params.with_gemini_fields(json!({
+            "contents": [],
+            "generationConfig": { "candidateCount": 1, "temperature": 1.0 },
+        }));
+
+        assert_eq!(
+            body,
+            json!({
+                "contents": [],
+                "generationConfig": {
+                    "candidateCount": 1,
+                    "temperature": 0.3,
+                    "maxOutputTokens": 1000,
+                    "stopSequences": ["END"],
+                    "thinkingConfig": { "thinkingBudget": 2048, "includeThoughts": true },
+                },
+                "safetySettings": [
+                    { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH" },
+                ],
+            })
+        );
+
+        // Nothing set leaves the body alone.
+        let body = json!({ "contents": [] });
+        assert_eq!(
+            GenerationParams::default().with_gemini_fields(body.clone()),
+            body
+        );
+    }
+
+    #[test]
+    fn chat_values_win_over_defaults_field_by_field() {
+        let chat = params(json!({ "temperature": 0.2 }));
+        let defaults = params(json!({ "temperature": 0.9, "max_tokens": 300 }));
+        assert_eq!(
+            chat.or(&defaults),
+            params(json!({ "temperature": 0.2, "max_tokens": 300 }))
+        );
+    }
+
+    #[test]
+    fn validates_ranges() {
+        assert!(params(json!({ "temperature": 2.0, "top_p": 1.0 }))
+            .validate()
+            .is_ok());
+        assert!(params(json!({ "temperature": 2.5 })).validate().is_err());
+        assert!(params(json!({ "max_tokens": 0 })).validate().is_err());
+        assert!(params(json!({ "stop": ["a", "b", "c", "d", "e"] }))
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use super::mock::MOCK_PROVIDER;
+use crate::{config::SERVER_KEY_PROVIDERS, error::AppError};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Longest fallback chain a chat can have, not counting its own provider.
+pub const MAX_FALLBACK_ROUTES: usize = 4;
+
+// A provider and model a reply can be generated with.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub provider: String,
+    pub model: String,
+}
+
+impl Route {
+    pub fn new(provider: &str, model: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    pub fn validate_chain(chain: &[Route]) -> Result<(), AppError> {
+        if chain.len() > MAX_FALLBACK_ROUTES {
+            return Err(AppError::BadRequest(format!(
+                "a fallback chain can have at most {} entries.",
+                MAX_FALLBACK_ROUTES
+            )));
+        }
+        for route in chain {
+            if !SERVER_KEY_PROVIDERS.contains(&route.provider.as_str())
+                && route.provider != MOCK_PROVIDER
+            {
+                return Err(AppError::BadRequest(format!(
+                    "provider '{}' is not supported.",
+                    route.provider
+                )));
+            }
+            if route.model.trim().is_empty() {
+                return Err(AppError::BadRequest(
+                    "fallback routes need a model.".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the chats.fallback_chain column; unreadable values count as no
+    // fallbacks.
+    pub fn chain_from_column(value: Option<&str>) -> Vec<Route> {
+        value
+            .and_then(|text| serde_json::from_str(text).ok())
+            .unwrap_or_default()
+    }
+
+    // An empty chain is stored as NULL.
+    pub fn chain_to_column(chain: &[Route]) -> Option<String> {
+        (!chain.is_empty()).then(|| serde_json::to_string(chain).unwrap_or_default())
+    }
+}
+
+// How a provider call that failed before producing any output is retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // Calls per key, counting the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // Whether an error is worth another attempt on the same key.
+    pub fn is_retryable(error: &AppError) -> bool {
+        matches!(
+            error,
+            AppError::LLMProviderError {
+                status_code: Some(408 | 429 | 500 | 502 | 503 | 504 | 529),
+                ..
+            }
+        )
+    }
+
+    // Wait before retry number `retry` (1 for the first). A Retry-After from
+    // the provider is honoured as is; otherwise the delay doubles each time,
+    // with jitter so clients sharing a key don't retry in lockstep. None when
+    // the provider asks for a longer wait than max_delay, in which case it's
+    // better to move on to another key or route.
+    pub fn delay(&self, retry: u32, retry_after: Option<u64>) -> Option<Duration> {
+        if let Some(seconds) = retry_after {
+            let wait = Duration::from_secs(seconds);
+            return (wait <= self.max_delay).then_some(wait);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        Some(backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0)))
+    }
+}
+
+// Seconds to wait according to a Retry-After header, given either as a number
+// of seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(seconds);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let wait = date.timestamp() - chrono::Utc::now().timestamp();
+    Some(wait.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_within_jitter_and_caps() {
+        let policy = policy();
+        for retry in 1..=8 {
+            let ceiling = (Duration::from_millis(500) * 2u32.pow(retry - 1)).min(policy.max_delay);
+            let delay = policy.delay(retry, None).unwrap();
+            assert!(
+                delay <= ceiling && delay >= ceiling / 2,
+                "retry {}: {:?}",
+                retry,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn honours_retry_after_up_to_max_delay() {
+        let policy = policy();
+        assert_eq!(policy.delay(1, Some(3)), Some(Duration::from_secs(3)));
+        assert_eq!(policy.delay(1, Some(0)), Some(Duration::ZERO));
+        assert_eq!(policy.delay(1, Some(60)), None);
+    }
+
+    #[test]
+    fn parses_retry_after_header() {
+        assert_eq!(retry_after(&headers("7")), Some(7));
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(0)
+        );
+        let later = chrono::Utc::now() + chrono::Duration::seconds(30);
+        let wait = retry_after(&headers(&later.to_rfc2822())).unwrap();
+        assert!((29..=30).contains(&wait), "{}", wait);
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn only_transient_provider_errors_are_retried() {
+        let error = |status| AppError::LLMProviderError {
+            provider: "OpenAI".to_string(),
+            status_code: status,
+            message: String::new(),
+            retry_after: None,
+        };
+        assert!(RetryPolicy::is_retryable(&error(Some(429))));
+        assert!(RetryPolicy::is_retryable(&error(Some(503))));
+        assert!(!RetryPolicy::is_retryable(&error(Some(400))));
+        assert!(!RetryPolicy::is_retryable(&error(Some(401))));
+        assert!(!RetryPolicy::is_retryable(&error(None)));
+        assert!(!RetryPolicy::is_retryable(&AppError::InternalServerError));
+    }
+
+    #[test]
+    fn validates_fallback_chains() {
+        assert!(
+            Route::validate_chain(&[Route::new("openrouter", "anthropic/claude-sonnet-4")]).is_ok()
+        );
+        assert!(Route::validate_chain(&[Route::new("nope", "x")]).is_err());
+        assert!(Route::validate_chain(&[Route::new("openai", " ")]).is_err());
+        assert!(Route::validate_chain(&vec![Route::new("openai", "gpt-4o"); 5]).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::error::AppError;
+use futures_util::{Stream, StreamExt};
+use std::fmt::Display;
+
+// One server-sent event. `event` is None for plain `data:` events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+// Splits bytes into lines ending in \n, \r\n or \r. Partial lines are kept
+// until the rest arrives, so neither a line nor a UTF-8 character can be cut
+// in half by a chunk boundary.
+#[derive(Default)]
+struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut i = 0;
+        while i < self.buffer.len() {
+            match self.buffer[i] {
+                b'\n' => {
+                    lines.push(String::from_utf8_lossy(&self.buffer[start..i]).into_owned());
+                    i += 1;
+                    start = i;
+                }
+                b'\r' => {
+                    // Could be the first half of a \r\n; wait for the next byte.
+                    if i + 1 == self.buffer.len() {
+                        break;
+                    }
+                    lines.push(String::from_utf8_lossy(&self.buffer[start..i]).into_owned());
+                    i += if self.buffer[i + 1] == b'\n' { 2 } else { 1 };
+                    start = i;
+                }
+                _ => i += 1,
+            }
+        }
+        self.buffer.drain(..start);
+        lines
+    }
+
+    // Whatever is left once the stream has ended, as a last line.
+    fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = rest.strip_suffix(b"\r").unwrap_or(&rest);
+        (!rest.is_empty()).then(|| String::from_utf8_lossy(rest).into_owned())
+    }
+}
+
+// Incremental decoder for text/event-stream bodies: `event:` and `data:`
+// fields (several data lines are joined with \n), comments, and any line
+// ending. `id:` and `retry:` are ignored.
+#[derive(Default)]
+pub struct SseDecoder {
+    lines: LineBuffer,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseDecoder {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        self.lines
+            .push(bytes)
+            .into_iter()
+            .filter_map(|line| self.process_line(&line))
+            .collect()
+    }
+
+    // Providers don't always end the last event with a blank line, so a
+    // pending event is still delivered when the stream ends.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        if let Some(line) = self.lines.finish() {
+            if let Some(event) = self.process_line(&line) {
+                return Some(event);
+            }
+        }
+        self.dispatch()
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => self.data.push(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data: std::mem::take(&mut self.data).join("\n"),
+        })
+    }
+}
+
+// Incremental decoder for newline-delimited JSON: one value per line, blank
+// lines skipped.
+#[derive(Default)]
+#[allow(dead_code)] // no provider streams NDJSON yet
+pub struct NdjsonDecoder {
+    lines: LineBuffer,
+}
+
+#[allow(dead_code)]
+impl NdjsonDecoder {
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.lines
+            .push(bytes)
+            .into_iter()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+
+    pub fn finish(&mut self) -> Option<String> {
+        self.lines.finish().filter(|line| !line.trim().is_empty())
+    }
+}
+
+// Turns a response body into its events. A transport error ends the stream
+// after yielding one error.
+pub fn sse_events<S, B, E>(body: S) -> impl Stream<Item = Result<SseEvent, AppError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    async_stream::stream! {
+        let mut body = std::pin::pin!(body);
+        let mut decoder = SseDecoder::default();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    for event in decoder.push(chunk.as_ref()) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("stream chunk error: {}", e);
+                    yield Err(AppError::InternalServerError);
+                    return;
+                }
+            }
+        }
+        if let Some(event) = decoder.finish() {
+            yield Ok(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+
+    fn data(data: &str) -> SseEvent {
+        SseEvent {
+            event: None,
+            data: data.to_string(),
+        }
+    }
+
+    fn named(event: &str, data: &str) -> SseEvent {
+        SseEvent {
+            event: Some(event.to_string()),
+            data: data.to_string(),
+        }
+    }
+
+    // Feeds `input` in pieces of `size` bytes and collects every event.
+    fn decode_in_pieces(input: &[u8], size: usize) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::default();
+        let mut events: Vec<SseEvent> = input
+            .chunks(size)
+            .flat_map(|piece| decoder.push(piece))
+            .collect();
+        events.extend(decoder.finish());
+        events
+    }
+
+    // The same events must come out however the body is cut up.
+    fn assert_decodes_fragmented(input: &str, expected: &[SseEvent]) {
+        for size in 1..=input.len() {
+            assert_eq!(
+                decode_in_pieces(input.as_bytes(), size),
+                expected,
+                "pieces of {} bytes",
+                size
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_openai_style_stream() {
+        let input = "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+                     data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+                     data: [DONE]\n\n";
+        assert_decodes_fragmented(
+            input,
+            &[
+                data("{\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}"),
+                data("{\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}"),
+                data("[DONE]"),
+            ],
+        );
+    }
+
+    #[test]
+    fn keeps_event_names() {
+        let input = "event: message_start\ndata: {\"type\":\"message_start\"}\n\n\
+                     event: content_block_delta\ndata: {\"delta\":{\"text\":\"hi\"}}\n\n";
+        assert_decodes_fragmented(
+            input,
+            &[
+                named("message_start", "{\"type\":\"message_start\"}"),
+                named("content_block_delta", "{\"delta\":{\"text\":\"hi\"}}"),
+            ],
+        );
+    }
+
+    #[test]
+    fn joins_multi_line_data() {
+        assert_decodes_fragmented(
+            "data: first\ndata: second\ndata:third\n\n",
+            &[data("first\nsecond\nthird")],
+        );
+    }
+
+    #[test]
+    fn handles_crlf_and_bare_cr() {
+        let expected = [named("a", "1"), data("2")];
+        assert_decodes_fragmented("event: a\r\ndata: 1\r\n\r\ndata: 2\r\n\r\n", &expected);
+        assert_decodes_fragmented("event: a\rdata: 1\r\rdata: 2\r\r", &expected);
+    }
+
+    #[test]
+    fn skips_comments_and_unknown_fields() {
+        let input = ": keep-alive\n\nid: 7\nretry: 1000\ndata: x\n\n: ping\n\n";
+        assert_decodes_fragmented(input, &[data("x")]);
+    }
+
+    #[test]
+    fn events_without_data_are_dropped() {
+        assert_decodes_fragmented("event: ping\n\ndata: x\n\n", &[data("x")]);
+    }
+
+    #[test]
+    fn delivers_last_event_without_trailing_blank_line() {
+        assert_decodes_fragmented("data: a\n\ndata: b", &[data("a"), data("b")]);
+        assert_decodes_fragmented("data: a\n\ndata: b\r", &[data("a"), data("b")]);
+    }
+
+    #[test]
+    fn keeps_multibyte_characters_split_across_chunks() {
+        assert_decodes_fragmented(
+            "data: {\"text\":\"héllo 🐱\"}\n\n",
+            &[data("{\"text\":\"héllo 🐱\"}")],
+        );
+    }
+
+    #[test]
+    fn ndjson_lines_survive_fragmentation() {
+        let input = "{\"a\":1}\n\n{\"b\":\"ü\"}\r\n{\"c\":3}";
+        for size in 1..=input.len() {
+            let mut decoder = NdjsonDecoder::default();
+            let mut lines: Vec<String> = input
+                .as_bytes()
+                .chunks(size)
+                .flat_map(|piece| decoder.push(piece))
+                .collect();
+            lines.extend(decoder.finish());
+            assert_eq!(
+                lines,
+                ["{\"a\":1}", "{\"b\":\"ü\"}", "{\"c\":3}"],
+                "pieces of {} bytes",
+                size
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_adapter_decodes_chunked_body() {
+        let body =
+            stream::iter(["da", "ta: a\n", "\nda", "ta: b\n\n"].map(Ok::<_, std::io::Error>));
+        let events: Vec<SseEvent> = sse_events(body).map(Result::unwrap).collect().await;
+        assert_eq!(events, [data("a"), data("b")]);
+    }
+
+    #[tokio::test]
+    async fn stream_adapter_stops_at_transport_error() {
+        let body = stream::iter([
+            Ok("data: a\n\n"),
+            Err(std::io::Error::other("reset")),
+            Ok("data: b\n\n"),
+        ]);
+        let events: Vec<Result<SseEvent, AppError>> = sse_events(body).collect().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].as_ref().unwrap(), &data("a"));
+        assert!(events[1].is_err());
+    }
+}
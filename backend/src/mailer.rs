@@ -0,0 +1,149 @@
+use crate::config::Config;
+use crate::error::AppError;
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+fn build_message(from: &Mailbox, email: &Email) -> Result<Message, AppError> {
+    let to: Mailbox = email
+        .to
+        .parse()
+        .map_err(|_| AppError::BadRequest("invalid recipient address".to_string()))?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(email.subject.clone())
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body.clone())
+        .map_err(|e| {
+            tracing::error!("failed to build email: {}", e);
+            AppError::InternalServerError
+        })
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        let message = build_message(&self.from, &email)?;
+        self.transport.send(message).await.map_err(|e| {
+            tracing::error!("failed to send email to {}: {}", email.to, e);
+            AppError::InternalServerError
+        })?;
+        Ok(())
+    }
+}
+
+// Writes every message as an .eml file, handy for local development.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: Mailbox,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        let message = build_message(&self.from, &email)?;
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            uuid::Uuid::new_v4()
+        ));
+
+        let write = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, message.formatted()).await
+        };
+        write.await.map_err(|e| {
+            tracing::error!("failed to write email to {}: {}", path.display(), e);
+            AppError::InternalServerError
+        })?;
+        Ok(())
+    }
+}
+
+// Only logs the message; the default when no mail backend is configured.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        tracing::info!(
+            "email to {} | subject: {}\n{}",
+            email.to,
+            email.subject,
+            email.body
+        );
+        Ok(())
+    }
+}
+
+pub fn from_config(config: &Config) -> Arc<dyn Mailer> {
+    let from: Mailbox = config
+        .mail_from
+        .parse()
+        .expect("MAIL_FROM must be a valid address, e.g. 'neko.chat <noreply@example.com>'");
+
+    match config.mail_backend.as_str() {
+        "smtp" => {
+            let host = config
+                .smtp_host
+                .as_deref()
+                .expect("SMTP_HOST must be set when MAIL_BACKEND=smtp");
+
+            let mut builder = match config.smtp_tls.as_str() {
+                "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+                "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                    host,
+                )),
+                _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+            }
+            .expect("failed to configure SMTP transport")
+            .port(config.smtp_port);
+
+            if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password)
+            {
+                builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+            }
+
+            tracing::info!("sending mail via SMTP ({}:{})", host, config.smtp_port);
+            Arc::new(SmtpMailer {
+                transport: builder.build(),
+                from,
+            })
+        }
+        "file" => {
+            tracing::info!("writing outgoing mail to {}", config.mail_dir);
+            Arc::new(FileMailer {
+                dir: PathBuf::from(&config.mail_dir),
+                from,
+            })
+        }
+        "log" => Arc::new(LogMailer),
+        other => panic!(
+            "unknown MAIL_BACKEND '{}', expected smtp, file or log",
+            other
+        ),
+    }
+}
@@ -0,0 +1,511 @@
+// OpenID Connect relying party: discovery, authorization code exchange and ID
+// token validation against the issuer's JWKS.
+use crate::config::OidcProvider;
+use crate::error::AppError;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, PublicKeyUse},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Discovery documents and signing keys are refetched after this long, or
+// earlier when a token is signed with a key we haven't seen yet.
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+}
+
+struct CachedProvider {
+    metadata: ProviderMetadata,
+    keys: Vec<Jwk>,
+    fetched_at: Instant,
+}
+
+#[derive(Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+// What we take from a provider's claims to find or create a local user.
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub email: String,
+    pub name: String,
+    pub picture: Option<String>,
+    // Set only when the provider is configured with a role claim.
+    pub role: Option<String>,
+}
+
+pub struct OidcClient {
+    http: reqwest::Client,
+    cache: Mutex<HashMap<String, CachedProvider>>,
+}
+
+impl OidcClient {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .expect("failed to build OIDC HTTP client"),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn fetch_provider(&self, provider: &OidcProvider) -> Result<CachedProvider, AppError> {
+        let discovery_url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = self
+            .get_json(&discovery_url)
+            .await
+            .and_then(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                tracing::error!("OIDC discovery for {} failed: {}", provider.id, e);
+                AppError::BadRequest(format!("{} sign-in is unavailable", provider.display_name))
+            })?;
+
+        if !issuers_for(provider).contains(&metadata.issuer.trim_end_matches('/').to_string()) {
+            tracing::error!(
+                "OIDC provider {} advertises issuer {}, expected {}",
+                provider.id,
+                metadata.issuer,
+                provider.issuer
+            );
+            return Err(AppError::BadRequest(format!(
+                "{} sign-in is misconfigured",
+                provider.display_name
+            )));
+        }
+
+        let jwks = self.get_json(&metadata.jwks_uri).await.map_err(|e| {
+            tracing::error!("fetching JWKS for {} failed: {}", provider.id, e);
+            AppError::BadRequest(format!("{} sign-in is unavailable", provider.display_name))
+        })?;
+
+        // Parse keys one by one so an encryption key in an algorithm we don't
+        // know doesn't hide the signing keys next to it.
+        let keys = jwks["keys"]
+            .as_array()
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|k| serde_json::from_value::<Jwk>(k.clone()).ok())
+                    .filter(|k| !matches!(k.common.public_key_use, Some(PublicKeyUse::Encryption)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(CachedProvider {
+            metadata,
+            keys,
+            fetched_at: Instant::now(),
+        })
+    }
+
+    async fn get_json(&self, url: &str) -> Result<Value, String> {
+        let response = self.http.get(url).send().await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("{} returned {}", url, response.status()));
+        }
+        response.json().await.map_err(|e| e.to_string())
+    }
+
+    async fn cached(
+        &self,
+        provider: &OidcProvider,
+        force_refresh: bool,
+    ) -> Result<(ProviderMetadata, Vec<Jwk>), AppError> {
+        let mut cache = self.cache.lock().await;
+        let stale = cache
+            .get(&provider.id)
+            .is_none_or(|c| force_refresh || c.fetched_at.elapsed() > METADATA_TTL);
+        if stale {
+            let fresh = self.fetch_provider(provider).await?;
+            cache.insert(provider.id.clone(), fresh);
+        }
+        let entry = &cache[&provider.id];
+        Ok((entry.metadata.clone(), entry.keys.clone()))
+    }
+
+    pub async fn metadata(&self, provider: &OidcProvider) -> Result<ProviderMetadata, AppError> {
+        Ok(self.cached(provider, false).await?.0)
+    }
+
+    pub fn authorization_url(
+        metadata: &ProviderMetadata,
+        provider: &OidcProvider,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> String {
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!(
+            "{}{}client_id={}&redirect_uri={}&scope={}&response_type=code&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+            metadata.authorization_endpoint,
+            separator,
+            urlencoding::encode(&provider.client_id),
+            urlencoding::encode(&provider.redirect_uri),
+            urlencoding::encode(&provider.scopes),
+            state,
+            nonce,
+            code_challenge
+        )
+    }
+
+    pub async fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<TokenResponse, AppError> {
+        let metadata = self.metadata(provider).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("code_verifier", code_verifier),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|_| AppError::BadRequest("Failed to exchange code for token".to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            tracing::warn!(
+                "{} token endpoint returned {}: {}",
+                provider.id,
+                status,
+                body
+            );
+            return Err(AppError::BadRequest(
+                "Failed to exchange code for token".to_string(),
+            ));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|_| AppError::BadRequest("Failed to parse token response".to_string()))
+    }
+
+    // Verifies the ID token's signature, issuer, audience, expiry and nonce and
+    // returns its claims.
+    pub async fn validate_id_token(
+        &self,
+        provider: &OidcProvider,
+        id_token: &str,
+        expected_nonce: &str,
+    ) -> Result<Map<String, Value>, AppError> {
+        let header = decode_header(id_token)?;
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            return Err(AppError::BadRequest(
+                "ID token uses an unsupported signing algorithm".to_string(),
+            ));
+        }
+
+        let (_, keys) = self.cached(provider, false).await?;
+        let jwk = match find_key(&keys, header.kid.as_deref()) {
+            Some(jwk) => jwk,
+            // The provider may have rotated its keys since we cached them.
+            None => {
+                let (_, keys) = self.cached(provider, true).await?;
+                find_key(&keys, header.kid.as_deref()).ok_or_else(|| {
+                    AppError::BadRequest("ID token signed with an unknown key".to_string())
+                })?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&issuers_for(provider));
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims =
+            decode::<Map<String, Value>>(id_token, &DecodingKey::from_jwk(&jwk)?, &validation)?
+                .claims;
+
+        if claims.get("nonce").and_then(Value::as_str) != Some(expected_nonce) {
+            return Err(AppError::BadRequest("ID token nonce mismatch".to_string()));
+        }
+
+        Ok(claims)
+    }
+
+    // Some providers keep profile claims out of the ID token; fill the gaps
+    // from the userinfo endpoint without overriding what the token says.
+    pub async fn merge_userinfo(
+        &self,
+        provider: &OidcProvider,
+        access_token: &str,
+        claims: &mut Map<String, Value>,
+    ) -> Result<(), AppError> {
+        let Some(userinfo_endpoint) = self.metadata(provider).await?.userinfo_endpoint else {
+            return Ok(());
+        };
+
+        let userinfo: Map<String, Value> = self
+            .http
+            .get(&userinfo_endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await
+            .map_err(|_| AppError::BadRequest("Failed to get user info".to_string()))?
+            .json()
+            .await
+            .map_err(|_| AppError::BadRequest("Failed to parse user info".to_string()))?;
+
+        // The userinfo response must describe the same subject as the ID token.
+        if userinfo.get("sub") != claims.get("sub") {
+            return Err(AppError::BadRequest(
+                "user info does not match the ID token".to_string(),
+            ));
+        }
+
+        for (key, value) in userinfo {
+            claims.entry(key).or_insert(value);
+        }
+        Ok(())
+    }
+}
+
+impl Default for OidcClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn find_key(keys: &[Jwk], kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys
+            .iter()
+            .find(|k| k.common.key_id.as_deref() == Some(kid))
+            .cloned(),
+        // Without a key id the provider must publish exactly one signing key.
+        None if keys.len() == 1 => keys.first().cloned(),
+        None => None,
+    }
+}
+
+// Google issues ID tokens with and without the scheme in `iss`.
+fn issuers_for(provider: &OidcProvider) -> Vec<String> {
+    let mut issuers = vec![provider.issuer.clone()];
+    if provider.issuer == "https://accounts.google.com" {
+        issuers.push("accounts.google.com".to_string());
+    }
+    issuers
+}
+
+// Reads a claim by dotted path (e.g. "realm_access.roles") as a list of strings.
+fn claim_values(claims: &Map<String, Value>, path: &str) -> Vec<String> {
+    let mut parts = path.split('.');
+    let mut value = parts.next().and_then(|first| claims.get(first));
+    for part in parts {
+        value = value.and_then(|v| v.get(part));
+    }
+
+    match value {
+        Some(Value::String(s)) => s.split_whitespace().map(str::to_string).collect(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn identity_from_claims(
+    provider: &OidcProvider,
+    claims: &Map<String, Value>,
+) -> Result<OidcIdentity, AppError> {
+    let string_claim = |name: &str| {
+        claims
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    let subject = string_claim("sub")
+        .ok_or_else(|| AppError::BadRequest("ID token has no subject".to_string()))?;
+    let email = string_claim("email")
+        .map(|e| e.to_lowercase())
+        .ok_or_else(|| {
+            AppError::BadRequest(format!(
+                "your {} account did not share an email address",
+                provider.display_name
+            ))
+        })?;
+
+    // Some providers send email_verified as the string "true".
+    let email_verified = match claims.get("email_verified") {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::String(s)) => s == "true",
+        _ => false,
+    };
+    if !email_verified && !provider.trust_email {
+        return Err(AppError::BadRequest(format!(
+            "{} account email is not verified",
+            provider.display_name
+        )));
+    }
+
+    let name = string_claim("name")
+        .or_else(|| string_claim("preferred_username"))
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+
+    let role = provider.role_claim.as_deref().map(|path| {
+        let groups = claim_values(claims, path);
+        if groups.iter().any(|g| provider.admin_groups.contains(g)) {
+            "admin".to_string()
+        } else {
+            "user".to_string()
+        }
+    });
+
+    Ok(OidcIdentity {
+        subject,
+        email,
+        name,
+        picture: string_claim("picture"),
+        role,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provider() -> OidcProvider {
+        OidcProvider {
+            id: "test".to_string(),
+            display_name: "Test IdP".to_string(),
+            issuer: "https://idp.example".to_string(),
+            client_id: "neko".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:5173/auth/callback".to_string(),
+            scopes: "openid email profile".to_string(),
+            role_claim: None,
+            admin_groups: Vec::new(),
+            trust_email: false,
+        }
+    }
+
+    fn claims(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn requires_a_verified_email_unless_the_provider_is_trusted() {
+        let unverified =
+            claims(json!({ "sub": "1", "email": "ada@example.com", "email_verified": false }));
+        let missing = claims(json!({ "sub": "1", "email": "ada@example.com" }));
+        let mut trusted = provider();
+        trusted.trust_email = true;
+
+        for claims in [&unverified, &missing] {
+            assert!(identity_from_claims(&provider(), claims).is_err());
+            assert!(identity_from_claims(&trusted, claims).is_ok());
+        }
+    }
+
+    #[test]
+    fn accepts_email_verified_as_a_string() {
+        let claims =
+            claims(json!({ "sub": "1", "email": "ada@example.com", "email_verified": "true" }));
+        assert!(identity_from_claims(&provider(), &claims).is_ok());
+    }
+
+    #[test]
+    fn rejects_claims_without_an_email() {
+        let mut trusted = provider();
+        trusted.trust_email = true;
+        for claims in [
+            claims(json!({ "sub": "1", "email_verified": true })),
+            claims(json!({ "sub": "1", "email": "  ", "email_verified": true })),
+        ] {
+            assert!(identity_from_claims(&trusted, &claims).is_err());
+        }
+    }
+
+    #[test]
+    fn normalizes_the_email_and_falls_back_to_its_local_part_for_the_name() {
+        let claims =
+            claims(json!({ "sub": "1", "email": " Ada@Example.COM ", "email_verified": true }));
+        let identity = identity_from_claims(&provider(), &claims).unwrap();
+        assert_eq!(identity.subject, "1");
+        assert_eq!(identity.email, "ada@example.com");
+        assert_eq!(identity.name, "ada");
+        assert_eq!(identity.role, None);
+    }
+
+    #[test]
+    fn maps_groups_onto_roles() {
+        let mut provider = provider();
+        provider.role_claim = Some("realm_access.roles".to_string());
+        provider.admin_groups = vec!["neko-admins".to_string()];
+        let with_groups = |groups: Value| {
+            claims(json!({
+                "sub": "1",
+                "email": "ada@example.com",
+                "email_verified": true,
+                "realm_access": { "roles": groups },
+            }))
+        };
+
+        let admin = identity_from_claims(&provider, &with_groups(json!(["staff", "neko-admins"])));
+        assert_eq!(admin.unwrap().role.as_deref(), Some("admin"));
+        let spaced = identity_from_claims(&provider, &with_groups(json!("staff neko-admins")));
+        assert_eq!(spaced.unwrap().role.as_deref(), Some("admin"));
+        let user = identity_from_claims(&provider, &with_groups(json!(["staff"])));
+        assert_eq!(user.unwrap().role.as_deref(), Some("user"));
+
+        // No groups at all demotes rather than keeping whatever role was there.
+        let none =
+            claims(json!({ "sub": "1", "email": "ada@example.com", "email_verified": true }));
+        let none = identity_from_claims(&provider, &none);
+        assert_eq!(none.unwrap().role.as_deref(), Some("user"));
+    }
+}
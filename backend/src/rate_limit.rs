@@ -0,0 +1,398 @@
+// In-memory token-bucket rate limiting for auth and generation endpoints.
+// Buckets live per process, which matches the single-binary SQLite deployment.
+use crate::auth::{Claims, ClientInfo, TokenScope};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::AppState;
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Buckets are pruned once a limiter tracks this many keys.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+// Allows `per_minute` requests per key, refilled continuously, with bursts of
+// up to `per_minute`. A limit of 0 disables the limiter.
+pub struct RateLimiter {
+    per_minute: u32,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            per_minute,
+            clock,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes one token for `key`, or returns how long until one is available.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+
+        let now = self.clock.now();
+        let capacity = self.per_minute as f64;
+        let refill_per_sec = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_KEYS && !buckets.contains_key(key) {
+            // Drop buckets that have refilled completely; they carry no state.
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated_at).as_secs_f64() * refill_per_sec
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_sec).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / refill_per_sec,
+            ))
+        }
+    }
+}
+
+pub struct RateLimits {
+    enabled: bool,
+    auth_per_ip: RateLimiter,
+    login_per_account: RateLimiter,
+    generation_per_user: RateLimiter,
+    generation_per_ip: RateLimiter,
+}
+
+impl RateLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: &Config, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            enabled: config.rate_limit_enabled,
+            auth_per_ip: RateLimiter::new(config.rate_limit_auth_per_minute, clock.clone()),
+            login_per_account: RateLimiter::new(
+                config.rate_limit_login_per_account_per_minute,
+                clock.clone(),
+            ),
+            generation_per_user: RateLimiter::new(
+                config.rate_limit_generation_per_minute,
+                clock.clone(),
+            ),
+            generation_per_ip: RateLimiter::new(
+                config.rate_limit_generation_per_ip_per_minute,
+                clock,
+            ),
+        }
+    }
+
+    fn check(&self, limiter: &RateLimiter, key: &str) -> Result<(), AppError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        limiter
+            .check(key)
+            .map_err(|wait| AppError::TooManyRequests {
+                message: "too many requests, please slow down".to_string(),
+                retry_after: (wait.as_secs_f64().ceil() as u64).max(1),
+            })
+    }
+
+    // Per-account bucket for login attempts, independent of the caller's IP.
+    pub fn check_login_account(&self, email: &str) -> Result<(), AppError> {
+        self.check(&self.login_per_account, &email.trim().to_lowercase())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RequestClass {
+    Auth,
+    Generation,
+    Other,
+}
+
+// Endpoints that take credentials or send mail are throttled per IP;
+// everything that calls an LLM provider is throttled per IP and per user.
+fn classify(method: &Method, path: &str) -> RequestClass {
+    const AUTH_PATHS: [&str; 12] = [
+        "/api/auth/login",
+        "/api/auth/register",
+        "/api/auth/refresh",
+        "/api/auth/mfa",
+        "/api/auth/mfa/disable",
+        "/api/auth/mfa/recovery-codes",
+        "/api/auth/password-reset",
+        "/api/auth/password-reset/confirm",
+        "/api/auth/verify-email",
+        "/api/auth/google",
+        "/api/auth/google/callback",
+        "/api/auth/oidc/callback",
+    ];
+
+    // Starting a sign-in is unauthenticated and stores a pending OAuth state.
+    let starts_oidc = method == Method::GET
+        && path
+            .strip_prefix("/api/auth/oidc/")
+            .is_some_and(|provider| !provider.contains('/') && provider != "providers");
+
+    if AUTH_PATHS.contains(&path)
+        || starts_oidc
+        || (path.starts_with("/api/keys/") && path.ends_with("/reveal"))
+    {
+        RequestClass::Auth
+    } else if path != "/v1/models"
+        && TokenScope::required_for(method, path) == Some(TokenScope::Generate)
+    {
+        RequestClass::Generation
+    } else {
+        RequestClass::Other
+    }
+}
+
+pub async fn enforce(State(app_state): State<AppState>, request: Request, next: Next) -> Response {
+    let limits = app_state.rate_limits.clone();
+    let class = classify(request.method(), request.uri().path());
+    if class == RequestClass::Other {
+        return next.run(request).await;
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let Ok(client) = ClientInfo::from_request_parts(&mut parts, &app_state).await;
+    let ip = client.ip.unwrap_or_else(|| "unknown".to_string());
+
+    let result = match class {
+        RequestClass::Auth => limits.check(&limits.auth_per_ip, &ip),
+        RequestClass::Generation => {
+            let per_user = match Claims::from_request_parts(&mut parts, &app_state).await {
+                Ok(claims) => {
+                    let result = limits.check(&limits.generation_per_user, &claims.sub);
+                    // The handler reuses these instead of looking the token up again.
+                    parts.extensions.insert(claims);
+                    result
+                }
+                // Unauthenticated requests are rejected by the handler itself.
+                Err(_) => Ok(()),
+            };
+            per_user.and_then(|_| limits.check(&limits.generation_per_ip, &ip))
+        }
+        RequestClass::Other => Ok(()),
+    };
+
+    match result {
+        Ok(()) => next.run(Request::from_parts(parts, body)).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+// Progressive lockout: once `failures` reaches `threshold`, each further
+// failure doubles the lock, starting at `base_secs` and capped at `max_secs`.
+pub fn lockout_duration(
+    failures: i64,
+    threshold: i64,
+    base_secs: i64,
+    max_secs: i64,
+) -> Option<chrono::Duration> {
+    if threshold <= 0 || failures < threshold {
+        return None;
+    }
+    let exponent = (failures - threshold).min(30) as u32;
+    let secs = base_secs.saturating_mul(1i64 << exponent).min(max_secs);
+    Some(chrono::Duration::seconds(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, StatusCode};
+
+    struct ManualClock(Mutex<Instant>);
+
+    impl ManualClock {
+        fn new() -> Arc<Self> {
+            Arc::new(Self(Mutex::new(Instant::now())))
+        }
+
+        fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn allows_burst_up_to_limit_then_blocks() {
+        let clock = ManualClock::new();
+        let limiter = RateLimiter::new(3, clock.clone());
+
+        for _ in 0..3 {
+            assert!(limiter.check("1.2.3.4").is_ok());
+        }
+        let wait = limiter.check("1.2.3.4").unwrap_err();
+        // 3 per minute refills one token every 20 seconds.
+        assert_eq!(wait.as_secs(), 20);
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let clock = ManualClock::new();
+        let limiter = RateLimiter::new(6, clock.clone());
+
+        for _ in 0..6 {
+            limiter.check("user").unwrap();
+        }
+        assert!(limiter.check("user").is_err());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(limiter.check("user").is_err());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(limiter.check("user").is_ok());
+        assert!(limiter.check("user").is_err());
+
+        clock.advance(Duration::from_secs(600));
+        for _ in 0..6 {
+            assert!(limiter.check("user").is_ok());
+        }
+        assert!(limiter.check("user").is_err());
+    }
+
+    #[test]
+    fn keys_are_independent() {
+        let clock = ManualClock::new();
+        let limiter = RateLimiter::new(1, clock);
+
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_err());
+        assert!(limiter.check("b").is_ok());
+    }
+
+    #[test]
+    fn zero_limit_disables_limiter() {
+        let limiter = RateLimiter::new(0, ManualClock::new());
+        for _ in 0..1000 {
+            assert!(limiter.check("a").is_ok());
+        }
+    }
+
+    #[test]
+    fn prunes_idle_buckets_when_full() {
+        let clock = ManualClock::new();
+        let limiter = RateLimiter::new(60, clock.clone());
+
+        for i in 0..MAX_TRACKED_KEYS {
+            limiter.check(&i.to_string()).unwrap();
+        }
+        clock.advance(Duration::from_secs(60));
+        limiter.check("new").unwrap();
+
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn classifies_requests() {
+        assert_eq!(
+            classify(&Method::POST, "/api/auth/login"),
+            RequestClass::Auth
+        );
+        assert_eq!(classify(&Method::POST, "/api/auth/mfa"), RequestClass::Auth);
+        assert_eq!(
+            classify(&Method::POST, "/api/chats/abc/stream"),
+            RequestClass::Generation
+        );
+        assert_eq!(
+            classify(&Method::POST, "/api/chats/abc/messages"),
+            RequestClass::Generation
+        );
+        assert_eq!(
+            classify(&Method::POST, "/v1/chat/completions"),
+            RequestClass::Generation
+        );
+        assert_eq!(classify(&Method::GET, "/v1/models"), RequestClass::Other);
+        assert_eq!(
+            classify(&Method::GET, "/api/chats/abc/messages"),
+            RequestClass::Other
+        );
+        assert_eq!(classify(&Method::GET, "/api/auth/me"), RequestClass::Other);
+        assert_eq!(
+            classify(&Method::POST, "/api/keys/openai/reveal"),
+            RequestClass::Auth
+        );
+        assert_eq!(
+            classify(&Method::GET, "/api/auth/oidc/keycloak"),
+            RequestClass::Auth
+        );
+        assert_eq!(
+            classify(&Method::GET, "/api/auth/oidc/providers"),
+            RequestClass::Other
+        );
+        assert_eq!(
+            classify(&Method::POST, "/api/auth/oidc/keycloak/link"),
+            RequestClass::Other
+        );
+    }
+
+    #[test]
+    fn lockout_grows_exponentially_and_caps() {
+        let secs = |failures| lockout_duration(failures, 5, 30, 3600).map(|d| d.num_seconds());
+
+        assert_eq!(secs(4), None);
+        assert_eq!(secs(5), Some(30));
+        assert_eq!(secs(6), Some(60));
+        assert_eq!(secs(7), Some(120));
+        assert_eq!(secs(12), Some(3600));
+        assert_eq!(secs(1000), Some(3600));
+        assert_eq!(lockout_duration(100, 0, 30, 3600), None);
+    }
+
+    #[test]
+    fn too_many_requests_sets_retry_after() {
+        let response = AppError::TooManyRequests {
+            message: "slow down".to_string(),
+            retry_after: 42,
+        }
+        .into_response();
+
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "42");
+    }
+}
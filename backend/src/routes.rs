@@ -1,12 +1,15 @@
 use crate::{
     handlers::{
-        auth_handler, chat_handler, key_handler, llm_handler, settings_handler, ws_handler,
+        account_handler, admin_handler, auth_handler, chat_handler, key_handler, llm_handler,
+        mfa_handler, oidc_handler, openai_handler, settings_handler, token_handler,
+        workspace_handler, ws_handler,
     },
-    AppState,
+    rate_limit, AppState,
 };
 use axum::routing::delete;
 use axum::{
-    routing::{get, patch, post},
+    middleware,
+    routing::{get, patch, post, put},
     Router,
 };
 
@@ -14,13 +17,108 @@ pub fn create_router(app_state: AppState) -> Router {
     Router::new()
         .route("/api/auth/register", post(auth_handler::register))
         .route("/api/auth/login", post(auth_handler::login))
-        .route("/api/auth/google", get(auth_handler::google_auth_url))
+        .route(
+            "/api/auth/signup-options",
+            get(auth_handler::signup_options),
+        )
+        .route(
+            "/api/auth/oidc/providers",
+            get(oidc_handler::list_providers),
+        )
+        .route("/api/auth/oidc/callback", get(oidc_handler::oidc_callback))
+        .route("/api/auth/oidc/:provider", get(oidc_handler::oidc_auth_url))
+        .route(
+            "/api/auth/oidc/:provider/link",
+            post(oidc_handler::oidc_link_url).delete(oidc_handler::oidc_unlink),
+        )
+        .route("/api/auth/identities", get(oidc_handler::list_identities))
+        .route("/api/auth/google", get(oidc_handler::google_auth_url))
         .route(
             "/api/auth/google/callback",
-            get(auth_handler::google_callback),
+            get(oidc_handler::oidc_callback),
+        )
+        .route(
+            "/api/auth/google/link",
+            post(oidc_handler::google_link_url).delete(oidc_handler::google_unlink),
+        )
+        .route("/api/auth/refresh", post(auth_handler::refresh))
+        .route("/api/auth/mfa", post(auth_handler::complete_mfa_login))
+        .route("/api/auth/mfa/setup", post(mfa_handler::setup_mfa))
+        .route("/api/auth/mfa/enable", post(mfa_handler::enable_mfa))
+        .route("/api/auth/mfa/disable", post(mfa_handler::disable_mfa))
+        .route(
+            "/api/auth/mfa/recovery-codes",
+            post(mfa_handler::regenerate_recovery_codes),
+        )
+        .route("/api/admin/stats", get(admin_handler::get_stats))
+        .route(
+            "/api/admin/audit-events",
+            get(admin_handler::list_audit_events),
+        )
+        .route(
+            "/api/admin/invites",
+            get(admin_handler::list_invites).post(admin_handler::create_invite),
+        )
+        .route(
+            "/api/admin/invites/:id",
+            delete(admin_handler::delete_invite),
+        )
+        .route(
+            "/api/admin/users",
+            get(admin_handler::list_users).post(admin_handler::create_user),
+        )
+        .route(
+            "/api/admin/users/:id",
+            get(admin_handler::get_user)
+                .patch(admin_handler::update_user)
+                .delete(admin_handler::delete_user),
+        )
+        .route(
+            "/api/admin/users/:id/password",
+            post(admin_handler::reset_user_password),
+        )
+        .route(
+            "/api/admin/users/:id/mfa",
+            delete(mfa_handler::admin_reset_mfa),
+        )
+        .route(
+            "/api/admin/server-keys",
+            get(admin_handler::list_server_keys),
+        )
+        .route(
+            "/api/admin/server-keys/:provider/models",
+            put(admin_handler::set_server_key_models),
+        )
+        .route("/api/auth/verify-email", post(auth_handler::verify_email))
+        .route(
+            "/api/auth/verify-email/request",
+            post(auth_handler::request_email_verification),
+        )
+        .route(
+            "/api/auth/password-reset",
+            post(auth_handler::request_password_reset),
+        )
+        .route(
+            "/api/auth/password-reset/confirm",
+            post(auth_handler::confirm_password_reset),
+        )
+        .route("/api/auth/logout", post(auth_handler::logout))
+        .route("/api/auth/sessions", get(auth_handler::list_sessions))
+        .route(
+            "/api/auth/sessions/:id",
+            delete(auth_handler::revoke_session),
         )
         .route("/api/auth/me", get(auth_handler::get_me))
+        .route("/api/auth/account", delete(account_handler::delete_account))
+        .route(
+            "/api/auth/account/export",
+            get(account_handler::export_account),
+        )
         .route("/api/auth/profile", get(auth_handler::get_me))
+        .route(
+            "/api/auth/profile/password",
+            post(auth_handler::change_password),
+        )
         .route(
             "/api/chats",
             get(chat_handler::list_chats).post(chat_handler::create_chat),
@@ -49,6 +147,14 @@ pub fn create_router(app_state: AppState) -> Router {
             "/api/chats/:id/messages/bulk",
             post(chat_handler::bulk_insert_messages),
         )
+        .route(
+            "/api/chats/:id/members",
+            get(chat_handler::list_chat_members).post(chat_handler::add_chat_member),
+        )
+        .route(
+            "/api/chats/:id/members/:user_id",
+            patch(chat_handler::update_chat_member).delete(chat_handler::remove_chat_member),
+        )
         .route("/api/chats/:id/stream", post(llm_handler::stream_message))
         .route(
             "/api/chats/:id/regenerate",
@@ -62,9 +168,51 @@ pub fn create_router(app_state: AppState) -> Router {
             "/api/keys",
             post(key_handler::add_key).get(key_handler::list_keys),
         )
+        .route("/api/keys/test", post(key_handler::test_key))
+        .route(
+            "/api/keys/providers",
+            get(key_handler::list_available_providers),
+        )
+        .route("/api/keys/:provider", delete(key_handler::delete_key))
+        .route("/api/keys/:provider/reveal", post(key_handler::reveal_key))
+        .route(
+            "/api/keys/:provider/:key_id",
+            patch(key_handler::update_key).delete(key_handler::delete_key_by_id),
+        )
+        .route(
+            "/api/tokens",
+            post(token_handler::create_token).get(token_handler::list_tokens),
+        )
+        .route("/api/tokens/:id", delete(token_handler::revoke_token))
         .route(
-            "/api/keys/:provider",
-            get(key_handler::get_key).delete(key_handler::delete_key),
+            "/api/workspaces",
+            get(workspace_handler::list_workspaces).post(workspace_handler::create_workspace),
+        )
+        .route(
+            "/api/workspaces/:id",
+            get(workspace_handler::get_workspace)
+                .patch(workspace_handler::update_workspace)
+                .delete(workspace_handler::delete_workspace),
+        )
+        .route(
+            "/api/workspaces/:id/members",
+            get(workspace_handler::list_members).post(workspace_handler::add_member),
+        )
+        .route(
+            "/api/workspaces/:id/members/:user_id",
+            patch(workspace_handler::update_member).delete(workspace_handler::remove_member),
+        )
+        .route(
+            "/api/workspaces/:id/keys",
+            get(workspace_handler::list_workspace_keys).post(workspace_handler::add_workspace_key),
+        )
+        .route(
+            "/api/workspaces/:id/keys/:key_id",
+            delete(workspace_handler::delete_workspace_key),
+        )
+        .route(
+            "/api/workspaces/:id/usage",
+            get(workspace_handler::get_workspace_usage),
         )
         .route(
             "/api/settings",
@@ -112,6 +260,19 @@ pub fn create_router(app_state: AppState) -> Router {
             "/api/settings/models/toggle",
             post(settings_handler::toggle_model_enabled),
         )
+        .route(
+            "/api/settings/models/key",
+            put(settings_handler::set_model_key),
+        )
+        .route("/v1/models", get(openai_handler::list_models))
+        .route(
+            "/v1/chat/completions",
+            post(openai_handler::chat_completions),
+        )
         .route("/ws", get(ws_handler::websocket_handler))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            rate_limit::enforce,
+        ))
         .with_state(app_state)
 }
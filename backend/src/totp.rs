@@ -0,0 +1,143 @@
+// RFC 6238 time-based one-time passwords (SHA-1, 6 digits, 30 second steps),
+// the variant every authenticator app understands.
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng, RngCore};
+use sha1::Sha1;
+
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+// Generates a new 160-bit secret, base32 encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32::encode(BASE32, &secret)
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+// The code an authenticator app shows at `unix_time`.
+pub fn generate(secret: &str, unix_time: i64) -> Option<String> {
+    let secret = base32::decode(BASE32, secret)?;
+    Some(format!(
+        "{:0width$}",
+        code_at(&secret, unix_time / STEP_SECONDS),
+        width = DIGITS as usize
+    ))
+}
+
+// Checks a code against the current step and one step either side to allow for
+// clock drift. Returns the matching step so callers can reject replays.
+pub fn verify(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = base32::decode(BASE32, secret)?;
+
+    let current = unix_time / STEP_SECONDS;
+    (current - 1..=current + 1).find(|&step| code_at(&secret, step) == code)
+}
+
+// One-time recovery codes, formatted as "xxxxx-xxxxx".
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .map(|_| {
+            let raw: String = (&mut rng)
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &raw[..5], &raw[5..])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The RFC 6238 SHA-1 seed, "12345678901234567890".
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        // The RFC lists 8-digit codes; 6-digit codes are their last six digits.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(
+                generate(RFC_SECRET, time).as_deref(),
+                Some(code),
+                "T = {}",
+                time
+            );
+            assert_eq!(verify(RFC_SECRET, code, time), Some(time / STEP_SECONDS));
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_clock_drift() {
+        let now = 1234567890;
+        let step = now / STEP_SECONDS;
+        for drift in [-1, 0, 1] {
+            let code = generate(RFC_SECRET, now + drift * STEP_SECONDS).unwrap();
+            assert_eq!(verify(RFC_SECRET, &code, now), Some(step + drift));
+        }
+        for drift in [-2, 2] {
+            let code = generate(RFC_SECRET, now + drift * STEP_SECONDS).unwrap();
+            assert_eq!(verify(RFC_SECRET, &code, now), None);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        assert_eq!(verify(RFC_SECRET, "287 082", 59), Some(1));
+        assert_eq!(verify(RFC_SECRET, "28708", 59), None);
+        assert_eq!(verify(RFC_SECRET, "2870820", 59), None);
+        assert_eq!(verify(RFC_SECRET, "28708a", 59), None);
+        assert_eq!(verify("not base32!", "287082", 59), None);
+    }
+
+    #[test]
+    fn generated_secrets_round_trip() {
+        let secret = generate_secret();
+        assert_eq!(base32::decode(BASE32, &secret).unwrap().len(), 20);
+        let code = generate(&secret, 1_700_000_000).unwrap();
+        assert!(verify(&secret, &code, 1_700_000_000).is_some());
+    }
+}
@@ -0,0 +1,40 @@
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+// One row per LLM request. Token counts are not reported by every provider
+// through `LLMClient`, so usage is tracked in characters. Requests made with a
+// workspace's key are also attributed to that workspace.
+pub struct UsageRecord<'a> {
+    pub user_id: &'a str,
+    pub workspace_id: Option<&'a str>,
+    pub provider: &'a str,
+    pub model: &'a str,
+    pub source: &'a str,
+    pub prompt_chars: usize,
+    pub completion_chars: usize,
+    pub success: bool,
+}
+
+pub async fn record_usage(pool: &SqlitePool, record: UsageRecord<'_>) {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO usage_logs (id, user_id, workspace_id, provider, model, source, prompt_chars, completion_chars, success)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(record.user_id)
+    .bind(record.workspace_id)
+    .bind(record.provider)
+    .bind(record.model)
+    .bind(record.source)
+    .bind(record.prompt_chars as i64)
+    .bind(record.completion_chars as i64)
+    .bind(record.success)
+    .execute(pool)
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!("failed to record usage: {}", e);
+    }
+}
@@ -0,0 +1,230 @@
+mod common;
+
+use backend::totp;
+use common::spawn_app;
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+async fn register_then_login() {
+    let app = spawn_app().await;
+
+    let (status, user) = app
+        .register("ada@example.com", "correct horse battery")
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(user["email"], "ada@example.com");
+    assert!(user.get("passwordHash").is_none());
+
+    let (status, body) = app.login("ada@example.com", "correct horse battery").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["user"]["id"], user["id"]);
+    let token = body["token"].as_str().unwrap();
+
+    let (status, me) = app.get("/api/auth/me", token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["user_id"], user["id"]);
+}
+
+#[tokio::test]
+async fn rejects_duplicate_and_invalid_registrations() {
+    let app = spawn_app().await;
+    app.signup("ada@example.com").await;
+
+    let (status, _) = app.register("ada@example.com", "another password").await;
+    assert!(!status.is_success(), "duplicate email was accepted");
+
+    let (status, _) = app.register("not-an-email", "correct horse battery").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rejects_wrong_password_and_missing_token() {
+    let app = spawn_app().await;
+    app.signup("ada@example.com").await;
+
+    let (status, _) = app.login("ada@example.com", "wrong password").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app
+        .login("nobody@example.com", "correct horse battery")
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.send(Method::GET, "/api/chats", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = app.get("/api/chats", "not-a-token").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn changing_the_password_needs_the_current_one_and_revokes_tokens() {
+    let app = spawn_app().await;
+    let session = app.signup("ada@example.com").await;
+    let (status, created) = app
+        .post(
+            "/api/tokens",
+            &session,
+            json!({ "name": "laptop", "scopes": ["chats:read"] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", created);
+    let pat = created["token"].as_str().unwrap();
+    let (status, _) = app.get("/api/chats", pat).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = app
+        .post(
+            "/api/auth/profile/password",
+            &session,
+            json!({ "currentPassword": "wrong password", "newPassword": "a new password" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = app
+        .post(
+            "/api/auth/profile/password",
+            &session,
+            json!({ "currentPassword": "correct horse battery", "newPassword": "a new password" }),
+        )
+        .await;
+    assert!(status.is_success());
+
+    let (status, _) = app.get("/api/chats", pat).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = app.login("ada@example.com", "a new password").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn second_factor_codes_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let session = app.signup("ada@example.com").await;
+    let (status, setup) = app.post("/api/auth/mfa/setup", &session, json!({})).await;
+    assert_eq!(status, StatusCode::OK, "{}", setup);
+    let secret = setup["secret"].as_str().unwrap();
+    let now = chrono::Utc::now().timestamp();
+    let code = totp::generate(secret, now).unwrap();
+
+    let (status, _) = app
+        .post(
+            "/api/auth/mfa/enable",
+            &session,
+            json!({ "password": "correct horse battery", "code": code }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = app.login("ada@example.com", "correct horse battery").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["mfa_required"], true);
+    let mfa_token = body["mfa_token"].as_str().unwrap();
+
+    // The code that enabled 2FA is spent.
+    let (status, _) = app
+        .send(
+            Method::POST,
+            "/api/auth/mfa",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": code })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let next_code = totp::generate(secret, now + 30).unwrap();
+    let (status, body) = app
+        .send(
+            Method::POST,
+            "/api/auth/mfa",
+            None,
+            Some(json!({ "mfa_token": mfa_token, "code": next_code })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    // Turning 2FA off takes the password as well as a code.
+    let (status, _) = app
+        .post(
+            "/api/auth/mfa/disable",
+            &session,
+            json!({ "password": "wrong password", "code": totp::generate(secret, now - 30).unwrap() }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn enabling_two_factor_needs_a_password_and_a_session() {
+    let app = spawn_app().await;
+    let session = app.signup("ada@example.com").await;
+    let (_, created) = app
+        .post(
+            "/api/tokens",
+            &session,
+            json!({ "name": "laptop", "scopes": ["chats:read", "chats:write", "generate", "keys:manage"] }),
+        )
+        .await;
+    let pat = created["token"].as_str().unwrap();
+
+    for path in [
+        "/api/auth/mfa/setup",
+        "/api/auth/mfa/enable",
+        "/api/auth/mfa/disable",
+        "/api/auth/mfa/recovery-codes",
+    ] {
+        let (status, _) = app.post(path, pat, json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", path);
+    }
+
+    let (_, setup) = app.post("/api/auth/mfa/setup", &session, json!({})).await;
+    let code = totp::generate(
+        setup["secret"].as_str().unwrap(),
+        chrono::Utc::now().timestamp(),
+    )
+    .unwrap();
+    let (status, _) = app
+        .post(
+            "/api/auth/mfa/enable",
+            &session,
+            json!({ "password": "wrong password", "code": code }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = app
+        .post(
+            "/api/auth/mfa/enable",
+            &session,
+            json!({ "password": "correct horse battery", "code": code }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[tokio::test]
+async fn token_lifetimes_are_bounded() {
+    let app = spawn_app().await;
+    let session = app.signup("ada@example.com").await;
+
+    for days in [0, -1, 3651, i64::MAX] {
+        let (status, body) = app
+            .post(
+                "/api/tokens",
+                &session,
+                json!({ "name": "ci", "scopes": ["chats:read"], "expires_in_days": days }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{} days: {}", days, body);
+    }
+
+    let (status, body) = app
+        .post(
+            "/api/tokens",
+            &session,
+            json!({ "name": "ci", "scopes": ["chats:read"], "expires_in_days": 3650 }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use common::{spawn_app, spawn_app_with};
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn chat_crud() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+
+    let (status, chat) = app
+        .post(
+            "/api/chats",
+            &token,
+            json!({ "title": "Plans", "provider": "mock", "model": "echo", "system_prompt": "Be brief." }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(chat["title"], "Plans");
+    assert_eq!(chat["systemPrompt"], "Be brief.");
+    assert_eq!(chat["pinned"], false);
+    let chat_id = chat["id"].as_str().unwrap();
+
+    let (status, chats) = app.get("/api/chats", &token).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(chats.as_array().unwrap().len(), 1);
+    assert_eq!(chats[0]["id"], chat_id);
+
+    let (status, chat) = app
+        .patch(
+            &format!("/api/chats/{}", chat_id),
+            &token,
+            json!({ "title": "Holiday plans", "pinned": true }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(chat["title"], "Holiday plans");
+    assert_eq!(chat["pinned"], true);
+
+    let (status, _) = app.delete(&format!("/api/chats/{}", chat_id), &token).await;
+    assert!(status.is_success());
+
+    let (_, chats) = app.get("/api/chats", &token).await;
+    assert!(chats.as_array().unwrap().is_empty());
+    let (status, _) = app
+        .get(&format!("/api/chats/{}/messages", chat_id), &token)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn chats_are_private_to_their_owner() {
+    let app = spawn_app().await;
+    let ada = app.signup("ada@example.com").await;
+    let bob = app.signup("bob@example.com").await;
+    let chat_id = app.create_mock_chat(&ada, "echo").await;
+
+    let (_, chats) = app.get("/api/chats", &bob).await;
+    assert!(chats.as_array().unwrap().is_empty());
+
+    let path = format!("/api/chats/{}", chat_id);
+    let (status, _) = app.get(&format!("{}/messages", path), &bob).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.patch(&path, &bob, json!({ "title": "mine now" })).await;
+    assert!(!status.is_success());
+    let (status, _) = app.delete(&path, &bob).await;
+    assert!(!status.is_success());
+    let (status, _) = app
+        .post(
+            &format!("{}/messages", path),
+            &bob,
+            json!({ "content": "hi" }),
+        )
+        .await;
+    assert!(!status.is_success());
+}
+
+#[tokio::test]
+async fn send_message_records_reply_and_route() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "echo").await;
+
+    let (status, reply) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &token,
+            json!({ "content": "Where should we go?" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", reply);
+    assert_eq!(reply["role"], "assistant");
+    assert_eq!(reply["content"], "Where should we go?");
+    assert_eq!(reply["provider"], "mock");
+    assert_eq!(reply["model"], "echo");
+
+    let messages = app.messages(&token, &chat_id).await;
+    let roles: Vec<&str> = messages
+        .iter()
+        .map(|m| m["role"].as_str().unwrap())
+        .collect();
+    assert_eq!(roles, ["user", "assistant"]);
+
+    // The first message names the chat
+    let (_, chats) = app.get("/api/chats", &token).await;
+    assert_eq!(chats[0]["title"], "Where should we go?");
+}
+
+#[tokio::test]
+async fn failing_provider_falls_back_to_the_next_route() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let (status, chat) = app
+        .post(
+            "/api/chats",
+            &token,
+            json!({
+                "title": "New Chat",
+                "provider": "mock",
+                "model": "error=503",
+                "fallback_chain": [{ "provider": "mock", "model": "text=from the fallback" }],
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", chat);
+    let chat_id = chat["id"].as_str().unwrap();
+
+    let (status, reply) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &token,
+            json!({ "content": "hello" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", reply);
+    assert_eq!(reply["content"], "from the fallback");
+    assert_eq!(reply["model"], "text=from the fallback");
+
+    // Without a fallback the provider's status comes back once retries run out
+    let chat_id = app.create_mock_chat(&token, "error=503").await;
+    let (status, _) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &token,
+            json!({ "content": "hello" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn branching_copies_history_into_one_chat_per_model() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "echo").await;
+    app.post(
+        &format!("/api/chats/{}/messages", chat_id),
+        &token,
+        json!({ "content": "first question" }),
+    )
+    .await;
+
+    let (status, branches) = app
+        .post(
+            &format!("/api/chats/{}/parallel", chat_id),
+            &token,
+            json!({
+                "content": "second question",
+                "models": [
+                    { "provider": "mock", "model": "text=answer one" },
+                    { "provider": "mock", "model": "text=answer two" },
+                ],
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", branches);
+    let branches = branches.as_array().unwrap();
+    assert_eq!(branches.len(), 2);
+
+    let parent = app.messages(&token, &chat_id).await;
+    assert_eq!(parent.last().unwrap()["content"], "second question");
+
+    for (branch, answer) in branches.iter().zip(["answer one", "answer two"]) {
+        assert_eq!(branch["isBranch"], true);
+        assert_eq!(branch["parentChatId"], chat_id);
+        assert_eq!(branch["branchPointMessageId"], parent.last().unwrap()["id"]);
+        let branch_id = branch["id"].as_str().unwrap();
+
+        let history = app.messages(&token, branch_id).await;
+        let contents: Vec<&str> = history
+            .iter()
+            .map(|m| m["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(contents, ["first question", "first question"]);
+
+        let (status, reply) = app
+            .post(
+                &format!("/api/chats/{}/messages", branch_id),
+                &token,
+                json!({ "content": "second question" }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", reply);
+        assert_eq!(reply["content"], answer);
+    }
+
+    let (_, chats) = app.get("/api/chats", &token).await;
+    assert_eq!(chats.as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn mock_provider_is_off_unless_enabled() {
+    let app = spawn_app_with(|config| config.enable_mock_provider = false).await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "echo").await;
+
+    let (status, body) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &token,
+            json!({ "content": "hello" }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "api key for provider 'mock' not found.");
+}
+
+#[tokio::test]
+async fn sharing_does_not_reveal_which_emails_have_accounts() {
+    let app = spawn_app().await;
+    let ada = app.signup("ada@example.com").await;
+    app.signup("bob@example.com").await;
+    app.signup("eve@example.com").await;
+    let chat_id = app.create_mock_chat(&ada, "echo").await;
+    let members = format!("/api/chats/{}/members", chat_id);
+
+    // A stranger's address is refused exactly like one nobody registered.
+    let stranger = app
+        .post(&members, &ada, json!({ "email": "eve@example.com" }))
+        .await;
+    let unknown = app
+        .post(&members, &ada, json!({ "email": "nobody@example.com" }))
+        .await;
+    assert_eq!(stranger.0, StatusCode::BAD_REQUEST);
+    assert_eq!(stranger, unknown);
+
+    let (status, workspace) = app
+        .post("/api/workspaces", &ada, json!({ "name": "team" }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", workspace);
+    let (status, _) = app
+        .post(
+            &format!(
+                "/api/workspaces/{}/members",
+                workspace["id"].as_str().unwrap()
+            ),
+            &ada,
+            json!({ "email": "bob@example.com" }),
+        )
+        .await;
+    assert!(status.is_success());
+
+    let (status, member) = app
+        .post(&members, &ada, json!({ "email": "bob@example.com" }))
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", member);
+    assert_eq!(member["email"], "bob@example.com");
+    assert_eq!(member["role"], "viewer");
+}
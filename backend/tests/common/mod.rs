@@ -0,0 +1,207 @@
+#![allow(dead_code)] // each test binary uses a different part
+
+use backend::{config::Config, crypto::Keyring, database, routes, AppState};
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+use sqlx::sqlite::SqlitePoolOptions;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+// The config every test server starts from. Config only reads the
+// environment, so the variables that matter are pinned here (ahead of any
+// .env) before it is read once.
+fn base_config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        for (name, value) in [
+            ("DATABASE_URL", "sqlite::memory:"),
+            ("JWT_SECRET", "integration-test-jwt-secret"),
+            ("ENCRYPTION_KEY", "0123456789abcdef0123456789abcdef"),
+            ("RATE_LIMIT_ENABLED", "false"),
+            ("ALLOW_SIGNUP", "true"),
+            ("SIGNUP_ALLOWED_DOMAINS", ""),
+            ("REQUIRE_INVITE_CODE", "false"),
+            ("MAIL_BACKEND", "log"),
+            ("AUDIT_LOG_FILE", ""),
+            ("ENABLE_MOCK_PROVIDER", "true"),
+            ("LLM_RETRY_MAX_ATTEMPTS", "2"),
+            ("LLM_RETRY_BASE_DELAY_MS", "1"),
+        ] {
+            std::env::set_var(name, value);
+        }
+        std::env::remove_var("ENCRYPTION_KEYS");
+        Config::from_env()
+    })
+}
+
+// A server on a free local port, backed by its own in-memory database.
+pub struct TestApp {
+    pub addr: SocketAddr,
+    pub client: reqwest::Client,
+}
+
+pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Config)) -> TestApp {
+    let mut config = base_config().clone();
+    configure(&mut config);
+
+    // Every "sqlite::memory:" pool gets a database of its own, shared by the
+    // pool's connections.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(5)
+        .min_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("failed to open in-memory database");
+    database::init_schema(&pool).await;
+
+    let keyring = Arc::new(Keyring::from_config(&config));
+    let app = routes::create_router(AppState::new(pool, config, keyring));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+
+    TestApp {
+        addr,
+        client: reqwest::Client::new(),
+    }
+}
+
+impl TestApp {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn request(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        let request = self.client.request(method, self.url(path));
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    // Sends a JSON request and returns the status with the decoded body
+    // (Null when there is none).
+    pub async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = self.request(method, path, token);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.expect("request failed");
+        let status = response.status();
+        let text = response.text().await.unwrap();
+        (status, serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    pub async fn get(&self, path: &str, token: &str) -> (StatusCode, Value) {
+        self.send(Method::GET, path, Some(token), None).await
+    }
+
+    pub async fn post(&self, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        self.send(Method::POST, path, Some(token), Some(body)).await
+    }
+
+    pub async fn patch(&self, path: &str, token: &str, body: Value) -> (StatusCode, Value) {
+        self.send(Method::PATCH, path, Some(token), Some(body))
+            .await
+    }
+
+    pub async fn delete(&self, path: &str, token: &str) -> (StatusCode, Value) {
+        self.send(Method::DELETE, path, Some(token), None).await
+    }
+
+    pub async fn register(&self, email: &str, password: &str) -> (StatusCode, Value) {
+        let body = json!({ "name": "Test User", "email": email, "password": password });
+        self.send(Method::POST, "/api/auth/register", None, Some(body))
+            .await
+    }
+
+    pub async fn login(&self, email: &str, password: &str) -> (StatusCode, Value) {
+        let body = json!({ "email": email, "password": password });
+        self.send(Method::POST, "/api/auth/login", None, Some(body))
+            .await
+    }
+
+    // Registers a user and returns an access token for them.
+    pub async fn signup(&self, email: &str) -> String {
+        let (status, _) = self.register(email, "correct horse battery").await;
+        assert_eq!(status, StatusCode::OK, "registering {}", email);
+        let (status, body) = self.login(email, "correct horse battery").await;
+        assert_eq!(status, StatusCode::OK, "logging in {}", email);
+        body["token"]
+            .as_str()
+            .expect("no token in login response")
+            .to_string()
+    }
+
+    // Creates a chat on the mock provider; `script` is the mock model name.
+    pub async fn create_mock_chat(&self, token: &str, script: &str) -> String {
+        let body = json!({ "title": "New Chat", "provider": "mock", "model": script });
+        let (status, chat) = self.post("/api/chats", token, body).await;
+        assert_eq!(status, StatusCode::OK, "{}", chat);
+        chat["id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn messages(&self, token: &str, chat_id: &str) -> Vec<Value> {
+        let (status, messages) = self
+            .get(&format!("/api/chats/{}/messages", chat_id), token)
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", messages);
+        messages.as_array().unwrap().clone()
+    }
+
+    // Streamed replies are saved from a background task once the stream
+    // ends, so wait for the assistant message to appear.
+    pub async fn wait_for_reply(&self, token: &str, chat_id: &str) -> Value {
+        for _ in 0..100 {
+            let messages = self.messages(token, chat_id).await;
+            if let Some(reply) = messages.iter().find(|m| m["role"] == "assistant") {
+                return reply.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("no assistant reply was saved in chat {}", chat_id);
+    }
+}
+
+// Splits a text/event-stream body into (event, data) pairs.
+pub fn parse_events(body: &str) -> Vec<(String, Value)> {
+    body.split("\n\n")
+        .filter(|block| !block.trim().is_empty())
+        .map(|block| {
+            let mut event = String::new();
+            let mut data = String::new();
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event: ") {
+                    event = value.to_string();
+                } else if let Some(value) = line.strip_prefix("data: ") {
+                    data.push_str(value);
+                }
+            }
+            (event, serde_json::from_str(&data).unwrap_or(Value::Null))
+        })
+        .collect()
+}
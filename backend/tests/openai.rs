@@ -0,0 +1,50 @@
+mod common;
+
+use common::spawn_app;
+use reqwest::StatusCode;
+use serde_json::json;
+
+#[tokio::test]
+async fn saved_chat_keeps_the_normalized_conversation() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+
+    let (status, completion) = app
+        .post(
+            "/v1/chat/completions",
+            &token,
+            json!({
+                "model": "mock/echo",
+                "neko_save_chat": true,
+                "messages": [
+                    { "role": "developer", "content": "Answer briefly." },
+                    { "role": "user", "content": [
+                        { "type": "text", "text": "What is in" },
+                        { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } },
+                        { "type": "text", "text": "this picture?" },
+                    ] },
+                ],
+            }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", completion);
+    assert_eq!(
+        completion["choices"][0]["message"]["content"],
+        "What is in\nthis picture?"
+    );
+
+    let (_, chats) = app.get("/api/chats", &token).await;
+    let chat_id = chats[0]["id"].as_str().unwrap();
+    let messages = app.messages(&token, chat_id).await;
+    let saved: Vec<(&str, &str)> = messages
+        .iter()
+        .map(|m| (m["role"].as_str().unwrap(), m["content"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        saved,
+        [
+            ("user", "What is in\nthis picture?"),
+            ("assistant", "What is in\nthis picture?"),
+        ]
+    );
+}
@@ -0,0 +1,218 @@
+mod common;
+
+use common::{parse_events, spawn_app, TestApp};
+use futures_util::StreamExt;
+use reqwest::{header, Method, StatusCode};
+use serde_json::{json, Value};
+
+async fn stream_reply(
+    app: &TestApp,
+    token: &str,
+    chat_id: &str,
+    content: &str,
+) -> Vec<(String, Value)> {
+    let response = app
+        .request(
+            Method::POST,
+            &format!("/api/chats/{}/stream", chat_id),
+            Some(token),
+        )
+        .header(header::ACCEPT, "text/event-stream")
+        .json(&json!({ "content": content }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+    parse_events(&response.text().await.unwrap())
+}
+
+fn text_of(events: &[(String, Value)], name: &str) -> String {
+    events
+        .iter()
+        .filter(|(event, _)| event == name)
+        .map(|(_, data)| data["text"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn streamed_reply_is_saved() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app
+        .create_mock_chat(&token, "text=Hello there, world")
+        .await;
+
+    let events = stream_reply(&app, &token, &chat_id, "hi").await;
+    assert_eq!(
+        events[0],
+        (
+            "route".to_string(),
+            json!({ "provider": "mock", "model": "text=Hello there, world" })
+        )
+    );
+    assert_eq!(text_of(&events, "text"), "Hello there, world");
+    assert!(events.iter().all(|(event, _)| event != "error"));
+
+    let reply = app.wait_for_reply(&token, &chat_id).await;
+    assert_eq!(reply["content"], "Hello there, world");
+    assert_eq!(reply["provider"], "mock");
+    assert_eq!(reply["model"], "text=Hello there, world");
+    assert!(reply["reasoning"].is_null());
+
+    let messages = app.messages(&token, &chat_id).await;
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"], "hi");
+}
+
+#[tokio::test]
+async fn reasoning_streams_and_is_saved_apart_from_the_answer() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app
+        .create_mock_chat(&token, "reasoning=Let me think.;text=Forty-two.")
+        .await;
+
+    let events = stream_reply(&app, &token, &chat_id, "What is the answer?").await;
+    assert_eq!(text_of(&events, "reasoning"), "Let me think.");
+    assert_eq!(text_of(&events, "text"), "Forty-two.");
+
+    let reply = app.wait_for_reply(&token, &chat_id).await;
+    assert_eq!(reply["content"], "Forty-two.");
+    assert_eq!(reply["reasoning"], "Let me think.");
+}
+
+#[tokio::test]
+async fn plain_stream_carries_only_the_answer() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app
+        .create_mock_chat(&token, "reasoning=hidden;fail_after=2;text=one two three")
+        .await;
+
+    let body = app
+        .request(
+            Method::POST,
+            &format!("/api/chats/{}/stream", chat_id),
+            Some(&token),
+        )
+        .json(&json!({ "content": "count" }))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "one two ERROR: Mock: scripted failure after 2 chunks");
+}
+
+#[tokio::test]
+async fn partial_reply_is_saved_when_the_provider_fails_mid_stream() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app
+        .create_mock_chat(&token, "fail_after=2;text=one two three four")
+        .await;
+
+    let events = stream_reply(&app, &token, &chat_id, "count").await;
+    let (last_event, last_data) = events.last().unwrap();
+    assert_eq!(last_event, "error");
+    assert_eq!(
+        last_data["message"],
+        "Mock: scripted failure after 2 chunks"
+    );
+
+    let reply = app.wait_for_reply(&token, &chat_id).await;
+    assert_eq!(reply["content"], "one two ");
+}
+
+#[tokio::test]
+async fn partial_reply_is_saved_when_the_client_disconnects() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let words: Vec<String> = (1..=40).map(|n| format!("w{}", n)).collect();
+    let script = format!("delay=25;text={}", words.join(" "));
+    let chat_id = app.create_mock_chat(&token, &script).await;
+
+    let response = app
+        .request(
+            Method::POST,
+            &format!("/api/chats/{}/stream", chat_id),
+            Some(&token),
+        )
+        .header(header::ACCEPT, "text/event-stream")
+        .json(&json!({ "content": "go" }))
+        .send()
+        .await
+        .unwrap();
+    let mut body = response.bytes_stream();
+    let mut received = String::new();
+    while !received.contains("event: text") {
+        received.push_str(&String::from_utf8_lossy(
+            &body.next().await.unwrap().unwrap(),
+        ));
+    }
+    drop(body);
+
+    let reply = app.wait_for_reply(&token, &chat_id).await;
+    let saved = reply["content"].as_str().unwrap();
+    assert!(saved.starts_with("w1 "), "{}", saved);
+    assert!(
+        saved.len() < words.join(" ").len(),
+        "the whole reply was saved: {}",
+        saved
+    );
+}
+
+#[tokio::test]
+async fn regenerate_answers_the_last_message_without_adding_one() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "echo").await;
+    let (status, _) = app
+        .post(
+            &format!("/api/chats/{}/messages/bulk", chat_id),
+            &token,
+            json!({ "messages": [{ "role": "user", "content": "say this back" }] }),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let response = app
+        .request(
+            Method::POST,
+            &format!("/api/chats/{}/regenerate", chat_id),
+            Some(&token),
+        )
+        .header(header::ACCEPT, "text/event-stream")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = parse_events(&response.text().await.unwrap());
+    assert_eq!(text_of(&events, "text"), "say this back");
+
+    let reply = app.wait_for_reply(&token, &chat_id).await;
+    assert_eq!(reply["content"], "say this back");
+    assert_eq!(app.messages(&token, &chat_id).await.len(), 2);
+}
+
+#[tokio::test]
+async fn stream_setup_errors_are_reported_in_band() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "error=400").await;
+
+    let events = stream_reply(&app, &token, &chat_id, "hello").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "error");
+    assert_eq!(events[0].1["message"], "Mock (HTTP 400): scripted failure");
+
+    // Only the user's message is kept
+    let messages = app.messages(&token, &chat_id).await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "user");
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{spawn_app, TestApp};
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(app: &TestApp, token: &str) -> Socket {
+    let url = format!("ws://{}/ws?token={}", app.addr, token);
+    let (socket, _) = connect_async(url)
+        .await
+        .expect("websocket handshake failed");
+    socket
+}
+
+// The next chat message pushed to the socket, or None if nothing arrives
+// within `wait`.
+async fn next_message(socket: &mut Socket, wait: Duration) -> Option<Value> {
+    loop {
+        match tokio::time::timeout(wait, socket.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => return Some(serde_json::from_str(&text).unwrap()),
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+#[tokio::test]
+async fn new_messages_reach_the_chat_owner() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "text=Pong").await;
+    let mut socket = connect(&app, &token).await;
+
+    let (status, _) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &token,
+            json!({ "content": "Ping" }),
+        )
+        .await;
+    assert!(status.is_success());
+
+    let user = next_message(&mut socket, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(user["chatId"], chat_id);
+    assert_eq!(user["role"], "user");
+    assert_eq!(user["content"], "Ping");
+
+    let reply = next_message(&mut socket, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(reply["chatId"], chat_id);
+    assert_eq!(reply["role"], "assistant");
+    assert_eq!(reply["content"], "Pong");
+}
+
+#[tokio::test]
+async fn streamed_replies_are_pushed_once_saved() {
+    let app = spawn_app().await;
+    let token = app.signup("ada@example.com").await;
+    let chat_id = app.create_mock_chat(&token, "text=streamed answer").await;
+    let mut socket = connect(&app, &token).await;
+
+    app.request(
+        reqwest::Method::POST,
+        &format!("/api/chats/{}/stream", chat_id),
+        Some(&token),
+    )
+    .json(&json!({ "content": "question" }))
+    .send()
+    .await
+    .unwrap()
+    .text()
+    .await
+    .unwrap();
+
+    let user = next_message(&mut socket, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(user["content"], "question");
+    let reply = next_message(&mut socket, Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(reply["content"], "streamed answer");
+    assert_eq!(reply["provider"], "mock");
+}
+
+#[tokio::test]
+async fn other_users_do_not_receive_the_messages() {
+    let app = spawn_app().await;
+    let ada = app.signup("ada@example.com").await;
+    let bob = app.signup("bob@example.com").await;
+    let chat_id = app.create_mock_chat(&ada, "echo").await;
+    let mut bobs_socket = connect(&app, &bob).await;
+
+    let (status, _) = app
+        .post(
+            &format!("/api/chats/{}/messages", chat_id),
+            &ada,
+            json!({ "content": "just for me" }),
+        )
+        .await;
+    assert!(status.is_success());
+
+    assert!(next_message(&mut bobs_socket, Duration::from_millis(300))
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn rejects_connections_without_a_valid_token() {
+    let app = spawn_app().await;
+    let url = format!("ws://{}/ws?token=not-a-token", app.addr);
+    assert!(connect_async(url).await.is_err());
+}
+
+#[tokio::test]
+async fn socket_closes_once_its_session_is_revoked() {
+    let app = spawn_app().await;
+    let revoked = app.signup("ada@example.com").await;
+    let (_, body) = app.login("ada@example.com", "correct horse battery").await;
+    let other_session = body["token"].as_str().unwrap().to_string();
+    let chat_id = app.create_mock_chat(&other_session, "echo").await;
+    let mut socket = connect(&app, &revoked).await;
+
+    let (status, _) = app.post("/api/auth/logout", &revoked, json!({})).await;
+    assert!(status.is_success());
+
+    // The next broadcast makes the socket check its session and hang up
+    // instead of delivering it.
+    app.post(
+        &format!("/api/chats/{}/messages", chat_id),
+        &other_session,
+        json!({ "content": "not for the logged out socket" }),
+    )
+    .await;
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), socket.next())
+        .await
+        .expect("socket stayed open");
+    match frame {
+        Some(Ok(Message::Close(Some(close)))) => assert_eq!(close.reason, "session ended"),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+}
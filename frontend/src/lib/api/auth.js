@@ -10,10 +10,18 @@ export const authAPI = {
     );
   },
 
+  // Second login step for accounts with two-factor authentication
+  async verifyMfa(mfaToken, code) {
+    return withErrorHandling(
+      () => api.post('/api/auth/mfa', { mfa_token: mfaToken, code }),
+      'Invalid verification code.'
+    );
+  },
+
   // Register new user
-  async register(email, password, name) {
+  async register(email, password, name, inviteCode) {
     return withErrorHandling(
-      () => api.post(endpoints.auth.register, { email, password, name }),
+      () => api.post(endpoints.auth.register, { email, password, name, invite_code: inviteCode }),
       'Registration failed. Please try again.'
     );
   },
@@ -51,11 +59,13 @@ export const authAPI = {
   },
 
   // Change password
-  async changePassword(currentPassword, newPassword) {
+  // `code` is the 2FA code, required when two-factor authentication is on
+  async changePassword(currentPassword, newPassword, code) {
     return withErrorHandling(
       () => api.post(`${endpoints.auth.profile}/password`, {
         currentPassword,
-        newPassword
+        newPassword,
+        code: code || undefined
       }),
       'Failed to change password.'
     );
@@ -80,27 +90,89 @@ export const authAPI = {
     );
   },
 
-  // Delete account
-  async deleteAccount(password) {
+  // Delete account (asks for the password, and the 2FA code when enabled)
+  async deleteAccount(password, code) {
     return withErrorHandling(
-      () => api.delete(endpoints.auth.profile, { password }),
+      () => api.delete(endpoints.auth.account, { password, code }),
       'Failed to delete account.'
     );
   },
 
-  // Google OAuth
-  async getGoogleAuthUrl() {
+  // Everything stored about the account, as JSON
+  async exportAccount() {
+    return withErrorHandling(
+      () => api.get(endpoints.auth.accountExport),
+      'Failed to export account data.'
+    );
+  },
+
+  // Confirm email address with the token from the verification email
+  async verifyEmail(token) {
+    return withErrorHandling(
+      () => api.post('/api/auth/verify-email', { token }),
+      'Failed to verify email.'
+    );
+  },
+
+  // Send a new verification email
+  async requestEmailVerification() {
+    return withErrorHandling(
+      () => api.post('/api/auth/verify-email/request'),
+      'Failed to send verification email.'
+    );
+  },
+
+  // Which signup restrictions the server enforces
+  async getSignupOptions() {
+    return withErrorHandling(
+      () => api.get('/api/auth/signup-options'),
+      'Failed to load signup options.'
+    );
+  },
+
+  // Configured single sign-on (OpenID Connect) providers
+  async getOidcProviders() {
+    return withErrorHandling(
+      () => api.get('/api/auth/oidc/providers'),
+      'Failed to load sign-in providers.'
+    );
+  },
+
+  async getOidcAuthUrl(provider, inviteCode) {
+    return withErrorHandling(
+      () => api.get(`/api/auth/oidc/${provider}`, { invite_code: inviteCode || undefined }),
+      'Failed to start sign-in.'
+    );
+  },
+
+  // Shared callback for every provider; the state identifies which one
+  async oidcCallback(code, state) {
+    return withErrorHandling(
+      () => api.get('/api/auth/oidc/callback', { code, state }),
+      'Sign-in failed.'
+    );
+  },
+
+  // Link a provider account to the logged-in user
+  async getOidcLinkUrl(provider) {
+    return withErrorHandling(
+      () => api.post(`/api/auth/oidc/${provider}/link`),
+      'Failed to get link URL.'
+    );
+  },
+
+  async unlinkOidc(provider) {
     return withErrorHandling(
-      () => api.get('/api/auth/google'),
-      'Failed to get Google auth URL.'
+      () => api.delete(`/api/auth/oidc/${provider}/link`),
+      'Failed to unlink account.'
     );
   },
 
-  // Google OAuth callback
-  async googleCallback(code, state) {
+  // Provider accounts linked to the logged-in user
+  async getIdentities() {
     return withErrorHandling(
-      () => api.get(`/api/auth/google/callback?code=${code}&state=${state}`),
-      'Google authentication failed.'
+      () => api.get('/api/auth/identities'),
+      'Failed to load linked accounts.'
     );
   },
-};
\ No newline at end of file
+};
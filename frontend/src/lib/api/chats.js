@@ -1,5 +1,51 @@
 import { api, endpoints, withErrorHandling } from './client.js';
 
+// Reads a reply streamed as server-sent events: "route" names the provider
+// and model answering, "text" and "reasoning" events carry pieces of the
+// answer and of the model's thinking, "error" ends the reply.
+async function readReplyEvents(reader, options, accumulated) {
+  const decoder = new TextDecoder();
+  let buffer = '';
+
+  while (true) {
+    const { done, value } = await reader.read();
+    if (done) break;
+
+    buffer += decoder.decode(value, { stream: true });
+    const events = buffer.split('\n\n');
+    buffer = events.pop() || '';
+
+    for (const raw of events) {
+      let event = 'message';
+      let data = '';
+      for (const line of raw.split('\n')) {
+        if (line.startsWith('event: ')) event = line.slice(7);
+        else if (line.startsWith('data: ')) data += line.slice(6);
+      }
+      const payload = data ? JSON.parse(data) : {};
+
+      if (event === 'error') {
+        throw new Error(payload.message || 'Streaming error occurred');
+      }
+      if (event === 'route') {
+        if (options.onRoute) {
+          options.onRoute(payload);
+        }
+      } else if (event === 'reasoning') {
+        accumulated.reasoning += payload.text;
+        if (options.onReasoning) {
+          options.onReasoning(payload.text, accumulated.reasoning);
+        }
+      } else if (event === 'text') {
+        accumulated.content += payload.text;
+        if (options.onChunk) {
+          options.onChunk(payload.text, accumulated.content);
+        }
+      }
+    }
+  }
+}
+
 // Chat API service
 export const chatAPI = {
   // Get all chats for the current user
@@ -72,7 +118,7 @@ export const chatAPI = {
   async streamMessage(chatId, message, options = {}) {
     let reader = null;
     let abortController = new AbortController();
-    let accumulatedContent = '';
+    const accumulated = { content: '', reasoning: '' };
     
     // Store abort controller for cancellation
     if (options.onStart) {
@@ -90,7 +136,7 @@ export const chatAPI = {
       
       const response = await fetch(`/api/chats/${chatId}/stream`, {
         method: 'POST',
-        headers: api.getHeaders(),
+        headers: { ...(await api.getFreshHeaders()), Accept: 'text/event-stream' },
         body: JSON.stringify(requestBody),
         signal: abortController.signal
       });
@@ -114,38 +160,15 @@ export const chatAPI = {
         throw new Error('Response body is not readable');
       }
 
-      const decoder = new TextDecoder();
-
       try {
-        while (true) {
-          const { done, value } = await reader.read();
-          
-          if (done) {
-            break;
-          }
-
-          const chunk = decoder.decode(value, { stream: true });
-          
-          // Check for error messages in the chunk
-          if (chunk.startsWith('ERROR:')) {
-            const errorMsg = chunk.substring(6).trim();
-            throw new Error(errorMsg || 'Streaming error occurred');
-          }
-          
-          accumulatedContent += chunk;
-          
-          // Call the onChunk callback with only the new chunk
-          if (options.onChunk && chunk) {
-            options.onChunk(chunk, accumulatedContent);
-          }
-        }
+        await readReplyEvents(reader, options, accumulated);
 
         // Call completion callback
         if (options.onComplete) {
-          options.onComplete(accumulatedContent);
+          options.onComplete(accumulated.content);
         }
 
-        return accumulatedContent;
+        return accumulated.content;
       } finally {
         if (reader) {
           reader.releaseLock();
@@ -156,7 +179,7 @@ export const chatAPI = {
       
       if (error.name === 'AbortError') {
         // Don't call onComplete for aborted streams, let the UI handle the partial content
-        return accumulatedContent || '';
+        return accumulated.content;
       }
       
       if (options.onError) {
@@ -170,7 +193,7 @@ export const chatAPI = {
   async regenerateResponse(chatId, options = {}) {
     let reader = null;
     let abortController = new AbortController();
-    let accumulatedContent = '';
+    const accumulated = { content: '', reasoning: '' };
     
     // Store abort controller for cancellation
     if (options.onStart) {
@@ -180,7 +203,7 @@ export const chatAPI = {
     try {
       const response = await fetch(`/api/chats/${chatId}/regenerate`, {
         method: 'POST',
-        headers: api.getHeaders(),
+        headers: { ...(await api.getFreshHeaders()), Accept: 'text/event-stream' },
         signal: abortController.signal
       });
 
@@ -203,38 +226,15 @@ export const chatAPI = {
         throw new Error('Response body is not readable');
       }
 
-      const decoder = new TextDecoder();
-
       try {
-        while (true) {
-          const { done, value } = await reader.read();
-          
-          if (done) {
-            break;
-          }
-
-          const chunk = decoder.decode(value, { stream: true });
-          
-          // Check for error messages in the chunk
-          if (chunk.startsWith('ERROR:')) {
-            const errorMsg = chunk.substring(6).trim();
-            throw new Error(errorMsg || 'Streaming error occurred');
-          }
-          
-          accumulatedContent += chunk;
-          
-          // Call the onChunk callback with only the new chunk
-          if (options.onChunk && chunk) {
-            options.onChunk(chunk, accumulatedContent);
-          }
-        }
+        await readReplyEvents(reader, options, accumulated);
 
         // Call completion callback
         if (options.onComplete) {
-          options.onComplete(accumulatedContent);
+          options.onComplete(accumulated.content);
         }
 
-        return accumulatedContent;
+        return accumulated.content;
       } finally {
         if (reader) {
           reader.releaseLock();
@@ -246,7 +246,7 @@ export const chatAPI = {
       if (error.name === 'AbortError') {
         console.log('Regenerate stream was cancelled by user');
         // Don't call onComplete for aborted streams, let the UI handle the partial content
-        return accumulatedContent || '';
+        return accumulated.content;
       }
       
       if (options.onError) {
@@ -331,6 +331,24 @@ export const chatAPI = {
     );
   },
 
+  // Set sampling settings (temperature, top_p, max_tokens, stop, seed,
+  // presence_penalty, frequency_penalty); {} goes back to the user defaults
+  async setGenerationParams(chatId, generationParams = {}) {
+    return withErrorHandling(
+      () => api.patch(endpoints.chats.update(chatId), { generation_params: generationParams }),
+      'Failed to update generation settings.'
+    );
+  },
+
+  // Set the routes ([{ provider, model }]) tried in order when the chat's own
+  // provider keeps failing; [] removes them
+  async setFallbackChain(chatId, fallbackChain = []) {
+    return withErrorHandling(
+      () => api.patch(endpoints.chats.update(chatId), { fallback_chain: fallbackChain }),
+      'Failed to update fallback routes.'
+    );
+  },
+
   // Share chat (get shareable link)
   async shareChat(chatId, options = {}) {
     return withErrorHandling(
@@ -363,4 +381,36 @@ export const chatAPI = {
       'Failed to insert messages.'
     );
   },
+
+  // List the owner and collaborators of a chat
+  async getMembers(chatId) {
+    return withErrorHandling(
+      () => api.get(endpoints.chats.members(chatId)),
+      'Failed to load chat members.'
+    );
+  },
+
+  // Invite a user to a chat as viewer or editor
+  async addMember(chatId, email, role = 'viewer') {
+    return withErrorHandling(
+      () => api.post(endpoints.chats.members(chatId), { email, role }),
+      'Failed to add chat member.'
+    );
+  },
+
+  // Change a collaborator's role
+  async updateMember(chatId, userId, role) {
+    return withErrorHandling(
+      () => api.patch(endpoints.chats.member(chatId, userId), { role }),
+      'Failed to update chat member.'
+    );
+  },
+
+  // Remove a collaborator (or leave a chat shared with you)
+  async removeMember(chatId, userId) {
+    return withErrorHandling(
+      () => api.delete(endpoints.chats.member(chatId, userId)),
+      'Failed to remove chat member.'
+    );
+  },
 };
\ No newline at end of file
@@ -24,6 +24,59 @@ class APIClient {
     return localStorage.getItem("neko-auth-token");
   }
 
+  // Get refresh token from localStorage
+  getRefreshToken() {
+    if (!browser) return null;
+    return localStorage.getItem("neko-refresh-token");
+  }
+
+  // Exchange the refresh token for a new access token.
+  // Concurrent callers share a single in-flight refresh.
+  async refreshAccessToken() {
+    const refreshToken = this.getRefreshToken();
+    if (!refreshToken) return false;
+
+    if (!this.refreshPromise) {
+      this.refreshPromise = fetch(`${this.baseURL}/api/auth/refresh`, {
+        method: "POST",
+        headers: this.defaultHeaders,
+        body: JSON.stringify({ refresh_token: refreshToken }),
+      })
+        .then(async (response) => {
+          if (!response.ok) return false;
+          const data = await response.json();
+          localStorage.setItem("neko-auth-token", data.token);
+          localStorage.setItem("neko-refresh-token", data.refresh_token);
+          return true;
+        })
+        .catch(() => false)
+        .finally(() => {
+          this.refreshPromise = null;
+        });
+    }
+
+    return this.refreshPromise;
+  }
+
+  // Access tokens are short-lived; refresh ahead of time for requests
+  // that bypass `request()` (e.g. streaming fetches).
+  async getFreshHeaders(customHeaders = {}) {
+    const token = this.getAuthToken();
+    if (token) {
+      try {
+        const payload = JSON.parse(
+          atob(token.split(".")[1].replace(/-/g, "+").replace(/_/g, "/")),
+        );
+        if (payload.exp * 1000 - Date.now() < 30000) {
+          await this.refreshAccessToken();
+        }
+      } catch (error) {
+        // Not a JWT we can read; let the server decide.
+      }
+    }
+    return this.getHeaders(customHeaders);
+  }
+
   // Create headers with authentication
   getHeaders(customHeaders = {}) {
     const headers = { ...this.defaultHeaders, ...customHeaders };
@@ -57,6 +110,7 @@ class APIClient {
           // Unauthorized - redirect to login (unless disabled)
           if (browser && !this.currentRequest?.noRedirectOn401) {
             localStorage.removeItem("neko-auth-token");
+            localStorage.removeItem("neko-refresh-token");
             window.location.href = "/auth";
           }
           throw new Error("Authentication required");
@@ -88,11 +142,14 @@ class APIClient {
     const url = `${this.baseURL}${endpoint}`;
     const config = {
       headers: this.getHeaders(options.headers),
+      // The API runs on another origin in development; cookies (the OAuth
+      // state) only travel with credentialed requests.
+      credentials: "include",
       ...options,
     };
 
     // Extract custom options
-    const { noRedirectOn401, ...fetchOptions } = config;
+    const { noRedirectOn401, retried, ...fetchOptions } = config;
 
     // Add timeout
     const controller = new AbortController();
@@ -105,6 +162,17 @@ class APIClient {
     try {
       const response = await fetch(url, fetchOptions);
       clearTimeout(timeoutId);
+
+      // The access token may simply have expired; refresh once and retry.
+      if (
+        response.status === 401 &&
+        !retried &&
+        endpoint !== "/api/auth/refresh" &&
+        (await this.refreshAccessToken())
+      ) {
+        return this.request(endpoint, { ...options, retried: true });
+      }
+
       const result = await this.handleResponse(response);
       return result;
     } catch (error) {
@@ -158,9 +226,10 @@ class APIClient {
     });
   }
 
-  async delete(endpoint) {
+  async delete(endpoint, data) {
     return this.request(endpoint, {
       method: "DELETE",
+      ...(data !== undefined && { body: JSON.stringify(data) }),
     });
   }
 
@@ -232,6 +301,8 @@ export const endpoints = {
     register: "/api/auth/register",
     refresh: "/api/auth/refresh",
     profile: "/api/auth/profile",
+    account: "/api/auth/account",
+    accountExport: "/api/auth/account/export",
   },
 
   // Chats
@@ -242,6 +313,8 @@ export const endpoints = {
     update: (id) => `/api/chats/${id}`,
     delete: (id) => `/api/chats/${id}`,
     messages: (id) => `/api/chats/${id}/messages`,
+    members: (id) => `/api/chats/${id}/members`,
+    member: (id, userId) => `/api/chats/${id}/members/${userId}`,
   },
 
   // AI/LLM
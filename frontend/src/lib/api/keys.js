@@ -27,41 +27,70 @@ export const keysAPI = {
     }
   },
 
-  // Add a new API key
-  async addKey(provider, apiKey) {
+  // Providers the user can chat with, including through workspace or
+  // server-wide keys. Resolves to [{ provider, sources }].
+  async getProviders() {
+    return withErrorHandling(
+      () => api.get("/api/keys/providers"),
+      "Failed to load available providers.",
+    );
+  },
+
+  // Add a new API key. The backend checks it with the provider first unless
+  // validate is false. Adding under an existing label replaces that key.
+  async addKey(provider, apiKey, validate = true, label) {
     return withErrorHandling(
       () =>
         api.post("/api/keys", {
           provider,
+          label,
           api_key: apiKey,
+          validate,
         }),
       "Failed to add API key.",
     );
   },
 
-  // Get a specific API key (decrypted)
-  async getKey(provider) {
+  // Reveal a stored API key. Needs the account password (and a 2FA code
+  // when enabled) every time; reveals are recorded in the audit log.
+  async revealKey(provider, password, code, keyId) {
     return withErrorHandling(
-      () => api.get(`/api/keys/${provider}`),
-      "Failed to get API key.",
+      () =>
+        api.post(`/api/keys/${provider}/reveal`, {
+          password,
+          code: code || undefined,
+          key_id: keyId,
+        }),
+      "Failed to reveal API key.",
     );
   },
 
-  // Delete an API key
-  async deleteKey(provider) {
+  // Rename or reorder a key ({ label, priority }); lower priority is tried first
+  async updateKey(provider, keyId, updates) {
     return withErrorHandling(
-      () => api.delete(`/api/keys/${provider}`),
+      () => api.patch(`/api/keys/${provider}/${keyId}`, updates),
+      "Failed to update API key.",
+    );
+  },
+
+  // Delete one key, or every key for the provider when keyId is omitted
+  async deleteKey(provider, keyId) {
+    const path = keyId ? `/api/keys/${provider}/${keyId}` : `/api/keys/${provider}`;
+    return withErrorHandling(
+      () => api.delete(path),
       "Failed to delete API key.",
     );
   },
 
-  // Test an API key (if we want to add validation)
-  async testKey(provider, apiKey) {
+  // Test an API key against the provider. Without apiKey a stored key (keyId,
+  // or the provider's default) is tested and its status updated. Resolves to { provider, valid, error }.
+  async testKey(provider, apiKey, keyId) {
     return withErrorHandling(
       () =>
         api.post(`/api/keys/test`, {
           provider,
-          api_key: apiKey,
+          api_key: apiKey || undefined,
+          key_id: keyId,
         }),
       "Failed to test API key.",
     );
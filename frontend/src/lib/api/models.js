@@ -53,6 +53,21 @@ export const modelsAPI = {
     });
   },
 
+  /**
+   * Pin an enabled model to one of the provider's API keys
+   * @param {string} provider - The provider name
+   * @param {string} modelId - The model ID
+   * @param {string|null} keyId - The key to use, or null for the provider's default
+   * @returns {Promise<Object>} The updated model preference
+   */
+  async setModelKey(provider, modelId, keyId) {
+    return api.put('/api/settings/models/key', {
+      provider,
+      model_id: modelId,
+      api_key_id: keyId
+    });
+  },
+
   /**
    * Refresh models for all providers that have API keys
    * @param {Array} providers - List of providers to refresh
@@ -18,6 +18,23 @@ export const settingsAPI = {
     );
   },
 
+  // Set default sampling settings for new requests; {} clears them
+  async setDefaultGenerationParams(generationParams = {}) {
+    return withErrorHandling(
+      () => api.put(endpoints.settings.update, { generation_params: generationParams }),
+      'Failed to update generation settings.'
+    );
+  },
+
+  // Set Gemini safety filters as [{ category, threshold }]; [] restores
+  // Gemini's defaults
+  async setGeminiSafetySettings(safetySettings = []) {
+    return withErrorHandling(
+      () => api.put(endpoints.settings.update, { gemini_safety_settings: safetySettings }),
+      'Failed to update Gemini safety settings.'
+    );
+  },
+
   // Get API keys
   async getApiKeys() {
     return withErrorHandling(
@@ -0,0 +1,98 @@
+import { api, withErrorHandling } from "./client.js";
+
+// Workspaces API service. Members can use a workspace's provider keys but
+// only ever see their labels and hints; admins and owners manage them.
+export const workspacesAPI = {
+  async list() {
+    return withErrorHandling(
+      () => api.get("/api/workspaces"),
+      "Failed to load workspaces.",
+    );
+  },
+
+  async create(name) {
+    return withErrorHandling(
+      () => api.post("/api/workspaces", { name }),
+      "Failed to create workspace.",
+    );
+  },
+
+  async rename(workspaceId, name) {
+    return withErrorHandling(
+      () => api.patch(`/api/workspaces/${workspaceId}`, { name }),
+      "Failed to rename workspace.",
+    );
+  },
+
+  async remove(workspaceId) {
+    return withErrorHandling(
+      () => api.delete(`/api/workspaces/${workspaceId}`),
+      "Failed to delete workspace.",
+    );
+  },
+
+  async getMembers(workspaceId) {
+    return withErrorHandling(
+      () => api.get(`/api/workspaces/${workspaceId}/members`),
+      "Failed to load workspace members.",
+    );
+  },
+
+  // role is one of "owner", "admin" or "member"
+  async addMember(workspaceId, email, role = "member") {
+    return withErrorHandling(
+      () => api.post(`/api/workspaces/${workspaceId}/members`, { email, role }),
+      "Failed to add member.",
+    );
+  },
+
+  async updateMember(workspaceId, userId, role) {
+    return withErrorHandling(
+      () => api.patch(`/api/workspaces/${workspaceId}/members/${userId}`, { role }),
+      "Failed to update member.",
+    );
+  },
+
+  // Removing your own user id leaves the workspace
+  async removeMember(workspaceId, userId) {
+    return withErrorHandling(
+      () => api.delete(`/api/workspaces/${workspaceId}/members/${userId}`),
+      "Failed to remove member.",
+    );
+  },
+
+  async getKeys(workspaceId) {
+    return withErrorHandling(
+      () => api.get(`/api/workspaces/${workspaceId}/keys`),
+      "Failed to load workspace keys.",
+    );
+  },
+
+  async addKey(workspaceId, provider, apiKey, label, validate = true) {
+    return withErrorHandling(
+      () =>
+        api.post(`/api/workspaces/${workspaceId}/keys`, {
+          provider,
+          label,
+          api_key: apiKey,
+          validate,
+        }),
+      "Failed to add workspace key.",
+    );
+  },
+
+  async deleteKey(workspaceId, keyId) {
+    return withErrorHandling(
+      () => api.delete(`/api/workspaces/${workspaceId}/keys/${keyId}`),
+      "Failed to delete workspace key.",
+    );
+  },
+
+  // Per-member, per-model totals over the last `days` days
+  async getUsage(workspaceId, days = 30) {
+    return withErrorHandling(
+      () => api.get(`/api/workspaces/${workspaceId}/usage?days=${days}`),
+      "Failed to load workspace usage.",
+    );
+  },
+};
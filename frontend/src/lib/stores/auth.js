@@ -1,6 +1,7 @@
 import { writable } from "svelte/store";
 import { browser } from "$app/environment";
 import { authAPI } from "$lib/api/auth.js";
+import { api } from "$lib/api/client.js";
 
 // Authentication state
 export const isAuthenticated = writable(false);
@@ -31,6 +32,25 @@ const defaultUser = {
   lastLoginAt: null,
 };
 
+function startSession(response) {
+  if (!response.token || !response.user) {
+    throw new Error("Invalid response from server");
+  }
+
+  // Store token
+  if (browser) {
+    localStorage.setItem("neko-auth-token", response.token);
+    localStorage.setItem("neko-refresh-token", response.refresh_token);
+  }
+
+  // Update stores
+  user.set(response.user);
+  auth.set(response.user);
+  isAuthenticated.set(true);
+
+  return { success: true, user: response.user };
+}
+
 // Authentication actions
 export async function login(email, password) {
   isLoading.set(true);
@@ -38,21 +58,11 @@ export async function login(email, password) {
 
   try {
     const response = await authAPI.login(email, password);
-    if (response.token && response.user) {
-      // Store token
-      if (browser) {
-        localStorage.setItem("neko-auth-token", response.token);
-      }
-
-      // Update stores
-      user.set(response.user);
-      auth.set(response.user);
-      isAuthenticated.set(true);
-
-      return { success: true, user: response.user };
-    } else {
-      throw new Error("Invalid response from server");
+    if (response.mfa_required) {
+      // Second step: the caller asks for a code and calls verifyMfa.
+      return { success: false, mfaRequired: true, mfaToken: response.mfa_token };
     }
+    return startSession(response);
   } catch (error) {
     console.error("Login error:", error);
     const errorMessage = error.message || "Login failed";
@@ -63,7 +73,23 @@ export async function login(email, password) {
   }
 }
 
-export async function signup(email, password, name = "") {
+export async function verifyMfa(mfaToken, code) {
+  isLoading.set(true);
+  authError.set(null);
+
+  try {
+    const response = await authAPI.verifyMfa(mfaToken, code);
+    return startSession(response);
+  } catch (error) {
+    const errorMessage = error.message || "Invalid verification code";
+    authError.set(errorMessage);
+    return { success: false, error: errorMessage };
+  } finally {
+    isLoading.set(false);
+  }
+}
+
+export async function signup(email, password, name = "", inviteCode = "") {
   isLoading.set(true);
   authError.set(null);
 
@@ -72,6 +98,7 @@ export async function signup(email, password, name = "") {
       email,
       password,
       name || email.split("@")[0],
+      inviteCode || undefined,
     );
 
     if (response) {
@@ -93,6 +120,18 @@ export async function logout() {
   isLoading.set(true);
 
   try {
+    // Revoke the server-side session; local state is cleared regardless.
+    if (browser && localStorage.getItem("neko-auth-token")) {
+      try {
+        await api.request("/api/auth/logout", {
+          method: "POST",
+          noRedirectOn401: true,
+        });
+      } catch (error) {
+        console.warn("Logout request failed:", error);
+      }
+    }
+
     // Clear local state
     user.set(null);
     auth.set(null);
@@ -101,6 +140,7 @@ export async function logout() {
 
     if (browser) {
       localStorage.removeItem("neko-auth-token");
+      localStorage.removeItem("neko-refresh-token");
     }
 
     return { success: true };
@@ -183,12 +223,11 @@ export async function changePassword(currentPassword, newPassword) {
   }
 }
 
-export async function deleteAccount() {
+export async function deleteAccount(password, code) {
   isLoading.set(true);
 
   try {
-    // Simulate API call
-    await new Promise((resolve) => setTimeout(resolve, 1500));
+    await authAPI.deleteAccount(password, code);
 
     // Clear all data
     await logout();
@@ -202,6 +241,26 @@ export async function deleteAccount() {
   }
 }
 
+// Downloads everything stored about the account as a JSON file
+export async function downloadAccountExport() {
+  try {
+    const data = await authAPI.exportAccount();
+    const blob = new Blob([JSON.stringify(data, null, 2)], {
+      type: "application/json",
+    });
+    const url = URL.createObjectURL(blob);
+    const link = document.createElement("a");
+    link.href = url;
+    link.download = `neko-chat-export-${new Date().toISOString().slice(0, 10)}.json`;
+    link.click();
+    URL.revokeObjectURL(url);
+    return { success: true };
+  } catch (error) {
+    authError.set(error.message);
+    return { success: false, error: error.message };
+  }
+}
+
 // Password reset
 export async function requestPasswordReset(email) {
   isLoading.set(true);
@@ -1,5 +1,6 @@
 import { writable, derived, get } from "svelte/store";
 import { browser } from "$app/environment";
+import { api } from "$lib/api/client.js";
 import { chatAPI } from "$lib/api/chats.js";
 import { showError, showSuccess } from "./app.js";
 import { rightSidebarCollapsed } from "./ui.js";
@@ -381,6 +382,15 @@ export async function sendMessage(content, options = {}) {
           streaming: true,
         });
       },
+      onReasoning: (chunk, accumulatedReasoning) => {
+        updateMessageInActiveChat(assistantMessageId, {
+          reasoning: accumulatedReasoning,
+          streaming: true,
+        });
+      },
+      onRoute: ({ provider, model }) => {
+        updateMessageInActiveChat(assistantMessageId, { provider, model });
+      },
       onComplete: async () => {
         // Mark streaming as complete
         updateMessageInActiveChat(assistantMessageId, {
@@ -530,10 +540,7 @@ export async function sendParallelMessage(content, models, options = {}) {
     // Create branch chats for each model using the API client
     const response = await fetch(`/api/chats/${currentChatId}/parallel`, {
       method: "POST",
-      headers: {
-        "Content-Type": "application/json",
-        Authorization: `Bearer ${localStorage.getItem("neko-auth-token")}`,
-      },
+      headers: await api.getFreshHeaders(),
       body: JSON.stringify({
         content,
         models,